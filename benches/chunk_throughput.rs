@@ -0,0 +1,140 @@
+// Measures throughput and allocations of encrypted file data over loopback, without disk.
+// Chat connection sends chunks with an ack for each of them, data connections only chunks.
+// Run with: cargo bench --bench chunk_throughput
+
+use rust_project::config::settings;
+use rust_project::modules::{encryption::*, parallel_transfer::DATA_CHUNK_SIZE, protocol::*};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use tokio::net::{TcpListener, TcpStream};
+
+// Counts allocations of whole process, both sides of connection run here.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+// Bytes sent in every run.
+const DATA_SIZE: usize = 1 << 30;
+
+async fn get_2_secure_streams() -> (SecureStream, SecureStream) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let handle = tokio::task::spawn(async move {
+        let stream = TcpStream::connect(addr).await.unwrap();
+        SecureStream::handshake(stream, None).await.unwrap().0
+    });
+
+    let (stream, _) = listener.accept().await.unwrap();
+    let (secure, _) = SecureStream::handshake(stream, None).await.unwrap();
+
+    (secure, handle.await.unwrap())
+}
+
+// Chat connection: chunks from pool interleaved with acks going back, as in file_uploader.
+async fn send_chat_chunks(stream: SecureStream, chunk_size: usize) {
+    let (mut read_half, mut write_half) = stream.into_split();
+
+    let acks = tokio::task::spawn(async move {
+        for _ in (0..DATA_SIZE).step_by(chunk_size) {
+            let _: Message = read_half.read().await.unwrap();
+        }
+    });
+
+    for position in (0..DATA_SIZE).step_by(chunk_size) {
+        let mut chunk = CHUNK_POOL.take(chunk_size);
+        chunk.fill(1, position as u64, chunk_size).fill(7);
+        write_half.send_chunk(&mut chunk).await.unwrap();
+        CHUNK_POOL.give(chunk);
+    }
+
+    acks.await.unwrap();
+}
+
+async fn read_chat_chunks(stream: SecureStream, chunk_size: usize) {
+    let (mut read_half, mut write_half) = stream.into_split();
+    let mut chunk = CHUNK_POOL.take(chunk_size);
+    let ack = Message::Internal(InternalMessage::FileAck(1, 1));
+
+    for _ in (0..DATA_SIZE).step_by(chunk_size) {
+        match read_half.read_msg_or_chunk::<Message>(&mut chunk).await {
+            Ok(Frame::Chunk) => write_half.send(&ack).await.unwrap(),
+            _ => panic!("Expected chunk!"),
+        }
+    }
+}
+
+async fn send_chunks(mut stream: SecureStream, chunk_size: usize) {
+    let mut chunk = ChunkBuffer::new(chunk_size);
+
+    for position in (0..DATA_SIZE).step_by(chunk_size) {
+        chunk.fill(1, position as u64, chunk_size).fill(7);
+        stream.send_chunk(&mut chunk).await.unwrap();
+    }
+}
+
+async fn read_chunks(mut stream: SecureStream, chunk_size: usize) {
+    let mut chunk = ChunkBuffer::new(chunk_size);
+
+    for _ in (0..DATA_SIZE).step_by(chunk_size) {
+        stream.read_chunk(&mut chunk).await.unwrap();
+    }
+}
+
+fn report(name: &str, chunk_size: usize, elapsed: Duration, allocations: usize) {
+    println!(
+        "{:<24} {:>7} B chunks: {:>8.1} MiB/s, {:>5.2} allocations per chunk",
+        name,
+        chunk_size,
+        DATA_SIZE as f64 / (1 << 20) as f64 / elapsed.as_secs_f64(),
+        allocations as f64 / (DATA_SIZE / chunk_size) as f64,
+    );
+}
+
+#[tokio::main]
+async fn main() {
+    let msg_chunk_size = settings().transfer.chunk_size(&settings().frame_limits);
+
+    println!("Sending {} MiB over loopback", DATA_SIZE >> 20);
+
+    for chunk_size in [msg_chunk_size, DATA_CHUNK_SIZE] {
+        let (sender, receiver) = get_2_secure_streams().await;
+        let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+        let start = Instant::now();
+
+        let reader = tokio::task::spawn(read_chat_chunks(receiver, chunk_size));
+        send_chat_chunks(sender, chunk_size).await;
+        reader.await.unwrap();
+
+        let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+        report("Chat connection", chunk_size, start.elapsed(), allocations);
+    }
+
+    for chunk_size in [msg_chunk_size, DATA_CHUNK_SIZE] {
+        let (sender, receiver) = get_2_secure_streams().await;
+        let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+        let start = Instant::now();
+
+        let reader = tokio::task::spawn(read_chunks(receiver, chunk_size));
+        send_chunks(sender, chunk_size).await;
+        reader.await.unwrap();
+
+        let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+        report("Data connection", chunk_size, start.elapsed(), allocations);
+    }
+}
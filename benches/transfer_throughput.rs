@@ -0,0 +1,97 @@
+// Measures download speed between two peers connected over loopback.
+// Compares download over chat connection (1 connection) with parallel range downloads.
+// Run with: cargo bench --bench transfer_throughput
+
+use rust_project::config::DOWNLOAD_PATH;
+use rust_project::modules::{message_bubble::LoadingBar, networking::*, peer_state::PeerState};
+use std::time::{Duration, Instant};
+use tempfile::tempdir;
+use tokio::net::{TcpListener, TcpStream};
+
+// Big enough to be above default parallel_min_size.
+const FILE_SIZE: usize = 256 << 20;
+
+const CONNECTIONS: [usize; 4] = [1, 2, 4, 8];
+
+async fn get_2_peers() -> (PeerState<'static>, PeerState<'static>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let handle = tokio::task::spawn(async move {
+        let stream = TcpStream::connect(addr).await.unwrap();
+        establish_connection(stream, addr, true).await.unwrap()
+    });
+
+    let (stream, peer_address) = listener.accept().await.unwrap();
+    let cd1 = establish_connection(stream, peer_address, false)
+        .await
+        .unwrap();
+    let cd2 = handle.await.unwrap();
+
+    (PeerState::from(cd1), PeerState::from(cd2))
+}
+
+// Downloads newest file message of peer and returns how long it took.
+async fn download_last(peer: &mut PeerState<'static>) -> Result<Duration, String> {
+    // Sender hashes file before sending its message.
+    let msg_cnt = peer.messages.list.len();
+    while peer.messages.list.len() == msg_cnt {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        peer.update();
+    }
+
+    let last = peer.messages.list.len() - 1;
+    peer.messages.select(last as u16);
+
+    let start = Instant::now();
+    peer.handle_action_on_msg();
+
+    let loading_bar = peer.messages.list[last]
+        .loading_bar
+        .clone()
+        .ok_or("Newest message is not a file!")?;
+
+    loop {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        match &loading_bar.lock().unwrap().loadingbar {
+            LoadingBar::Verified(_) => return Ok(start.elapsed()),
+            LoadingBar::Error(e) => return Err(e.clone()),
+            _ => {}
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let tmp_dir = tempdir().unwrap();
+    let file_name = "rust-project-bench-file-Thr0uGhPuT7x";
+    let source = tmp_dir.path().join(file_name);
+    let download_path = DOWNLOAD_PATH.join(file_name);
+
+    let content: Vec<u8> = (0..FILE_SIZE).map(|_| rand::random()).collect();
+    std::fs::write(&source, &content).unwrap();
+    drop(content);
+
+    let (mut uploader, mut downloader) = get_2_peers().await;
+
+    println!("Downloading {} MiB over loopback", FILE_SIZE >> 20);
+
+    for connections in CONNECTIONS {
+        downloader.set_download_connections(connections);
+        uploader.upload_file(source.clone());
+
+        let result = download_last(&mut downloader).await;
+        let _ = std::fs::remove_file(&download_path);
+
+        match result {
+            Ok(elapsed) => println!(
+                "{} connection(s): {:>8.1} MiB/s ({:.2?})",
+                connections,
+                FILE_SIZE as f64 / (1 << 20) as f64 / elapsed.as_secs_f64(),
+                elapsed
+            ),
+            Err(e) => println!("{} connection(s): failed: {}", connections, e),
+        }
+    }
+}
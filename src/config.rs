@@ -1,30 +1,230 @@
-// TODO: make config be read at a start of program from external file to avoid recompilation after changing any setting.
+// TODO: move remaining constants to Settings to avoid recompilation after changing them.
 
 // TODO array of multicasts address in case of busy port.
 pub static MULTICAST_IP: &str = "239.42.17.19";
 pub static MULTICAST_PORT: u16 = 7899;
 
+// Discovery packets older (or newer) than this number of seconds are dropped.
+pub static DISCOVERY_MAX_AGE: u64 = 60;
+
 pub static UNIQUE_BYTES: &[u8] = b"CHATapp>4RxPOv@1Gy8SZ8syH7$MlVAA2>0y]D`%KTIN\"Y[Lk9Z}\"k{p)";
 
+use cli_log::*;
 use copypasta::ClipboardContext;
-use directories::UserDirs;
-use once_cell::sync::Lazy;
-use rand::{distributions::Alphanumeric, Rng};
+use directories::{ProjectDirs, UserDirs};
+use once_cell::sync::{Lazy, OnceCell};
+use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 use std::sync::Mutex;
 
-// Lazily initialized static variable for USER_ID
-pub static USER_ID: Lazy<u64> = Lazy::new(|| {
-    let mut rng = rand::thread_rng();
-    rng.gen() // Generate a random u64
+use crate::modules::encryption::RoomKey;
+use crate::modules::identity::Identity;
+
+static SETTINGS_FILE: &str = "settings.toml";
+
+/// Settings read at startup from settings.toml in CONFIG_PATH, can be overriden by command line.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct Settings {
+    pub passphrase: Option<String>, // Room passphrase, only peers knowing it are visible.
+    pub ask_before_connecting: bool, // New peers wait in peer list until user accepts them.
+    pub allowlist_only: bool,       // Only peers from allowlist are connected.
+    pub frame_limits: FrameLimits,
+    pub connection_limits: ConnectionLimits,
+    pub transfer: TransferSettings,
+    pub bandwidth: BandwidthSettings,
+    pub auto_download: Vec<AutoDownloadRule>, // First matching rule is used.
+}
+
+/// Maximal sizes (in bytes) of frames accepted from peers, connection is dropped on bigger one.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct FrameLimits {
+    pub handshake: u64,
+    pub connection_info: u64,
+    pub message: u64,
+}
+
+impl Default for FrameLimits {
+    fn default() -> Self {
+        FrameLimits {
+            handshake: 1024,
+            connection_info: 4096,
+            message: 1 << 20,
+        }
+    }
+}
+
+/// Limits protecting against floods of discovery packets and incoming connections.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ConnectionLimits {
+    pub attempts_per_minute: u32, // Per source address.
+    pub max_handshakes: usize,    // Handshakes running at the same time.
+    pub max_peers: usize,
+    pub failure_backoff_secs: u64, // Doubled with every failed handshake in a row.
+    pub max_backoff_secs: u64,
+}
+
+impl Default for ConnectionLimits {
+    fn default() -> Self {
+        ConnectionLimits {
+            attempts_per_minute: 20,
+            max_handshakes: 16,
+            max_peers: 64,
+            failure_backoff_secs: 2,
+            max_backoff_secs: 300,
+        }
+    }
+}
+
+/// File transfer tuning. Memory used by one transfer is about window * chunk_size on each side.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct TransferSettings {
+    pub chunk_size: usize, // Bytes of file in one message, capped by message frame limit.
+    pub window: usize,     // Chunks queued between disk and socket before reading stops.
+    pub offer_expiry_hours: u64, // Offered files can't be downloaded after that, 0 means never.
+    pub connections: usize, // Data connections used by one download of big file.
+    pub parallel_min_size: u64, // Smaller files are downloaded over chat connection.
+}
+
+impl Default for TransferSettings {
+    fn default() -> Self {
+        TransferSettings {
+            chunk_size: 16 * 1024,
+            window: 16,
+            offer_expiry_hours: 7 * 24,
+            connections: 4,
+            parallel_min_size: 64 << 20,
+        }
+    }
+}
+
+/// Most data connections of one download, uploader rejects requests for more ranges.
+pub const MAX_CONNECTIONS: usize = 64;
+
+impl TransferSettings {
+    // Chunk has to fit in a frame together with message header.
+    pub fn chunk_size(&self, frame_limits: &FrameLimits) -> usize {
+        self.chunk_size
+            .clamp(1, (frame_limits.message as usize).saturating_sub(64).max(1))
+    }
+
+    pub fn window(&self) -> usize {
+        self.window.max(1)
+    }
+
+    // Number of data connections for file of given size, 1 means chat connection is used.
+    pub fn connections_for(&self, file_size: u64, connections: usize) -> usize {
+        match file_size >= self.parallel_min_size {
+            true => connections.clamp(1, MAX_CONNECTIONS),
+            false => 1,
+        }
+    }
+
+    // Lifetime of file offer in seconds.
+    pub fn offer_expiry(&self) -> Option<u64> {
+        (self.offer_expiry_hours > 0).then(|| self.offer_expiry_hours * 3600)
+    }
+}
+
+/// Starting rate limits in bytes per second, missing value means unlimited. Can be changed in UI.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct BandwidthSettings {
+    pub upload_limit: Option<u64>,   // All uploads together.
+    pub download_limit: Option<u64>, // All downloads together.
+    pub peer_upload_limit: Option<u64>,
+    pub peer_download_limit: Option<u64>,
+}
+
+/// Rule for downloading incoming files without asking, file has to pass every condition.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct AutoDownloadRule {
+    pub trust: AutoDownloadTrust,
+    pub peer: Option<String>, // Id of peer as shown in its details, any peer if missing.
+    pub max_size: Option<u64>, // In bytes, any size if missing.
+    pub extensions: Vec<String>, // Any extension if empty.
+    pub folder: Option<PathBuf>, // Download folder if missing.
+}
+
+/// Least trust of peer whose files are downloaded by rule.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AutoDownloadTrust {
+    #[default]
+    Verified, // Key confirmed by comparing authentication string.
+    Trusted, // Key known from previous connections, or verified.
+}
+
+impl Settings {
+    // Read settings file, missing file means default settings.
+    pub fn load() -> Self {
+        let Some(path) = CONFIG_PATH.as_ref().map(|path| path.join(SETTINGS_FILE)) else {
+            return Self::default();
+        };
+
+        match std::fs::read_to_string(&path) {
+            Ok(content) => toml::from_str(&content).unwrap_or_else(|e| {
+                error!("Invalid settings file {}: {}", path.display(), e);
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    // Apply command line arguments on top of loaded settings.
+    pub fn with_args(mut self, mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--passphrase" => {
+                    self.passphrase = Some(args.next().ok_or("Missing value of --passphrase!")?);
+                }
+                "--ask" => {
+                    self.ask_before_connecting = true;
+                }
+                "--allowlist-only" => {
+                    self.allowlist_only = true;
+                }
+                _ => return Err(format!("Unknown argument: {}", arg)),
+            }
+        }
+
+        Ok(self)
+    }
+}
+
+// Set once in main, tests and other users get settings from file.
+pub static SETTINGS: OnceCell<Settings> = OnceCell::new();
+
+pub fn settings() -> &'static Settings {
+    SETTINGS.get_or_init(Settings::load)
+}
+
+// Derived from passphrase once at startup.
+pub static ROOM_KEY: Lazy<Option<RoomKey>> = Lazy::new(|| {
+    settings()
+        .passphrase
+        .as_deref()
+        .filter(|passphrase| !passphrase.is_empty())
+        .map(RoomKey::derive)
 });
 
-pub static USER_NAME: Lazy<String> = Lazy::new(|| {
-    let mut rng = rand::thread_rng();
-    let random_str: String = (0..10).map(|_| rng.sample(Alphanumeric) as char).collect();
-    random_str
+// Directory for files that have to survive restart. None if system doesn't provide one.
+pub static CONFIG_PATH: Lazy<Option<PathBuf>> = Lazy::new(|| {
+    ProjectDirs::from("", "", "rust-project").map(|dirs| dirs.config_dir().to_path_buf())
 });
 
+// Identity used to authenticate this user, loaded from CONFIG_PATH.
+pub static IDENTITY: Lazy<Identity> = Lazy::new(Identity::load_or_create);
+
+// Id derived from public key of IDENTITY, same across runs.
+pub static USER_ID: Lazy<u64> = Lazy::new(|| IDENTITY.user_id());
+
+pub static USER_NAME: Lazy<String> = Lazy::new(|| IDENTITY.user_name.clone());
+
 pub static DOWNLOAD_PATH: Lazy<PathBuf> = Lazy::new(|| {
     UserDirs::new()
         .unwrap()
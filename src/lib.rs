@@ -1,10 +1,25 @@
 pub mod modules {
+    pub mod access_list;
+    pub mod auto_download;
+    pub mod broadcast;
+    pub mod encryption;
     pub mod event_handler;
+    pub mod file_transfer;
+    pub mod identity;
+    pub mod limits;
     pub mod message_bubble;
+    pub mod multiplexer;
     pub mod networking;
+    pub mod offers;
+    pub mod parallel_transfer;
     pub mod peer_list;
     pub mod peer_state;
     pub mod protocol;
+    pub mod storage;
+    pub mod throttle;
+    pub mod transfer_list;
+    pub mod transfers;
+    pub mod trust;
     pub mod tui;
     pub mod widgets {
         pub mod list_component;
@@ -8,6 +8,7 @@ use crossterm::terminal::EnterAlternateScreen;
 use crossterm::terminal::LeaveAlternateScreen;
 use ratatui::prelude::CrosstermBackend;
 use ratatui::Terminal;
+use rust_project::config;
 use rust_project::modules::tui;
 use std::error::Error;
 use std::io;
@@ -17,6 +18,9 @@ use std::io;
 async fn main() -> Result<(), Box<dyn Error>> {
     init_cli_log!();
 
+    let settings = config::Settings::load().with_args(std::env::args().skip(1))?;
+    let _ = config::SETTINGS.set(settings);
+
     // Prepare terminal for rendering.
     enable_raw_mode()?;
     execute!(io::stdout(), EnterAlternateScreen, EnableMouseCapture)?;
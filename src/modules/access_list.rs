@@ -0,0 +1,114 @@
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use crate::config::settings;
+use crate::modules::storage::*;
+
+static ACCESS_LIST_FILE: &str = "access_list";
+
+// Blocked and allowed peers, shared by all connections.
+pub static ACCESS_LIST: Lazy<Mutex<AccessList>> = Lazy::new(|| {
+    Mutex::new(AccessList::open(
+        state_path(ACCESS_LIST_FILE),
+        settings().allowlist_only,
+    ))
+});
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct AccessEntries {
+    blocked_ids: HashSet<u64>,
+    blocked_addrs: HashSet<IpAddr>, // Addresses of blocked peers, dropped before handshake.
+    allowed_ids: HashSet<u64>,
+}
+
+/// Persistent blocklist and allowlist of peers.
+/// Peers are blocked by identity and address, allowlist is only checked in strict mode.
+pub struct AccessList {
+    entries: AccessEntries,
+    strict: bool,          // Only peers from allowlist are connected.
+    path: Option<PathBuf>, // None if list lives only in memory.
+}
+
+impl AccessList {
+    pub fn open(path: Option<PathBuf>, strict: bool) -> Self {
+        let entries = path.as_deref().and_then(load_state).unwrap_or_default();
+
+        AccessList {
+            entries,
+            strict,
+            path,
+        }
+    }
+
+    fn store(&self) {
+        if let Some(path) = &self.path {
+            store_state(path, &self.entries);
+        }
+    }
+
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    pub fn is_blocked(&self, peer_id: u64) -> bool {
+        self.entries.blocked_ids.contains(&peer_id)
+    }
+
+    pub fn is_allowlisted(&self, peer_id: u64) -> bool {
+        self.entries.allowed_ids.contains(&peer_id)
+    }
+
+    /// Checked before identity of peer is known.
+    pub fn is_address_allowed(&self, addr: IpAddr) -> bool {
+        !self.entries.blocked_addrs.contains(&addr)
+    }
+
+    /// Whether connection with peer is kept at all.
+    /// Refused peers are connected too, so user can see them and add them to allowlist.
+    pub fn is_connectable(&self, peer_id: u64, addr: IpAddr) -> bool {
+        self.is_address_allowed(addr) && !self.is_blocked(peer_id)
+    }
+
+    /// In strict mode peers outside of allowlist can't talk with us.
+    pub fn is_refused(&self, peer_id: u64) -> bool {
+        self.strict && !self.is_allowlisted(peer_id)
+    }
+
+    /// Whether we should talk with peer at all.
+    pub fn is_allowed(&self, peer_id: u64, addr: IpAddr) -> bool {
+        self.is_connectable(peer_id, addr) && !self.is_refused(peer_id)
+    }
+
+    /// Blocks or unblocks peer. Returns true if peer is blocked now.
+    pub fn toggle_blocked(&mut self, peer_id: u64, addr: IpAddr) -> bool {
+        let blocked = if self.entries.blocked_ids.remove(&peer_id) {
+            self.entries.blocked_addrs.remove(&addr);
+            false
+        } else {
+            self.entries.blocked_ids.insert(peer_id);
+            self.entries.blocked_addrs.insert(addr);
+            self.entries.allowed_ids.remove(&peer_id);
+            true
+        };
+
+        self.store();
+        blocked
+    }
+
+    /// Adds or removes peer from allowlist. Returns true if peer is allowlisted now.
+    pub fn toggle_allowlisted(&mut self, peer_id: u64) -> bool {
+        let allowed = if self.entries.allowed_ids.remove(&peer_id) {
+            false
+        } else {
+            self.entries.allowed_ids.insert(peer_id);
+            true
+        };
+
+        self.store();
+        allowed
+    }
+}
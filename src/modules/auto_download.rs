@@ -0,0 +1,74 @@
+use std::path::{Path, PathBuf};
+
+use crate::config::*;
+use crate::modules::protocol::FileSize;
+use crate::modules::trust::*;
+
+impl AutoDownloadRule {
+    pub fn matches(
+        &self,
+        peer_id: u64,
+        trust: &TrustStatus,
+        file_name: &str,
+        file_size: FileSize,
+    ) -> bool {
+        let trusted = match self.trust {
+            AutoDownloadTrust::Verified => *trust == TrustStatus::Verified,
+            AutoDownloadTrust::Trusted => {
+                matches!(trust, TrustStatus::Known | TrustStatus::Verified)
+            }
+        };
+
+        let peer_matches = self.peer.as_ref().is_none_or(|peer| {
+            peer.trim()
+                .eq_ignore_ascii_case(&format!("{:016x}", peer_id))
+        });
+
+        let size_matches = self.max_size.is_none_or(|max_size| file_size <= max_size);
+
+        let extension = Path::new(file_name)
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase());
+
+        let extension_matches = self.extensions.is_empty()
+            || extension.is_some_and(|extension| {
+                self.extensions.iter().any(|allowed| {
+                    allowed
+                        .trim_start_matches('.')
+                        .eq_ignore_ascii_case(&extension)
+                })
+            });
+
+        trusted && peer_matches && size_matches && extension_matches
+    }
+}
+
+/// Folder for file that should be downloaded without asking, None if no rule matches.
+pub fn auto_download_folder(
+    rules: &[AutoDownloadRule],
+    peer_id: u64,
+    trust: &TrustStatus,
+    file_name: &str,
+    file_size: FileSize,
+) -> Option<PathBuf> {
+    rules
+        .iter()
+        .find(|rule| rule.matches(peer_id, trust, file_name, file_size))
+        .map(|rule| rule.folder.clone().unwrap_or_else(|| DOWNLOAD_PATH.clone()))
+}
+
+// Trust of connected peer right now, user could have verified it after connecting.
+pub fn current_trust(peer_id: u64, trust_at_connect: &TrustStatus) -> TrustStatus {
+    match trust_at_connect {
+        TrustStatus::KeyChanged(_) => trust_at_connect.clone(),
+        _ if KNOWN_PEERS
+            .lock()
+            .unwrap()
+            .get(peer_id)
+            .is_some_and(|known| known.verified) =>
+        {
+            TrustStatus::Verified
+        }
+        _ => trust_at_connect.clone(),
+    }
+}
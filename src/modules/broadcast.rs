@@ -0,0 +1,123 @@
+use crossterm::event::KeyCode;
+use crossterm::event::KeyEvent;
+use crossterm::event::KeyModifiers;
+use ratatui::buffer::Buffer;
+use ratatui::layout::Rect;
+use ratatui::style::{Color, Style};
+use ratatui::widgets::{Block, Borders, Widget};
+use std::path::PathBuf;
+use tui_textarea::TextArea;
+
+use cli_log::*;
+
+use crate::modules::peer_list::PeerList;
+use crate::modules::peer_state::*;
+use crate::modules::protocol::*;
+
+/// Compose box sending one msg or file to all marked peers.
+pub struct Broadcast<'a> {
+    editor: TextArea<'a>,
+    editor_mode: EditorMode,
+}
+
+impl Default for Broadcast<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Broadcast<'_> {
+    pub fn new() -> Self {
+        Broadcast {
+            editor: TextArea::default(),
+            editor_mode: EditorMode::Text,
+        }
+    }
+
+    // Sends content of editor to every recipient, returns false if nothing was sent.
+    fn send(&mut self, recipients: Vec<Recipient>) -> bool {
+        if recipients.is_empty() {
+            return false;
+        }
+
+        match self.editor_mode {
+            EditorMode::Text => {
+                let msg = Message::User(UserMessage::Text(self.editor.lines().join("\n")));
+
+                for recipient in recipients.iter() {
+                    recipient.send(msg.clone());
+                }
+            }
+            EditorMode::File => {
+                let file_path = PathBuf::from(&self.editor.lines()[0]);
+
+                if !offer_path(file_path, recipients.clone()) {
+                    return false;
+                }
+            }
+        }
+
+        info!("Broadcasted msg to {} peers", recipients.len());
+        self.editor = TextArea::default();
+
+        true
+    }
+
+    // Returns true if screen should be closed.
+    pub fn handle_event(&mut self, key: KeyEvent, peers: &mut PeerList) -> bool {
+        if key.kind != crossterm::event::KeyEventKind::Press {
+            return false;
+        }
+
+        match key {
+            key if key.code == KeyCode::Esc => {
+                return true;
+            }
+            key if key.code == KeyCode::Tab => {
+                self.editor_mode = match self.editor_mode {
+                    EditorMode::Text => EditorMode::File,
+                    EditorMode::File => EditorMode::Text,
+                }
+            }
+            key if key.code == KeyCode::Enter && key.modifiers == KeyModifiers::NONE => {
+                let recipients = peers
+                    .broadcast_recipients()
+                    .map(PeerState::recipient)
+                    .collect();
+
+                return self.send(recipients);
+            }
+            editor_input => {
+                self.editor.input(editor_input);
+            }
+        }
+
+        false
+    }
+
+    pub fn render(&mut self, rect: &mut Rect, buf: &mut Buffer, peers: &PeerList) {
+        let names: Vec<&str> = peers
+            .broadcast_recipients()
+            .map(|peer| peer.name.as_str())
+            .collect();
+
+        let mode = match self.editor_mode {
+            EditorMode::Text => "Enter msg",
+            EditorMode::File => "Enter file path",
+        };
+
+        self.editor.set_block(
+            Block::default()
+                .title(format!(
+                    "{} for {} ({} peers): (Tab: msg/file, Enter: send to all, Esc: back)",
+                    mode,
+                    names.join(", "),
+                    names.len()
+                ))
+                .borders(Borders::ALL)
+                .border_style(Style::default().fg(Color::Green)),
+        );
+
+        Widget::render(&self.editor, *rect, buf);
+    }
+}
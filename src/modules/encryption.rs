@@ -0,0 +1,646 @@
+use bincode::serialize;
+use chacha20poly1305::aead::{AeadInPlace, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce, Tag};
+use curve25519_dalek::constants::RISTRETTO_BASEPOINT_POINT;
+use curve25519_dalek::ristretto::{CompressedRistretto, RistrettoPoint};
+use curve25519_dalek::scalar::Scalar;
+use ed25519_dalek::{Signature, Signer, VerifyingKey};
+use hkdf::Hkdf;
+use rand::rngs::OsRng;
+use serde::{de::DeserializeOwned, Serialize};
+use sha2::{Digest, Sha256, Sha512};
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+use x25519_dalek::{EphemeralSecret, PublicKey};
+
+use crate::config::IDENTITY;
+
+use super::protocol::*;
+
+// Domain separation labels, changing them breaks compatibility with older versions.
+static TRANSCRIPT_LABEL: &[u8] = b"CHATapp handshake v1";
+static SESSION_KEYS_LABEL: &[u8] = b"CHATapp session keys v1";
+static AUTH_LABEL: &[u8] = b"CHATapp handshake auth v1";
+static ROOM_KEY_LABEL: &[u8] = b"CHATapp room passphrase v2";
+static PAKE_SCALAR_LABEL: &[u8] = b"CHATapp room pake scalar v1";
+static PAKE_POINT_LABEL: &[u8] = b"CHATapp room pake point v1";
+
+/// Secret shared by users that know room passphrase.
+/// Nothing derived from it is ever sent, it's only used as SPAKE2 password during handshake.
+/// So passphrase can't be guessed offline from sniffed traffic, every guess needs a handshake with peer.
+pub struct RoomKey {
+    key: [u8; 32],
+}
+
+impl RoomKey {
+    pub fn derive(passphrase: &str) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(ROOM_KEY_LABEL);
+        hasher.update(passphrase.as_bytes());
+
+        RoomKey {
+            key: hasher.finalize().into(),
+        }
+    }
+
+    // Scalar used as password in SPAKE2.
+    fn pake_scalar(&self) -> Scalar {
+        Scalar::hash_from_bytes::<Sha512>(&[PAKE_SCALAR_LABEL, &self.key].concat())
+    }
+}
+
+/// Password authenticated key exchange (symmetric SPAKE2 over Ristretto).
+/// Both sides send x*G + w*S, where w is derived from room key and S is a point with unknown logarithm.
+/// Only side knowing w can remove w*S from peer message and compute same shared point.
+struct PakeState {
+    secret: Scalar,
+    password: Scalar,
+    public: [u8; 32],
+}
+
+impl PakeState {
+    fn new(room_key: &RoomKey) -> Self {
+        let secret = Scalar::random(&mut OsRng);
+        let password = room_key.pake_scalar();
+
+        let public = RISTRETTO_BASEPOINT_POINT * secret + pake_point() * password;
+
+        PakeState {
+            secret,
+            password,
+            public: public.compress().to_bytes(),
+        }
+    }
+
+    fn finish(&self, peer_public: &[u8; 32]) -> Result<[u8; 32], StreamSerializerError> {
+        let peer_public = CompressedRistretto(*peer_public)
+            .decompress()
+            .ok_or_else(|| StreamSerializerError::Crypto("Invalid pake key!".to_string()))?;
+
+        let shared = (peer_public - pake_point() * self.password) * self.secret;
+
+        Ok(shared.compress().to_bytes())
+    }
+}
+
+fn pake_point() -> RistrettoPoint {
+    RistrettoPoint::hash_from_bytes::<Sha512>(PAKE_POINT_LABEL)
+}
+
+/// One direction of encrypted connection.
+/// Every frame uses next value of counter as nonce, so nonce is never reused for given key.
+struct CipherState {
+    aead: ChaCha20Poly1305,
+    counter: u64,
+}
+
+impl CipherState {
+    fn new(key: &[u8]) -> Self {
+        CipherState {
+            aead: ChaCha20Poly1305::new(Key::from_slice(key)),
+            counter: 0,
+        }
+    }
+
+    fn next_nonce(&mut self) -> Nonce {
+        let mut nonce = [0u8; 12];
+        nonce[4..].copy_from_slice(&self.counter.to_be_bytes());
+        self.counter += 1;
+        nonce.into()
+    }
+
+    // Tag is appended to buffer, so frame needs no second allocation.
+    fn encrypt(&mut self, buffer: &mut Vec<u8>) -> Result<(), StreamSerializerError> {
+        let nonce = self.next_nonce();
+        Ok(self.aead.encrypt_in_place(&nonce, b"", buffer)?)
+    }
+
+    fn decrypt(&mut self, buffer: &mut Vec<u8>) -> Result<(), StreamSerializerError> {
+        let nonce = self.next_nonce();
+        Ok(self.aead.decrypt_in_place(&nonce, b"", buffer)?)
+    }
+
+    // Tag is written into last TAG_LENGTH bytes of frame.
+    fn encrypt_frame(&mut self, frame: &mut [u8]) -> Result<(), StreamSerializerError> {
+        let nonce = self.next_nonce();
+        let (data, tag) = frame.split_at_mut(frame.len() - TAG_LENGTH as usize);
+
+        let computed_tag = self.aead.encrypt_in_place_detached(&nonce, b"", data)?;
+        tag.copy_from_slice(&computed_tag);
+
+        Ok(())
+    }
+
+    fn decrypt_frame(&mut self, frame: &mut [u8]) -> Result<(), StreamSerializerError> {
+        let nonce = self.next_nonce();
+        let (data, tag) = frame.split_at_mut(frame.len() - TAG_LENGTH as usize);
+
+        Ok(self
+            .aead
+            .decrypt_in_place_detached(&nonce, b"", data, Tag::from_slice(tag))?)
+    }
+}
+
+// Size of Poly1305 tag appended to every frame.
+const TAG_LENGTH: u64 = 16;
+
+// Frame buffers bigger than this are freed after use instead of being kept for next frame.
+const REUSED_FRAME_CAPACITY: usize = 64 * 1024;
+
+// Frame format: 8 bytes of ciphertext len, ciphertext (bincode of msg + 16 bytes of tag).
+// Msg is serialized and encrypted in reused buffer, so small msgs (like acks) don't allocate.
+async fn write_frame<T: Serialize + FrameLimit, S: AsyncWriteExt + Unpin>(
+    stream: &mut S,
+    cipher: &mut CipherState,
+    buffer: &mut Vec<u8>,
+    msg: &T,
+) -> Result<(), StreamSerializerError> {
+    encode_frame_into(msg, buffer)?;
+    cipher.encrypt(buffer)?;
+    let frame_len = (buffer.len() as u64).to_be_bytes();
+
+    stream.write_all(&frame_len).await?;
+    stream.write_all(buffer).await?;
+
+    release_frame_buffer(buffer);
+    Ok(())
+}
+
+async fn read_frame_len<S: AsyncReadExt + Unpin>(
+    stream: &mut S,
+) -> Result<u64, StreamSerializerError> {
+    let mut frame_len_buff: [u8; 8] = [0; 8];
+    stream.read_exact(&mut frame_len_buff).await?;
+    Ok(u64::from_be_bytes(frame_len_buff))
+}
+
+async fn read_frame<T: DeserializeOwned + FrameLimit, S: AsyncReadExt + Unpin>(
+    stream: &mut S,
+    cipher: &mut CipherState,
+    buffer: &mut Vec<u8>,
+) -> Result<T, StreamSerializerError> {
+    let frame_len = read_frame_len(stream).await?;
+    read_frame_body(stream, cipher, buffer, frame_len).await
+}
+
+async fn read_frame_body<T: DeserializeOwned + FrameLimit, S: AsyncReadExt + Unpin>(
+    stream: &mut S,
+    cipher: &mut CipherState,
+    buffer: &mut Vec<u8>,
+    frame_len: u64,
+) -> Result<T, StreamSerializerError> {
+    // Chunk frame has flag set, so it is also rejected here.
+    check_frame_len(frame_len, T::frame_limit() + TAG_LENGTH)?;
+
+    buffer.resize(frame_len as usize, 0);
+    stream.read_exact(buffer).await?;
+    cipher.decrypt(buffer)?;
+
+    let msg = decode_frame::<T>(buffer);
+    release_frame_buffer(buffer);
+    msg
+}
+
+// Big msgs are rare, memory they needed is given back.
+fn release_frame_buffer(buffer: &mut Vec<u8>) {
+    buffer.clear();
+    if buffer.capacity() > REUSED_FRAME_CAPACITY {
+        *buffer = Vec::new();
+    }
+}
+
+// Chunk frame layout in buffer: 8 bytes of frame len, 8 bytes of file id, 8 bytes of file position, data, tag.
+// As in msg frames only frame len is sent in plain text.
+const CHUNK_HEADER_LENGTH: usize = 24;
+
+// Chunk frames have highest bit of frame len set, so they can be told apart from msg frames.
+const CHUNK_FRAME_FLAG: u64 = 1 << 63;
+
+/// Reusable buffer for file data.
+/// Chunk is read from disk straight into frame and encrypted in place,
+/// so sending or receiving a chunk doesn't allocate anything.
+pub struct ChunkBuffer {
+    buffer: Vec<u8>,
+    len: usize, // Bytes of file data currently in buffer.
+}
+
+impl ChunkBuffer {
+    pub fn new(capacity: usize) -> Self {
+        ChunkBuffer {
+            buffer: vec![0; CHUNK_HEADER_LENGTH + capacity + TAG_LENGTH as usize],
+            len: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buffer.len() - CHUNK_HEADER_LENGTH - TAG_LENGTH as usize
+    }
+
+    // Grows buffer, so it can hold chunk of given size.
+    fn reserve(&mut self, capacity: usize) {
+        if self.capacity() < capacity {
+            self.buffer
+                .resize(CHUNK_HEADER_LENGTH + capacity + TAG_LENGTH as usize, 0);
+        }
+    }
+
+    /// Prepares chunk of given length at position in file, returns space for its data.
+    pub fn fill(&mut self, file_id: FileID, position: FileSize, len: usize) -> &mut [u8] {
+        let len = len.min(self.capacity());
+        self.len = len;
+        self.buffer[8..16].copy_from_slice(&file_id.to_be_bytes());
+        self.buffer[16..CHUNK_HEADER_LENGTH].copy_from_slice(&position.to_be_bytes());
+        &mut self.buffer[CHUNK_HEADER_LENGTH..CHUNK_HEADER_LENGTH + len]
+    }
+
+    /// Shortens data of chunk, e.g. when less was read from disk than asked for.
+    pub fn truncate(&mut self, len: usize) {
+        self.len = self.len.min(len);
+    }
+
+    pub fn file_id(&self) -> FileID {
+        FileID::from_be_bytes(self.buffer[8..16].try_into().unwrap()) // This unwrap will never fail.
+    }
+
+    pub fn position(&self) -> FileSize {
+        FileSize::from_be_bytes(self.buffer[16..CHUNK_HEADER_LENGTH].try_into().unwrap())
+        // This unwrap will never fail.
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.buffer[CHUNK_HEADER_LENGTH..CHUNK_HEADER_LENGTH + self.len]
+    }
+
+    // Whole frame, from frame len to tag.
+    fn frame(&mut self) -> &mut [u8] {
+        &mut self.buffer[..CHUNK_HEADER_LENGTH + self.len + TAG_LENGTH as usize]
+    }
+}
+
+// Free chunk buffers kept by pool, about window * chunk_size of few transfers.
+const MAX_POOLED_BYTES: usize = 4 << 20;
+
+/// Free chunk buffers shared by all transfers on chat connections.
+/// Buffers go from uploader to writer and from reader to downloader, then come back here.
+pub struct ChunkPool {
+    free: Mutex<Vec<ChunkBuffer>>,
+}
+
+/// Pool used by chat connections.
+pub static CHUNK_POOL: ChunkPool = ChunkPool::new();
+
+impl ChunkPool {
+    pub const fn new() -> Self {
+        ChunkPool {
+            free: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Takes free buffer that can hold chunk of given size, allocates only if there is none.
+    pub fn take(&self, capacity: usize) -> ChunkBuffer {
+        match self.free.lock().unwrap().pop() {
+            Some(mut chunk) => {
+                chunk.reserve(capacity);
+                chunk
+            }
+            None => ChunkBuffer::new(capacity),
+        }
+    }
+
+    /// Gives buffer back, so next chunk can use it.
+    pub fn give(&self, chunk: ChunkBuffer) {
+        let mut free = self.free.lock().unwrap();
+        let pooled_bytes: usize = free.iter().map(|chunk| chunk.buffer.len()).sum();
+
+        if pooled_bytes + chunk.buffer.len() <= MAX_POOLED_BYTES {
+            free.push(chunk);
+        }
+    }
+}
+
+impl Default for ChunkPool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+async fn write_chunk<S: AsyncWriteExt + Unpin>(
+    stream: &mut S,
+    cipher: &mut CipherState,
+    chunk: &mut ChunkBuffer,
+) -> Result<(), StreamSerializerError> {
+    let frame = chunk.frame();
+    let frame_len = (frame.len() - 8) as u64 | CHUNK_FRAME_FLAG;
+    frame[..8].copy_from_slice(&frame_len.to_be_bytes());
+
+    cipher.encrypt_frame(&mut frame[8..])?;
+    stream.write_all(frame).await?;
+
+    // Buffer holds ciphertext now.
+    chunk.len = 0;
+
+    Ok(())
+}
+
+// Buffer grows up to limit if chunk doesn't fit.
+async fn read_chunk_body<S: AsyncReadExt + Unpin>(
+    stream: &mut S,
+    cipher: &mut CipherState,
+    chunk: &mut ChunkBuffer,
+    frame_len: u64,
+    limit: usize,
+) -> Result<(), StreamSerializerError> {
+    if frame_len & CHUNK_FRAME_FLAG == 0 {
+        return Err("Expected chunk frame!".into());
+    }
+    let frame_len = frame_len & !CHUNK_FRAME_FLAG;
+
+    // Frame len is checked before anything is written to buffer.
+    let header_len = (CHUNK_HEADER_LENGTH - 8) as u64 + TAG_LENGTH;
+    check_frame_len(frame_len, header_len + limit as u64)?;
+    if frame_len < header_len {
+        return Err("Chunk frame too short!".into());
+    }
+    chunk.reserve((frame_len - header_len) as usize);
+
+    let frame = &mut chunk.buffer[8..8 + frame_len as usize];
+    stream.read_exact(frame).await?;
+    cipher.decrypt_frame(frame)?;
+
+    chunk.len = (frame_len - header_len) as usize;
+
+    Ok(())
+}
+
+/// Frame read from chat connection, data of chunk is in buffer given by caller.
+pub enum Frame<T> {
+    Msg(T),
+    Chunk,
+}
+
+async fn read_msg_or_chunk<T: DeserializeOwned + FrameLimit, S: AsyncReadExt + Unpin>(
+    stream: &mut S,
+    cipher: &mut CipherState,
+    buffer: &mut Vec<u8>,
+    chunk: &mut ChunkBuffer,
+) -> Result<Frame<T>, StreamSerializerError> {
+    let frame_len = read_frame_len(stream).await?;
+
+    match frame_len & CHUNK_FRAME_FLAG != 0 {
+        // Chunk takes place of msg with file data, so the same limit applies.
+        true => {
+            read_chunk_body(stream, cipher, chunk, frame_len, T::frame_limit() as usize).await?;
+            Ok(Frame::Chunk)
+        }
+        false => Ok(Frame::Msg(
+            read_frame_body(stream, cipher, buffer, frame_len).await?,
+        )),
+    }
+}
+
+/// Tcp stream after successful handshake. Every frame is encrypted and authenticated.
+pub struct SecureStream {
+    stream: TcpStream,
+    sender: CipherState,
+    receiver: CipherState,
+    send_buffer: Vec<u8>,
+    read_buffer: Vec<u8>,
+}
+
+/// Reading half of SecureStream.
+pub struct SecureReadHalf {
+    stream: OwnedReadHalf,
+    cipher: CipherState,
+    buffer: Vec<u8>,
+}
+
+/// Writing half of SecureStream.
+pub struct SecureWriteHalf {
+    stream: OwnedWriteHalf,
+    cipher: CipherState,
+    buffer: Vec<u8>,
+}
+
+impl SecureStream {
+    /// Performs authenticated key exchange over freshly connected stream.
+    /// Returns encrypted stream together with verified identity key of peer.
+    ///
+    /// Both sides send HandshakeHello with their identity key and ephemeral X25519 key.
+    /// Session keys are derived from X25519 shared secret, after that each side signs
+    /// transcript of both hellos and sends the signature as first encrypted frame.
+    ///
+    /// With room key, hellos also carry SPAKE2 msgs and its result is mixed into session keys.
+    /// Peer with diffrent passphrase won't be able to decrypt anything, even our signature.
+    pub async fn handshake(
+        mut stream: TcpStream,
+        room_key: Option<&RoomKey>,
+    ) -> Result<(Self, VerifyingKey), StreamSerializerError> {
+        let ephemeral_secret = EphemeralSecret::random_from_rng(OsRng);
+        let pake_state = room_key.map(PakeState::new);
+
+        let hello = HandshakeHello {
+            identity_key: IDENTITY.signing_key.verifying_key().to_bytes(),
+            ephemeral_key: PublicKey::from(&ephemeral_secret).to_bytes(),
+            pake_key: pake_state.as_ref().map(|pake| pake.public),
+        };
+
+        hello.send(&mut stream).await?;
+        let peer_hello = HandshakeHello::read(&mut stream).await?;
+
+        // Protects from our own hello being reflected back to us.
+        if peer_hello.ephemeral_key == hello.ephemeral_key {
+            return Err(StreamSerializerError::Crypto(
+                "Peer reflected our handshake!".to_string(),
+            ));
+        }
+
+        let peer_identity = VerifyingKey::from_bytes(&peer_hello.identity_key)?;
+
+        let shared_secret =
+            ephemeral_secret.diffie_hellman(&PublicKey::from(peer_hello.ephemeral_key));
+
+        if !shared_secret.was_contributory() {
+            return Err(StreamSerializerError::Crypto(
+                "Peer sent low order ephemeral key!".to_string(),
+            ));
+        }
+
+        // Both sides have to agree on order of hellos, ephemeral keys are used to decide it.
+        let we_are_first = hello.ephemeral_key < peer_hello.ephemeral_key;
+        let (first, second) = match we_are_first {
+            true => (&hello, &peer_hello),
+            false => (&peer_hello, &hello),
+        };
+
+        let mut transcript = Sha256::new();
+        transcript.update(TRANSCRIPT_LABEL);
+        transcript.update(serialize(first)?);
+        transcript.update(serialize(second)?);
+        let transcript = transcript.finalize();
+
+        let mut key_material = shared_secret.as_bytes().to_vec();
+
+        match (&pake_state, &peer_hello.pake_key) {
+            (Some(pake), Some(peer_pake_key)) => {
+                key_material.extend_from_slice(&pake.finish(peer_pake_key)?);
+            }
+            (None, None) => {}
+            _ => {
+                return Err(StreamSerializerError::Crypto(
+                    "Peer is not in the same private room!".to_string(),
+                ));
+            }
+        }
+
+        let mut session_keys = [0u8; 64];
+        Hkdf::<Sha256>::new(Some(&transcript), &key_material)
+            .expand(SESSION_KEYS_LABEL, &mut session_keys)
+            .map_err(|_| StreamSerializerError::Crypto("Key derivation failed!".to_string()))?;
+
+        let (first_key, second_key) = session_keys.split_at(32);
+        let (send_key, receive_key) = match we_are_first {
+            true => (first_key, second_key),
+            false => (second_key, first_key),
+        };
+
+        let mut secure_stream = SecureStream {
+            stream,
+            sender: CipherState::new(send_key),
+            receiver: CipherState::new(receive_key),
+            send_buffer: Vec::new(),
+            read_buffer: Vec::new(),
+        };
+
+        // Prove that we own identity key that we have sent.
+        let signature = IDENTITY
+            .signing_key
+            .sign(&auth_payload(&transcript, &hello.ephemeral_key));
+        secure_stream
+            .send(&HandshakeAuth {
+                signature: signature.to_bytes().to_vec(),
+            })
+            .await?;
+
+        // With wrong passphrase this is the first frame that can't be decrypted.
+        let peer_auth: HandshakeAuth = secure_stream.read().await?;
+        let peer_signature = Signature::from_slice(&peer_auth.signature)?;
+
+        peer_identity.verify_strict(
+            &auth_payload(&transcript, &peer_hello.ephemeral_key),
+            &peer_signature,
+        )?;
+
+        Ok((secure_stream, peer_identity))
+    }
+
+    pub async fn send<T: Serialize + FrameLimit>(
+        &mut self,
+        msg: &T,
+    ) -> Result<(), StreamSerializerError> {
+        write_frame(
+            &mut self.stream,
+            &mut self.sender,
+            &mut self.send_buffer,
+            msg,
+        )
+        .await
+    }
+
+    pub async fn read<T: DeserializeOwned + FrameLimit>(
+        &mut self,
+    ) -> Result<T, StreamSerializerError> {
+        read_frame(&mut self.stream, &mut self.receiver, &mut self.read_buffer).await
+    }
+
+    /// Sends file data from chunk buffer as one frame, without serialization.
+    pub async fn send_chunk(
+        &mut self,
+        chunk: &mut ChunkBuffer,
+    ) -> Result<(), StreamSerializerError> {
+        write_chunk(&mut self.stream, &mut self.sender, chunk).await
+    }
+
+    /// Reads chunk frame into buffer, frames bigger than buffer and msg frames are rejected.
+    pub async fn read_chunk(
+        &mut self,
+        chunk: &mut ChunkBuffer,
+    ) -> Result<(), StreamSerializerError> {
+        let frame_len = read_frame_len(&mut self.stream).await?;
+        let limit = chunk.capacity();
+        read_chunk_body(
+            &mut self.stream,
+            &mut self.receiver,
+            chunk,
+            frame_len,
+            limit,
+        )
+        .await
+    }
+
+    pub fn peer_addr(&self) -> std::io::Result<SocketAddr> {
+        self.stream.peer_addr()
+    }
+
+    pub fn into_split(self) -> (SecureReadHalf, SecureWriteHalf) {
+        let (read_half, write_half) = self.stream.into_split();
+
+        (
+            SecureReadHalf {
+                stream: read_half,
+                cipher: self.receiver,
+                buffer: self.read_buffer,
+            },
+            SecureWriteHalf {
+                stream: write_half,
+                cipher: self.sender,
+                buffer: self.send_buffer,
+            },
+        )
+    }
+}
+
+impl SecureReadHalf {
+    pub async fn read<T: DeserializeOwned + FrameLimit>(
+        &mut self,
+    ) -> Result<T, StreamSerializerError> {
+        read_frame(&mut self.stream, &mut self.cipher, &mut self.buffer).await
+    }
+
+    /// Reads next frame, file data of chunk frame goes to given buffer.
+    /// Chunk can be as big as msg, buffer grows if needed.
+    pub async fn read_msg_or_chunk<T: DeserializeOwned + FrameLimit>(
+        &mut self,
+        chunk: &mut ChunkBuffer,
+    ) -> Result<Frame<T>, StreamSerializerError> {
+        read_msg_or_chunk(&mut self.stream, &mut self.cipher, &mut self.buffer, chunk).await
+    }
+}
+
+impl SecureWriteHalf {
+    pub async fn send<T: Serialize + FrameLimit>(
+        &mut self,
+        msg: &T,
+    ) -> Result<(), StreamSerializerError> {
+        write_frame(&mut self.stream, &mut self.cipher, &mut self.buffer, msg).await
+    }
+
+    /// Sends file data from chunk buffer as one frame, without serialization.
+    pub async fn send_chunk(
+        &mut self,
+        chunk: &mut ChunkBuffer,
+    ) -> Result<(), StreamSerializerError> {
+        write_chunk(&mut self.stream, &mut self.cipher, chunk).await
+    }
+}
+
+// Data signed by each side, ephemeral key of signer makes both signatures diffrent.
+fn auth_payload(transcript: &[u8], ephemeral_key: &[u8; 32]) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(AUTH_LABEL.len() + transcript.len() + 32);
+    payload.extend_from_slice(AUTH_LABEL);
+    payload.extend_from_slice(transcript);
+    payload.extend_from_slice(ephemeral_key);
+    payload
+}
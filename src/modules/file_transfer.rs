@@ -0,0 +1,1175 @@
+use cli_log::*;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use subtle::ConstantTimeEq;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::{mpsc, watch, Semaphore};
+
+use crate::config::*;
+use crate::modules::encryption::CHUNK_POOL;
+use crate::modules::message_bubble::*;
+use crate::modules::offers::OFFERS;
+use crate::modules::parallel_transfer::receive_ranges;
+use crate::modules::protocol::*;
+use crate::modules::throttle::Throttle;
+
+pub type DownloadedFilesMap = Arc<Mutex<HashMap<FileID, mpsc::Sender<FilePacket>>>>;
+pub type PartialFilesMap = Arc<Mutex<HashMap<FileID, PathBuf>>>; // Interrupted downloads.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum UploadState {
+    Running,
+    Paused,
+    Cancelled,
+    Interrupted, // Connection ended.
+}
+
+/// Handle used to steer running file_uploader and to show its progress.
+/// Every chunk takes one credit, peer gives them back with FileAck after writing chunks to disk.
+/// This way only few chunks are in flight and chat messages don't wait behind megabytes of file.
+pub struct UploadControl {
+    credits: Semaphore,
+    window: usize,
+    state: watch::Sender<UploadState>,
+    throttle: Option<Throttle>,
+    pub loading_bar: Arc<Mutex<LoadingBarWrap>>,
+}
+
+impl UploadControl {
+    pub fn new(window: usize) -> Self {
+        UploadControl {
+            credits: Semaphore::new(window),
+            window,
+            state: watch::Sender::new(UploadState::Running),
+            throttle: None,
+            loading_bar: Arc::new(Mutex::new(LoadingBarWrap {
+                loadingbar: LoadingBar::Status(LoadingBarStatus::new(0, 1)),
+                changed: true,
+            })),
+        }
+    }
+
+    // Chunks are sent no faster than throttle allows.
+    pub fn throttled(mut self, throttle: Throttle) -> Self {
+        self.throttle = Some(throttle);
+        self
+    }
+
+    // Waits until throttle lets given number of bytes through.
+    pub async fn throttle(&self, bytes: usize) {
+        if let Some(throttle) = &self.throttle {
+            throttle.acquire(bytes).await;
+        }
+    }
+
+    // Peer can't give more credits than window.
+    pub fn add_credits(&self, chunks: u32) {
+        let missing = self.window.saturating_sub(self.credits.available_permits());
+        self.credits.add_permits((chunks as usize).min(missing));
+    }
+
+    pub fn pause(&self) {
+        self.set_state(UploadState::Running, UploadState::Paused);
+        set_paused(&self.loading_bar, self.is_paused());
+    }
+
+    pub fn resume(&self) {
+        self.set_state(UploadState::Paused, UploadState::Running);
+        set_paused(&self.loading_bar, self.is_paused());
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.state.borrow() == UploadState::Paused
+    }
+
+    pub fn cancel(&self) {
+        self.stop(UploadState::Cancelled);
+    }
+
+    pub fn interrupt(&self) {
+        self.stop(UploadState::Interrupted);
+    }
+
+    fn set_state(&self, from: UploadState, to: UploadState) {
+        self.state.send_if_modified(|state| {
+            let modified = *state == from;
+            if modified {
+                *state = to;
+            }
+            modified
+        });
+    }
+
+    // Stopped upload stays stopped, so the first reason is reported.
+    fn stop(&self, reason: UploadState) {
+        self.set_state(UploadState::Running, reason);
+        self.set_state(UploadState::Paused, reason);
+        self.credits.close();
+    }
+
+    fn stop_reason(&self) -> String {
+        match *self.state.borrow() {
+            UploadState::Cancelled => "Upload cancelled!".to_string(),
+            _ => "Upload interrupted!".to_string(),
+        }
+    }
+
+    // Waits until upload isn't paused and chunk can be sent.
+    async fn wait_for_credit(&self) -> Result<(), String> {
+        let mut state = self.state.subscribe();
+        let _ = state.wait_for(|state| *state != UploadState::Paused).await;
+
+        match self.credits.acquire().await {
+            Ok(credit) => {
+                credit.forget();
+                Ok(())
+            }
+            Err(_) => Err(self.stop_reason()), // Closed when stopped.
+        }
+    }
+
+    // Waits until upload isn't paused. Used by range uploads, which don't take credits.
+    pub async fn wait_until_running(&self) -> Result<(), String> {
+        let mut state = self.state.subscribe();
+        let state = state
+            .wait_for(|state| *state != UploadState::Paused)
+            .await
+            .map(|state| *state);
+
+        match state {
+            Ok(UploadState::Running) => Ok(()),
+            _ => Err(self.stop_reason()),
+        }
+    }
+
+    // Resolves when upload is cancelled or interrupted, with the reason.
+    pub async fn stopped(&self) -> String {
+        let mut state = self.state.subscribe();
+        let _ = state
+            .wait_for(|state| matches!(state, UploadState::Cancelled | UploadState::Interrupted))
+            .await;
+
+        self.stop_reason()
+    }
+
+    // Waits until peer acknowledges all chunks.
+    async fn wait_for_delivery(&self) -> Result<(), String> {
+        match self.credits.acquire_many(self.window as u32).await {
+            Ok(_) => Ok(()),
+            Err(_) => Err(self.stop_reason()),
+        }
+    }
+}
+
+/// Uploads running on one connection, all of them are interrupted when it ends.
+#[derive(Default)]
+pub struct RunningUploads {
+    uploads: HashMap<FileID, Arc<UploadControl>>,
+}
+
+impl RunningUploads {
+    // Peer restarted download, old upload is stopped.
+    pub fn insert(&mut self, file_id: FileID, control: Arc<UploadControl>) {
+        // Finished uploaders don't hold their control anymore.
+        self.uploads
+            .retain(|_, control| Arc::strong_count(control) > 1);
+
+        if let Some(previous) = self.uploads.insert(file_id, control) {
+            previous.cancel();
+        }
+    }
+
+    // Applies ack, pause, resume or cancel sent by peer to its upload.
+    // Returns false if message isn't about any running upload.
+    pub fn handle(&self, message: &InternalMessage) -> bool {
+        let (InternalMessage::FileAck(file_id, _)
+        | InternalMessage::FileCancel(file_id)
+        | InternalMessage::FilePause(file_id)
+        | InternalMessage::FileResume(file_id)) = message
+        else {
+            return false;
+        };
+
+        let Some(control) = self.uploads.get(file_id) else {
+            return false;
+        };
+
+        match message {
+            InternalMessage::FileAck(_, chunks) => control.add_credits(*chunks),
+            InternalMessage::FileCancel(_) => control.cancel(),
+            InternalMessage::FilePause(_) => control.pause(),
+            InternalMessage::FileResume(_) => control.resume(),
+            _ => {}
+        }
+
+        true
+    }
+}
+
+impl Drop for RunningUploads {
+    fn drop(&mut self) {
+        for control in self.uploads.values() {
+            control.interrupt();
+        }
+    }
+}
+
+// Leaves space for " (9)" and ".part" within 255 bytes allowed by most file systems.
+const MAX_FILE_NAME_LEN: usize = 200;
+
+// Names that can't be used as file names on Windows, regardless of extension.
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Converts file name received from peer into name that is safe to create in download directory.
+/// Only last path component is kept, names that are only "." or ".." are rejected.
+/// Other problems are silently fixed: control, bidi and special characters are removed or replaced,
+/// reserved names are prefixed and length is capped.
+pub fn sanitize_file_name(file_name: &str) -> Result<String, String> {
+    let mut last = file_name.rsplit(['/', '\\']).next().unwrap_or_default(); // Split always returns something.
+    if is_drive_prefixed(last) {
+        last = &last[2..];
+    }
+
+    if last == ".." || last == "." {
+        return Err(format!("\"{}\" is a path", file_name.escape_debug()));
+    }
+
+    let mut name: String = last
+        .chars()
+        .filter(|c| !c.is_control() && !is_format_char(*c))
+        .map(|c| match c {
+            '<' | '>' | ':' | '"' | '|' | '?' | '*' => '_',
+            c => c,
+        })
+        .collect();
+
+    // Windows strips those silently, which could change meaning of name.
+    name = name.trim_end_matches(['.', ' ']).trim_start().to_string();
+
+    if name.is_empty() {
+        name = "download".to_string();
+    }
+
+    let stem = name.split('.').next().unwrap_or_default(); // Split always returns something.
+    if RESERVED_NAMES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(stem))
+    {
+        name.insert(0, '_');
+    }
+
+    if name.len() > MAX_FILE_NAME_LEN {
+        name = truncate_file_name(&name, MAX_FILE_NAME_LEN);
+    }
+
+    Ok(name)
+}
+
+// Invisible characters, bidi overrides can make "exe.txt" look like "txt.exe".
+fn is_format_char(c: char) -> bool {
+    matches!(c,
+        '\u{00AD}' | '\u{061C}' | '\u{180E}' | '\u{200B}'..='\u{200F}' | '\u{202A}'..='\u{202E}'
+        | '\u{2060}'..='\u{206F}' | '\u{FEFF}' | '\u{FFF9}'..='\u{FFFB}')
+}
+
+// Windows paths like "C:name" are relative to current directory of drive C.
+fn is_drive_prefixed(file_name: &str) -> bool {
+    let mut chars = file_name.chars();
+    matches!((chars.next(), chars.next()), (Some(c), Some(':')) if c.is_ascii_alphabetic())
+}
+
+// Shortens name to at most max_len bytes, keeping extension if it is reasonably short.
+fn truncate_file_name(name: &str, max_len: usize) -> String {
+    let (stem, extension) = match name.rfind('.') {
+        Some(idx) if idx > 0 && name.len() - idx <= 16 => name.split_at(idx),
+        _ => (name, ""),
+    };
+
+    let mut stem_len = max_len - extension.len();
+    while !stem.is_char_boundary(stem_len) {
+        stem_len -= 1;
+    }
+
+    format!("{}{}", &stem[..stem_len], extension)
+}
+
+// Name used when file with given name already exists: "name (i).ext".
+fn numbered_file_name(name: &str, i: usize) -> String {
+    if i == 0 {
+        return name.to_string();
+    }
+
+    match name.rfind('.') {
+        Some(idx) if idx > 0 => format!("{} ({}){}", &name[..idx], i, &name[idx..]),
+        _ => format!("{} ({})", name, i),
+    }
+}
+
+// Moves finished .part file to its name, or to the first free numbered name if something
+// was created there during download. Existing files are never overwritten.
+pub async fn rename_part_file(file_path: &Path) -> std::io::Result<PathBuf> {
+    let name = file_path.file_name().unwrap_or_default().to_string_lossy();
+
+    for i in 0..10 {
+        let target = file_path.with_file_name(numbered_file_name(&name, i));
+
+        // Unlike rename, hard link fails if target exists.
+        match tokio::fs::hard_link(part_path(file_path), &target).await {
+            Ok(()) => {
+                tokio::fs::remove_file(part_path(file_path)).await?;
+                return Ok(target);
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            // File systems without hard links.
+            Err(_) if !tokio::fs::try_exists(&target).await.unwrap_or(true) => {
+                tokio::fs::rename(part_path(file_path), &target).await?;
+                return Ok(target);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    Err(std::io::Error::new(
+        std::io::ErrorKind::AlreadyExists,
+        format!("Couldn't create file \"{}\"!", name),
+    ))
+}
+
+pub fn part_path(file_path: &Path) -> PathBuf {
+    let mut part_name = file_path.file_name().unwrap_or_default().to_os_string();
+    part_name.push(".part");
+    file_path.with_file_name(part_name)
+}
+
+pub fn set_loading_bar(loading_bar: &Mutex<LoadingBarWrap>, state: LoadingBar) {
+    *loading_bar.lock().unwrap() = LoadingBarWrap {
+        loadingbar: state,
+        changed: true,
+    };
+}
+
+/// Marks running transfer as paused or resumed, finished ones are left alone.
+pub fn set_paused(loading_bar: &Mutex<LoadingBarWrap>, paused: bool) {
+    let mut loading_bar_lock = loading_bar.lock().unwrap();
+
+    if let LoadingBar::Status(status) = &mut loading_bar_lock.loadingbar {
+        status.paused = paused;
+        loading_bar_lock.changed = true;
+    }
+}
+
+/// Computes hash sent in file header, reading file in chunks.
+pub async fn hash_file(file_path: &Path) -> std::io::Result<FileHash> {
+    let mut file = tokio::fs::File::open(file_path).await?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 1 << 16];
+
+    loop {
+        let n = file.read(&mut buffer).await?;
+        if n == 0 {
+            break Ok(hasher.finalize().into());
+        }
+        hasher.update(&buffer[..n]);
+    }
+}
+
+// Function responsible for downloading given file in the background.
+#[allow(clippy::too_many_arguments)]
+pub async fn file_downloader(
+    mut packets: mpsc::Receiver<FilePacket>,
+    requests: mpsc::UnboundedSender<Message>,
+    file_id: FileID,
+    file_name: String,
+    file_size: FileSize,
+    file_hash: FileHash,
+    loading_bar: Arc<Mutex<LoadingBarWrap>>,
+    downloaded_files: DownloadedFilesMap,
+    partial_files: PartialFilesMap,
+    throttle: Throttle,
+    connections: usize,
+    peer_id: u64,
+    download_dir: PathBuf,
+) {
+    let partial_file = partial_files.lock().unwrap().remove(&file_id);
+
+    let result = download(
+        &mut packets,
+        &requests,
+        file_id,
+        &file_name,
+        file_size,
+        &file_hash,
+        partial_file,
+        &loading_bar,
+        &throttle,
+        connections,
+        peer_id,
+        &download_dir,
+    )
+    .await;
+
+    match result {
+        Ok(file_path) => {
+            info!("Downloaded file to {}", file_path.display());
+            set_loading_bar(&loading_bar, LoadingBar::Verified(file_hash));
+        }
+        Err(DownloadError::Interrupted(file_path, e)) => {
+            // Partial file is kept, next try on the same message continues it.
+            partial_files.lock().unwrap().insert(file_id, file_path);
+            set_loading_bar(
+                &loading_bar,
+                LoadingBar::Error(format!("{} Press Enter to resume.", e)),
+            );
+        }
+        Err(DownloadError::Failed(e)) => {
+            set_loading_bar(&loading_bar, LoadingBar::Error(e));
+        }
+        Err(DownloadError::Cancelled) => {
+            set_loading_bar(
+                &loading_bar,
+                LoadingBar::Error("Download cancelled! Press Enter to start again.".to_string()),
+            );
+        }
+    }
+
+    // Clean map after yourself.
+    let _ = downloaded_files.lock().unwrap().remove(&file_id);
+}
+
+enum DownloadError {
+    Interrupted(PathBuf, String), // Final path of file, whose .part can be resumed.
+    Failed(String),
+    Cancelled,
+}
+
+impl From<String> for DownloadError {
+    fn from(e: String) -> Self {
+        DownloadError::Failed(e)
+    }
+}
+
+// Creates new .part file in given directory, choosing first free name.
+async fn create_part_file(
+    dir: &Path,
+    safe_name: &str,
+) -> Result<(PathBuf, tokio::fs::File), String> {
+    // If files [name, name (1), ..., name (9)] exists in download dir, abandon download.
+    for i in 0..10 {
+        let file_path = dir.join(numbered_file_name(safe_name, i));
+
+        if file_path.exists() {
+            continue;
+        }
+
+        if let Ok(file) = tokio::fs::OpenOptions::new()
+            .create_new(true) // Ensures the file doesn't already exist
+            .write(true)
+            .open(part_path(&file_path))
+            .await
+        {
+            return Ok((file_path, file));
+        }
+    }
+
+    Err(format!("Couldn't create file \"{}\"!", safe_name))
+}
+
+// Opens .part file left by interrupted download and hashes bytes already received.
+async fn open_part_file(
+    file_path: &Path,
+    file_size: FileSize,
+) -> std::io::Result<(tokio::fs::File, FileSize, Sha256)> {
+    let mut file = tokio::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(part_path(file_path))
+        .await?;
+
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 1 << 16];
+    let mut offset = 0;
+
+    loop {
+        let n = file.read(&mut buffer).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+        offset += n as FileSize;
+    }
+
+    if offset > file_size {
+        return Err(std::io::Error::other("partial file is bigger than file"));
+    }
+
+    Ok((file, offset, hasher))
+}
+
+// Downloads file into .part file in given folder and renames it when all bytes are received and hash matches.
+// Fresh download is split between given number of data connections if there is more than one.
+#[allow(clippy::too_many_arguments)]
+async fn download(
+    packets: &mut mpsc::Receiver<FilePacket>,
+    requests: &mpsc::UnboundedSender<Message>,
+    file_id: FileID,
+    file_name: &str,
+    file_size: FileSize,
+    file_hash: &FileHash,
+    partial_file: Option<PathBuf>,
+    loading_bar: &Mutex<LoadingBarWrap>,
+    throttle: &Throttle,
+    connections: usize,
+    peer_id: u64, // Only this peer can open data connections.
+    download_dir: &Path,
+) -> Result<PathBuf, DownloadError> {
+    let safe_name = sanitize_file_name(file_name).map_err(|e| {
+        warn!("Rejected download with unsafe file name: {}", e);
+        format!("Unsafe file name rejected: {}", e)
+    })?;
+
+    let resumed = match partial_file {
+        Some(file_path) => match open_part_file(&file_path, file_size).await {
+            Ok((file, offset, hasher)) => Some((file_path, file, offset, hasher)),
+            Err(e) => {
+                warn!("Couldn't resume {}: {}", file_path.display(), e);
+                let _ = tokio::fs::remove_file(part_path(&file_path)).await;
+                None
+            }
+        },
+        None => None,
+    };
+
+    let (file_path, mut file, offset, hasher) = match resumed {
+        Some(resumed) => resumed,
+        None => {
+            tokio::fs::create_dir_all(download_dir)
+                .await
+                .map_err(|e| format!("Couldn't create folder {}: {}", download_dir.display(), e))?;
+
+            let (file_path, file) = create_part_file(download_dir, &safe_name).await?;
+            (file_path, file, 0, Sha256::new())
+        }
+    };
+
+    set_loading_bar(
+        loading_bar,
+        LoadingBar::Status(LoadingBarStatus::new(offset, file_size)),
+    );
+
+    let mut parallel = false;
+
+    if offset == 0 && connections > 1 {
+        let result = receive_ranges(
+            packets,
+            requests,
+            &part_path(&file_path),
+            file_id,
+            file_size,
+            connections,
+            peer_id,
+            loading_bar,
+            throttle,
+        )
+        .await;
+
+        parallel = match result {
+            Ok(parallel) => parallel,
+            Err(e) => return Err(download_error(e, file_path).await),
+        };
+    }
+
+    let result = match parallel {
+        // Ranges arrive out of order, so file is hashed after it's complete.
+        true => hash_file(&part_path(&file_path))
+            .await
+            .map_err(|e| ContentError::Failed(e.to_string())),
+        false => {
+            let prefix_hash: FileHash = hasher.clone().finalize().into();
+            let _ = requests.send(Message::Internal(InternalMessage::FileRequest(
+                file_id,
+                offset,
+                prefix_hash,
+            )));
+
+            receive_content(
+                packets,
+                requests,
+                &mut file,
+                file_size,
+                offset,
+                hasher,
+                0,
+                loading_bar,
+                throttle,
+            )
+            .await
+        }
+    };
+    drop(file);
+
+    let received_hash = match result {
+        Ok(received_hash) => received_hash,
+        Err(e) => return Err(download_error(e, file_path).await),
+    };
+
+    // Corrupted or tampered file never shows up under its final name.
+    if !bool::from(received_hash.ct_eq(file_hash)) {
+        warn!("Hash mismatch in downloaded file \"{}\"", safe_name);
+        let _ = tokio::fs::remove_file(part_path(&file_path)).await;
+        return Err("Hash mismatch, file was deleted!".to_string().into());
+    }
+
+    // File under final name is always complete.
+    rename_part_file(&file_path)
+        .await
+        .map_err(|e| e.to_string().into())
+}
+
+// Failed download can be resumed, cancelled one is deleted.
+async fn download_error(e: ContentError, file_path: PathBuf) -> DownloadError {
+    match e {
+        ContentError::Failed(e) => DownloadError::Interrupted(file_path, e),
+        ContentError::Cancelled => {
+            let _ = tokio::fs::remove_file(part_path(&file_path)).await;
+            DownloadError::Cancelled
+        }
+    }
+}
+
+pub enum ContentError {
+    Failed(String),
+    Cancelled, // By user or by peer.
+}
+
+impl From<String> for ContentError {
+    fn from(e: String) -> Self {
+        ContentError::Failed(e)
+    }
+}
+
+// Writes incoming packets to file until file_size bytes are received. Returns hash of content.
+// Uploader starts from offset, or from the begining if partial file doesn't match its file.
+// Loading bar shows progress_base + received bytes, so many files can share one bar.
+#[allow(clippy::too_many_arguments)]
+async fn receive_content(
+    packets: &mut mpsc::Receiver<FilePacket>,
+    requests: &mpsc::UnboundedSender<Message>,
+    file: &mut tokio::fs::File,
+    file_size: FileSize,
+    offset: FileSize,
+    mut hasher: Sha256,
+    progress_base: FileSize,
+    loading_bar: &Mutex<LoadingBarWrap>,
+    throttle: &Throttle,
+) -> Result<FileHash, ContentError> {
+    let mut byte_cnt = offset;
+    let mut first_packet = true;
+
+    file.seek(SeekFrom::Start(offset))
+        .await
+        .map_err(|e| e.to_string())?;
+
+    while byte_cnt != file_size {
+        // Writer stops together with connection, so nothing will arrive anymore.
+        let packet = tokio::select! {
+            biased;
+            packet = packets.recv() => packet,
+            _ = requests.closed() => None,
+        };
+
+        let Some(packet) = packet else {
+            return Err(
+                format!("Download interrupted! Status: {}/{}.", byte_cnt, file_size).into(),
+            );
+        };
+
+        match packet {
+            FilePacket::Chunk(chunk) => {
+                let (id, byte_idx, bytes) = (chunk.file_id(), chunk.position(), chunk.data());
+
+                if first_packet && byte_idx == 0 && byte_cnt != 0 {
+                    info!("Partial file doesn't match, downloading from the begining");
+                    file.set_len(0).await.map_err(|e| e.to_string())?;
+                    file.seek(SeekFrom::Start(0))
+                        .await
+                        .map_err(|e| e.to_string())?;
+                    hasher = Sha256::new();
+                    byte_cnt = 0;
+                }
+                first_packet = false;
+
+                // Uploader waits for acks, so it has to be told that they won't come.
+                let stop_upload = || {
+                    let _ = requests.send(Message::Internal(InternalMessage::FileCancel(id)));
+                };
+
+                if byte_idx != byte_cnt || byte_idx + bytes.len() as FileSize > file_size {
+                    stop_upload();
+                    return Err(
+                        format!("Download error! Status: {}/{}.", byte_cnt, file_size).into(),
+                    );
+                }
+
+                file.write_all(bytes).await.map_err(|e| {
+                    stop_upload();
+                    e.to_string()
+                })?;
+                hasher.update(bytes);
+
+                let len = bytes.len();
+                CHUNK_POOL.give(chunk);
+
+                // Acks are held back to keep peer under download limit, chat doesn't wait for them.
+                throttle.acquire(len).await;
+
+                // Lets uploader send next chunk.
+                let _ = requests.send(Message::Internal(InternalMessage::FileAck(id, 1)));
+
+                byte_cnt += len as FileSize;
+
+                let mut loading_bar_lock = loading_bar.lock().unwrap();
+
+                if let LoadingBar::Status(status) = &mut loading_bar_lock.loadingbar {
+                    status.advance(progress_base + byte_cnt, Instant::now());
+                    loading_bar_lock.changed = true;
+                }
+            }
+            FilePacket::Internal(InternalMessage::FileContentError(_, e)) => {
+                return Err(e.into());
+            }
+            FilePacket::Internal(InternalMessage::FileCancel(_)) => {
+                return Err(ContentError::Cancelled);
+            }
+            FilePacket::Internal(InternalMessage::FilePause(_)) => set_paused(loading_bar, true),
+            FilePacket::Internal(InternalMessage::FileResume(_)) => set_paused(loading_bar, false),
+            FilePacket::Internal(_) => {}
+        }
+    }
+
+    // Make sure that all pending writes reach the file before it is renamed.
+    file.flush().await.map_err(|e| e.to_string())?;
+
+    Ok(hasher.finalize().into())
+}
+
+// Sanitizes every component of relative path received from peer.
+fn sanitize_path(components: &[String]) -> Result<PathBuf, String> {
+    if components.is_empty() {
+        return Err("empty path".to_string());
+    }
+
+    components
+        .iter()
+        .map(|component| sanitize_file_name(component))
+        .collect()
+}
+
+// Creates new directory, choosing first free name.
+async fn create_fresh_dir(dir: &Path, safe_name: &str) -> Result<PathBuf, String> {
+    for i in 0..10 {
+        let dir_path = dir.join(numbered_file_name(safe_name, i));
+
+        if tokio::fs::create_dir(&dir_path).await.is_ok() {
+            return Ok(dir_path);
+        }
+    }
+
+    Err(format!("Couldn't create directory \"{}\"!", safe_name))
+}
+
+/// Lists directory for sharing: relative paths of subdirectories and files with their sizes.
+/// Symlinks and special files are skipped, so nothing outside of the tree is shared.
+#[allow(clippy::type_complexity)]
+pub fn scan_directory(
+    root: &Path,
+) -> std::io::Result<(Vec<Vec<String>>, Vec<(Vec<String>, PathBuf, FileSize)>)> {
+    let mut directories = Vec::new();
+    let mut files = Vec::new();
+    let mut stack = vec![(root.to_path_buf(), Vec::new())];
+
+    while let Some((dir, relative)) = stack.pop() {
+        for entry in std::fs::read_dir(&dir)? {
+            let entry = entry?;
+            let metadata = std::fs::symlink_metadata(entry.path())?; // Doesn't follow symlinks.
+
+            let mut path = relative.clone();
+            path.push(entry.file_name().to_string_lossy().to_string());
+
+            if metadata.is_dir() {
+                directories.push(path.clone());
+                stack.push((entry.path(), path));
+            } else if metadata.is_file() {
+                files.push((path, entry.path(), metadata.len()));
+            } else {
+                info!(
+                    "Skipping {} while sharing directory",
+                    entry.path().display()
+                );
+            }
+        }
+    }
+
+    Ok((directories, files))
+}
+
+/// Prepares header of shared directory. All files get their ids offered to given peers.
+pub async fn share_directory(root: PathBuf, peers: &[u64]) -> std::io::Result<DirectoryTree> {
+    let name = root
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| "directory".to_string());
+
+    let scan_root = root.clone();
+    let (directories, scanned_files) =
+        tokio::task::spawn_blocking(move || scan_directory(&scan_root)).await??;
+
+    let mut files = Vec::with_capacity(scanned_files.len());
+    let mut offered = Vec::with_capacity(scanned_files.len());
+
+    for (path, file_path, size) in scanned_files {
+        let hash = hash_file(&file_path).await?;
+        let id: FileID = rand::random();
+
+        offered.push((id, file_path));
+        files.push(DirectoryEntry {
+            path,
+            size,
+            id,
+            hash,
+        });
+    }
+
+    OFFERS.lock().unwrap().add_many(offered, peers, unix_time());
+
+    Ok(DirectoryTree {
+        name,
+        directories,
+        files,
+    })
+}
+
+// Hash shown for whole directory, derived from hashes of all files.
+fn tree_hash(tree: &DirectoryTree) -> FileHash {
+    let mut hasher = Sha256::new();
+    for file in &tree.files {
+        hasher.update(file.hash);
+    }
+    hasher.finalize().into()
+}
+
+// Function responsible for downloading shared directory in the background.
+// Files are downloaded one by one, failure of one file doesn't stop the others.
+pub async fn directory_downloader(
+    requests: mpsc::UnboundedSender<Message>,
+    tree: DirectoryTree,
+    loading_bar: Arc<Mutex<LoadingBarWrap>>,
+    downloaded_files: DownloadedFilesMap,
+    throttle: Throttle,
+    download_dir: PathBuf,
+) {
+    let root = match sanitize_file_name(&tree.name) {
+        Ok(safe_name) => match tokio::fs::create_dir_all(&download_dir).await {
+            Ok(()) => create_fresh_dir(&download_dir, &safe_name).await,
+            Err(e) => Err(format!(
+                "Couldn't create folder {}: {}",
+                download_dir.display(),
+                e
+            )),
+        },
+        Err(e) => {
+            warn!("Rejected directory with unsafe name: {}", e);
+            Err(format!("Unsafe directory name rejected: {}", e))
+        }
+    };
+
+    let root = match root {
+        Ok(root) => root,
+        Err(e) => {
+            set_loading_bar(&loading_bar, LoadingBar::Error(e));
+            return;
+        }
+    };
+
+    set_loading_bar(
+        &loading_bar,
+        LoadingBar::Status(LoadingBarStatus::new(0, tree.total_size())),
+    );
+
+    let mut errors = Vec::new();
+
+    for dir in &tree.directories {
+        let result = match sanitize_path(dir) {
+            Ok(relative) => tokio::fs::create_dir_all(root.join(relative))
+                .await
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(e),
+        };
+
+        if let Err(e) = result {
+            errors.push(format!("{}: {}", dir.join("/"), e));
+        }
+    }
+
+    let mut progress_base: FileSize = 0;
+
+    for file in &tree.files {
+        let result = download_entry(
+            &requests,
+            &downloaded_files,
+            &root,
+            file,
+            progress_base,
+            &loading_bar,
+            &throttle,
+        )
+        .await;
+
+        match result {
+            Ok(()) => {}
+            Err(ContentError::Failed(e)) => {
+                errors.push(format!("{}: {}", file.path.join("/"), e));
+            }
+            Err(ContentError::Cancelled) => {
+                // Files downloaded so far are kept.
+                set_loading_bar(
+                    &loading_bar,
+                    LoadingBar::Error(
+                        "Download cancelled! Press Enter to start again.".to_string(),
+                    ),
+                );
+                return;
+            }
+        }
+
+        progress_base = progress_base.saturating_add(file.size);
+    }
+
+    for e in &errors {
+        warn!("Error while downloading directory {}: {}", tree.name, e);
+    }
+
+    match errors.first() {
+        None => {
+            info!("Downloaded directory to {}", root.display());
+            set_loading_bar(&loading_bar, LoadingBar::Verified(tree_hash(&tree)));
+        }
+        Some(e) => set_loading_bar(
+            &loading_bar,
+            LoadingBar::Error(format!(
+                "{} of {} entries failed! {}",
+                errors.len(),
+                tree.directories.len() + tree.files.len(),
+                e
+            )),
+        ),
+    }
+}
+
+// Downloads one file of directory to its place under root.
+async fn download_entry(
+    requests: &mpsc::UnboundedSender<Message>,
+    downloaded_files: &DownloadedFilesMap,
+    root: &Path,
+    entry: &DirectoryEntry,
+    progress_base: FileSize,
+    loading_bar: &Mutex<LoadingBarWrap>,
+    throttle: &Throttle,
+) -> Result<(), ContentError> {
+    let relative = sanitize_path(&entry.path).inspect_err(|e| {
+        warn!("Rejected directory entry with unsafe path: {}", e);
+    })?;
+    let file_path = root.join(relative);
+
+    if let Some(parent) = file_path.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+
+    // Leftover .part file isn't ours, entry fails instead of overwriting it.
+    let mut file = tokio::fs::OpenOptions::new()
+        .create_new(true)
+        .write(true)
+        .open(part_path(&file_path))
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let (tx, mut rx) = mpsc::channel::<FilePacket>(settings().transfer.window());
+    downloaded_files.lock().unwrap().insert(entry.id, tx);
+
+    let _ = requests.send(Message::Internal(InternalMessage::FileRequest(
+        entry.id,
+        0,
+        Sha256::digest(b"").into(),
+    )));
+
+    let result = receive_content(
+        &mut rx,
+        requests,
+        &mut file,
+        entry.size,
+        0,
+        Sha256::new(),
+        progress_base,
+        loading_bar,
+        throttle,
+    )
+    .await
+    .and_then(
+        |received_hash| match received_hash.ct_eq(&entry.hash).into() {
+            true => Ok(()),
+            false => Err("Hash mismatch, file was deleted!".to_string().into()),
+        },
+    );
+
+    downloaded_files.lock().unwrap().remove(&entry.id);
+    drop(file);
+
+    // Two entries can end up with the same name after sanitization, second one gets numbered name.
+    match result {
+        Ok(()) => match rename_part_file(&file_path).await {
+            Ok(_) => Ok(()),
+            Err(e) => Err(e.to_string().into()),
+        },
+        Err(e) => {
+            let _ = tokio::fs::remove_file(part_path(&file_path)).await;
+            Err(e)
+        }
+    }
+}
+
+// Checks if peer has the same first offset bytes of file, so upload can continue from there.
+async fn prefix_matches(
+    file: &mut tokio::fs::File,
+    offset: FileSize,
+    prefix_hash: &FileHash,
+) -> std::io::Result<bool> {
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 1 << 16];
+    let mut remaining = offset;
+
+    while remaining > 0 {
+        let len = remaining.min(buffer.len() as FileSize) as usize;
+        let n = file.read(&mut buffer[..len]).await?;
+        if n == 0 {
+            return Ok(false); // File is shorter than offset.
+        }
+        hasher.update(&buffer[..n]);
+        remaining -= n as FileSize;
+    }
+
+    Ok(bool::from(hasher.finalize().ct_eq(prefix_hash)))
+}
+
+// Function responsible for uploading given file in the background.
+// Packets queue is bounded, so reading waits for socket and memory use doesn't depend on file size.
+// Chunks are sent only while control has credits, pausing and cancelling also goes through it.
+pub async fn file_uploader(
+    packets: mpsc::Sender<FilePacket>,
+    control: Arc<UploadControl>,
+    file_name: PathBuf,
+    file_id: FileID,
+    offset: FileSize,
+    prefix_hash: FileHash,
+) {
+    let result = upload(packets, &control, file_name, file_id, offset, prefix_hash).await;
+
+    match result {
+        Ok(()) => set_loading_bar(&control.loading_bar, LoadingBar::Delivered),
+        Err(e) => set_loading_bar(&control.loading_bar, LoadingBar::Error(e)),
+    }
+}
+
+// Tells peer why upload failed and returns the reason.
+pub async fn report_error(packets: &mpsc::Sender<FilePacket>, file_id: FileID, e: &str) -> String {
+    let _ = packets
+        .send(FilePacket::Internal(InternalMessage::FileContentError(
+            file_id,
+            e.to_string(),
+        )))
+        .await;
+    e.to_string()
+}
+
+// Sends file and waits until peer writes it. Errors with file are also reported to peer.
+async fn upload(
+    packets: mpsc::Sender<FilePacket>,
+    control: &UploadControl,
+    file_name: PathBuf,
+    file_id: FileID,
+    offset: FileSize,
+    prefix_hash: FileHash,
+) -> Result<(), String> {
+    // Open the file in read-only mode
+    let Ok(mut file) = tokio::fs::File::open(file_name).await else {
+        return Err(report_error(&packets, file_id, "File does not exsists anymore!").await);
+    };
+
+    let file_size = file.metadata().await.map(|m| m.len()).unwrap_or(0);
+
+    // Peer with diffrent prefix gets the whole file again.
+    let mut byte_idx = match prefix_matches(&mut file, offset, &prefix_hash).await {
+        Ok(true) => offset,
+        _ => 0,
+    };
+
+    if file.seek(SeekFrom::Start(byte_idx)).await.is_err() {
+        return Err(report_error(&packets, file_id, "Error reading file!").await);
+    }
+
+    let mut status = LoadingBarStatus::new(byte_idx, file_size);
+    status.paused = control.is_paused();
+    set_loading_bar(&control.loading_bar, LoadingBar::Status(status));
+
+    let chunk_size = settings().transfer.chunk_size(&settings().frame_limits);
+
+    loop {
+        // Buffer comes back to pool after writer sends it, so chunks in flight don't allocate.
+        let mut chunk = CHUNK_POOL.take(chunk_size);
+
+        let Ok(n) = file.read(chunk.fill(file_id, byte_idx, chunk_size)).await else {
+            return Err(report_error(&packets, file_id, "Error reading file!").await);
+        };
+
+        if n == 0 {
+            CHUNK_POOL.give(chunk);
+            break; // End of file
+        }
+
+        chunk.truncate(n);
+        byte_idx += n as FileSize;
+
+        tokio::select! {
+            result = control.wait_for_credit() => result?,
+            _ = packets.closed() => return Err("Upload interrupted!".to_string()),
+        };
+
+        // Throttled before chunk enters writer queue, so chat is never held back by limit.
+        tokio::select! {
+            _ = control.throttle(n) => {}
+            _ = packets.closed() => return Err("Upload interrupted!".to_string()),
+        };
+
+        // Waits while queue is full.
+        if packets.send(FilePacket::Chunk(chunk)).await.is_err() {
+            return Err("Upload interrupted!".to_string());
+        }
+
+        let mut loading_bar_lock = control.loading_bar.lock().unwrap();
+        if let LoadingBar::Status(status) = &mut loading_bar_lock.loadingbar {
+            status.advance(byte_idx, Instant::now());
+            loading_bar_lock.changed = true;
+        }
+    }
+
+    // Writer forgets finished stream, only acks are left.
+    drop(packets);
+    control.wait_for_delivery().await
+}
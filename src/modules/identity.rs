@@ -0,0 +1,113 @@
+use cli_log::*;
+use ed25519_dalek::{SigningKey, VerifyingKey};
+use rand::{distributions::Alphanumeric, rngs::OsRng, Rng};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::Write;
+use std::path::Path;
+
+use crate::config::CONFIG_PATH;
+
+static IDENTITY_FILE: &str = "identity";
+
+/// Long term identity of this user. Stored in config directory and reused between runs.
+pub struct Identity {
+    pub signing_key: SigningKey,
+    pub user_name: String,
+}
+
+// Format of identity file.
+#[derive(Serialize, Deserialize)]
+struct StoredIdentity {
+    secret_key: [u8; 32],
+    user_name: String,
+}
+
+/// Id of user is derived from his public key, so it can't be claimed by anyone else.
+pub fn user_id_from_key(key: &VerifyingKey) -> u64 {
+    let hash = Sha256::digest(key.as_bytes());
+    u64::from_be_bytes(hash[..8].try_into().unwrap()) // This unwrap will never fail.
+}
+
+impl Identity {
+    pub fn user_id(&self) -> u64 {
+        user_id_from_key(&self.signing_key.verifying_key())
+    }
+
+    // Generate completly new identity with random name.
+    pub fn generate() -> Self {
+        let user_name: String = (0..10)
+            .map(|_| OsRng.sample(Alphanumeric) as char)
+            .collect();
+
+        Identity {
+            signing_key: SigningKey::generate(&mut OsRng),
+            user_name,
+        }
+    }
+
+    // Load identity from config directory or create new one if there is none.
+    pub fn load_or_create() -> Self {
+        Self::load_or_create_in(CONFIG_PATH.as_deref())
+    }
+
+    /// Load identity from given directory or create new one if there is none.
+    /// If identity can't be stored, it will be used only for this run.
+    pub fn load_or_create_in(config_path: Option<&Path>) -> Self {
+        let Some(config_path) = config_path else {
+            error!("No config directory available, using temporary identity!");
+            return Self::generate();
+        };
+
+        let identity_path = config_path.join(IDENTITY_FILE);
+
+        match Self::load(&identity_path) {
+            Ok(identity) => identity,
+            Err(e) => {
+                info!("Creating new identity, couldn't load old one: {:?}", e);
+
+                let identity = Self::generate();
+
+                if let Err(e) = identity.store(&identity_path) {
+                    error!(
+                        "Couldn't store identity, it will be lost after exit: {:?}",
+                        e
+                    );
+                }
+
+                identity
+            }
+        }
+    }
+
+    fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let stored: StoredIdentity = bincode::deserialize(&std::fs::read(path)?)?;
+
+        Ok(Identity {
+            signing_key: SigningKey::from_bytes(&stored.secret_key),
+            user_name: stored.user_name,
+        })
+    }
+
+    fn store(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let data = bincode::serialize(&StoredIdentity {
+            secret_key: self.signing_key.to_bytes(),
+            user_name: self.user_name.clone(),
+        })?;
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+
+        // Secret key should be readable only by its owner.
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+        options.open(path)?.write_all(&data)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,167 @@
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::config::{settings, ConnectionLimits};
+
+// Above this number of tracked sources, idle ones are forgotten.
+const MAX_TRACKED_SOURCES: usize = 1024;
+
+// Attempts limits per source address, shared by listener and discovery.
+pub static ATTEMPT_LIMITER: Lazy<Mutex<AttemptLimiter>> =
+    Lazy::new(|| Mutex::new(AttemptLimiter::new(&settings().connection_limits)));
+
+// Permits for handshakes running at the same time.
+pub static HANDSHAKE_SLOTS: Lazy<Arc<Semaphore>> =
+    Lazy::new(|| Arc::new(Semaphore::new(settings().connection_limits.max_handshakes)));
+
+// Number of connected peers, updated by peer list.
+pub static ACTIVE_PEERS: AtomicUsize = AtomicUsize::new(0);
+
+// Connection attempts dropped because of limits, shown in peer list.
+pub static DROPPED_ATTEMPTS: AtomicU64 = AtomicU64::new(0);
+
+struct SourceState {
+    tokens: f64,
+    last_refill: Instant,
+    failures: u32,                  // Failed handshakes in a row.
+    blocked_until: Option<Instant>, // Back-off after failed handshake.
+}
+
+/// Token bucket per source address with exponential back-off after failed handshakes.
+pub struct AttemptLimiter {
+    attempts_per_minute: f64,
+    backoff: Duration,
+    max_backoff: Duration,
+    sources: HashMap<IpAddr, SourceState>,
+}
+
+impl AttemptLimiter {
+    pub fn new(limits: &ConnectionLimits) -> Self {
+        AttemptLimiter {
+            attempts_per_minute: limits.attempts_per_minute as f64,
+            backoff: Duration::from_secs(limits.failure_backoff_secs),
+            max_backoff: Duration::from_secs(limits.max_backoff_secs),
+            sources: HashMap::new(),
+        }
+    }
+
+    fn source(&mut self, addr: IpAddr, now: Instant) -> &mut SourceState {
+        if self.sources.len() >= MAX_TRACKED_SOURCES && !self.sources.contains_key(&addr) {
+            self.forget_idle(now);
+        }
+
+        let capacity = self.attempts_per_minute;
+        self.sources.entry(addr).or_insert(SourceState {
+            tokens: capacity,
+            last_refill: now,
+            failures: 0,
+            blocked_until: None,
+        })
+    }
+
+    /// Takes one attempt from source budget. Returns false if attempt should be dropped.
+    pub fn allow(&mut self, addr: IpAddr, now: Instant) -> bool {
+        let capacity = self.attempts_per_minute;
+        let source = self.source(addr, now);
+
+        if source.blocked_until.is_some_and(|until| now < until) {
+            return false;
+        }
+
+        source.tokens = (source.tokens + refill(capacity, source.last_refill, now)).min(capacity);
+        source.last_refill = now;
+
+        if source.tokens < 1.0 {
+            return false;
+        }
+
+        source.tokens -= 1.0;
+        true
+    }
+
+    /// Each failed handshake in a row doubles time during which source is ignored.
+    pub fn record_failure(&mut self, addr: IpAddr, now: Instant) {
+        let (backoff, max_backoff) = (self.backoff, self.max_backoff);
+        let source = self.source(addr, now);
+
+        source.failures += 1;
+        let delay = backoff
+            .saturating_mul(1 << (source.failures - 1).min(16))
+            .min(max_backoff);
+        source.blocked_until = Some(now + delay);
+    }
+
+    /// Number of sources currently remembered.
+    pub fn tracked_sources(&self) -> usize {
+        self.sources.len()
+    }
+
+    // Forgets sources with full budget, failing ones are kept for max_backoff after back-off ends.
+    // If all of them are still busy, the one that is unblocked first is forgotten.
+    fn forget_idle(&mut self, now: Instant) {
+        let (capacity, max_backoff) = (self.attempts_per_minute, self.max_backoff);
+        self.sources.retain(|_, source| {
+            source
+                .blocked_until
+                .is_some_and(|until| now < until + max_backoff)
+                || source.tokens + refill(capacity, source.last_refill, now) < capacity
+        });
+
+        if self.sources.len() >= MAX_TRACKED_SOURCES {
+            let oldest = self
+                .sources
+                .iter()
+                .min_by_key(|(_, source)| source.blocked_until.unwrap_or(source.last_refill))
+                .map(|(addr, _)| *addr);
+
+            if let Some(addr) = oldest {
+                self.sources.remove(&addr);
+            }
+        }
+    }
+
+    pub fn record_success(&mut self, addr: IpAddr) {
+        if let Some(source) = self.sources.get_mut(&addr) {
+            source.failures = 0;
+            source.blocked_until = None;
+        }
+    }
+}
+
+fn refill(attempts_per_minute: f64, last_refill: Instant, now: Instant) -> f64 {
+    now.saturating_duration_since(last_refill).as_secs_f64() * attempts_per_minute / 60.0
+}
+
+/// Checks all limits before connection attempt from (or to) given address.
+/// Returned permit has to be held until handshake ends.
+pub fn admit(addr: IpAddr) -> Result<OwnedSemaphorePermit, &'static str> {
+    if ACTIVE_PEERS.load(Ordering::Relaxed) >= settings().connection_limits.max_peers {
+        DROPPED_ATTEMPTS.fetch_add(1, Ordering::Relaxed);
+        return Err("too many peers");
+    }
+
+    admit_handshake(addr)
+}
+
+/// Limits of attempts and handshakes without peer limit, for data connections of connected peers.
+pub fn admit_handshake(addr: IpAddr) -> Result<OwnedSemaphorePermit, &'static str> {
+    let result = if !ATTEMPT_LIMITER.lock().unwrap().allow(addr, Instant::now()) {
+        Err("too many attempts from source")
+    } else {
+        HANDSHAKE_SLOTS
+            .clone()
+            .try_acquire_owned()
+            .map_err(|_| "too many handshakes in progress")
+    };
+
+    if result.is_err() {
+        DROPPED_ATTEMPTS.fetch_add(1, Ordering::Relaxed);
+    }
+
+    result
+}
@@ -4,9 +4,10 @@ use ratatui::style::Style;
 use ratatui::text::{Line, Span};
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
 use humansize::{format_size, DECIMAL};
-use unicode_width::UnicodeWidthStr;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
 use crate::modules::protocol::*;
 use crate::modules::widgets::list_component::*;
@@ -17,16 +18,129 @@ pub enum MsgBubbleAllignment {
     Right,
 }
 
+// Speed is recalculated at most this often, so it doesn't jump with every chunk.
+const SPEED_SAMPLE_INTERVAL: Duration = Duration::from_millis(500);
+
+// Weight of the newest sample in smoothed speed.
+const SPEED_SMOOTHING: f64 = 0.3;
+
+// Without progress for this long, speed is unknown.
+const SPEED_TIMEOUT: Duration = Duration::from_secs(3);
+
 #[derive(Debug)]
 pub struct LoadingBarStatus {
     pub position: FileSize,
     pub end: FileSize,
+    pub paused: bool,                 // Paused by us or by peer.
+    start: FileSize,                  // Position when transfer started (or was resumed).
+    speed: Option<f64>,               // Smoothed bytes per second.
+    last_sample: (Instant, FileSize), // Time and position of last speed update.
+}
+
+impl LoadingBarStatus {
+    pub fn new(position: FileSize, end: FileSize) -> Self {
+        Self::new_at(position, end, Instant::now())
+    }
+
+    pub fn new_at(position: FileSize, end: FileSize, now: Instant) -> Self {
+        LoadingBarStatus {
+            position,
+            end,
+            paused: false,
+            start: position,
+            speed: None,
+            last_sample: (now, position),
+        }
+    }
+
+    // If any byte was moved since transfer started.
+    pub fn is_started(&self) -> bool {
+        self.position != self.start
+    }
+
+    // Moves bar forward and updates smoothed speed.
+    pub fn advance(&mut self, position: FileSize, now: Instant) {
+        self.position = position;
+
+        let (sample_time, sample_position) = self.last_sample;
+        let elapsed = now.saturating_duration_since(sample_time);
+
+        if elapsed < SPEED_SAMPLE_INTERVAL {
+            return;
+        }
+
+        let current = position.saturating_sub(sample_position) as f64 / elapsed.as_secs_f64();
+
+        // Time spent paused or stalled doesn't count as slow transfer.
+        self.speed = match self.speed {
+            Some(speed) if elapsed < SPEED_TIMEOUT => {
+                Some(SPEED_SMOOTHING * current + (1.0 - SPEED_SMOOTHING) * speed)
+            }
+            _ => Some(current),
+        };
+        self.last_sample = (now, position);
+    }
+
+    // Smoothed speed in bytes per second, None if transfer stalled or just started.
+    pub fn speed(&self, now: Instant) -> Option<f64> {
+        if self.paused || now.saturating_duration_since(self.last_sample.0) > SPEED_TIMEOUT {
+            return None;
+        }
+
+        self.speed.filter(|speed| *speed > 0.0)
+    }
+
+    pub fn eta(&self, now: Instant) -> Option<Duration> {
+        let speed = self.speed(now)?;
+
+        Some(Duration::from_secs_f64(
+            self.end.saturating_sub(self.position) as f64 / speed,
+        ))
+    }
+}
+
+// Short human readable duration, e.g. "1h 5m" or "42s".
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+
+    match secs {
+        0..60 => format!("{}s", secs),
+        60..3600 => format!("{}m {}s", secs / 60, secs % 60),
+        _ => format!("{}h {}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+// Cuts text to given display width, marking cut with "…".
+fn truncate_to_width(text: &str, width: usize) -> String {
+    if UnicodeWidthStr::width(text) <= width {
+        return text.to_string();
+    }
+
+    let mut truncated = String::new();
+    let mut truncated_width = 0;
+
+    for c in text.chars() {
+        let char_width = c.width().unwrap_or(0);
+        if truncated_width + char_width + 1 > width {
+            break;
+        }
+        truncated.push(c);
+        truncated_width += char_width;
+    }
+
+    if width > 0 {
+        truncated.push('…');
+    }
+
+    truncated
 }
 
 // Loading bar is currently used to show progress of file download.
 #[derive(Debug)]
 pub enum LoadingBar {
     Status(LoadingBarStatus),
+    Verified(FileHash), // Download finished and content matches hash from header.
+    Delivered,          // Upload finished and peer wrote all chunks.
     Error(String),
 }
 
@@ -41,8 +155,8 @@ pub fn is_loading_bar_free(ld: &Option<Arc<Mutex<LoadingBarWrap>>>) -> bool {
     match &ld {
         None => true,
         Some(lock) => match &lock.lock().unwrap().loadingbar {
-            LoadingBar::Status(LoadingBarStatus { position, end }) => position == end,
-            LoadingBar::Error(_) => true,
+            LoadingBar::Status(LoadingBarStatus { position, end, .. }) => position == end,
+            LoadingBar::Verified(_) | LoadingBar::Delivered | LoadingBar::Error(_) => true,
         },
     }
 }
@@ -52,7 +166,9 @@ pub fn is_loading_bar_free(ld: &Option<Arc<Mutex<LoadingBarWrap>>>) -> bool {
 pub struct MsgBubble<'a> {
     pub received_from: Option<String>,
     pub message: UserMessage,
-    pub loading_bar: Option<Arc<Mutex<LoadingBarWrap>>>, // Used for file downloading.
+    pub loading_bar: Option<Arc<Mutex<LoadingBarWrap>>>, // Used for file downloading (or uploading).
+    pub download_count: usize,                           // How many times peer requested our file.
+    pub error: Option<String>,                           // Why our msg wasn't sent.
     allignment: MsgBubbleAllignment,
     render_cache: Option<ListCache<'a>>,
 }
@@ -67,6 +183,8 @@ impl MsgBubble<'_> {
             received_from,
             message,
             loading_bar: None,
+            download_count: 0,
+            error: None,
             allignment,
             render_cache: None,
         }
@@ -109,14 +227,24 @@ impl<'a> ListItem<'a> for MsgBubble<'a> {
         // Total length of bubble insides (inside "│ " " │"). Will be only increased.
         let mut bubble_inner_width = (name_length.max(2) - 2).min(window_max_width - 4);
 
+        // Content is padded to width of error line below it.
+        if let Some(error) = &self.error {
+            Self::widen_for_error(error, window_max_width - 4, &mut bubble_inner_width);
+        }
+
         let mut middle_lines: Vec<Vec<Span<'a>>> = Self::formatted_content(
             &self.message,
             &self.loading_bar,
+            self.download_count,
             style,
             window_max_width - 4,
             &mut bubble_inner_width,
         );
 
+        if let Some(error) = &self.error {
+            middle_lines.push(Self::error_line(error, style, bubble_inner_width));
+        }
+
         // +/- 2/4 to bubble_width are related to adding "│ " " │"
         let left_padding_len = match self.allignment {
             MsgBubbleAllignment::Left => 0,
@@ -178,10 +306,28 @@ impl<'a> ListItem<'a> for MsgBubble<'a> {
 }
 
 impl<'a> MsgBubble<'a> {
+    fn widen_for_error(err: &str, window_max_width: u16, bubble_inner_width: &mut u16) {
+        let err_len = UnicodeWidthStr::width(err) as u16;
+
+        *bubble_inner_width = (*bubble_inner_width).max(err_len + 5).min(window_max_width);
+    }
+
+    fn error_line(err: &str, parent_style: Style, bubble_inner_width: u16) -> Vec<Span<'a>> {
+        vec![Span::styled(
+            format!(
+                "ERR: {: <width$}",
+                err,
+                width = (bubble_inner_width as usize).saturating_sub(5)
+            ),
+            parent_style.fg(Color::Red),
+        )]
+    }
+
     // Calculate inside of bubble based on content.
     fn formatted_content(
         message: &UserMessage,
         loading_bar: &Option<Arc<Mutex<LoadingBarWrap>>>,
+        download_count: usize,
         parent_style: Style,
         window_max_width: u16,
         bubble_inner_width: &mut u16,
@@ -221,89 +367,200 @@ impl<'a> MsgBubble<'a> {
                     })
                     .collect()
             }
-            UserMessage::FileHeader(file_name, size, _id) => {
-                let file_size: String = format_size(*size, DECIMAL);
-                let file_size_len = UnicodeWidthStr::width(file_size.as_str()) as u16;
-
-                let top_line =
-                    "┌──────┬─".to_string() + &"─".repeat(file_size_len as usize) + "─┐ ";
-                let mid_line = "│ FILE │ ".to_string() + &file_size + " │ ";
-                let bot_line =
-                    "└──────┴─".to_string() + &"─".repeat(file_size_len as usize) + "─┘ ";
-
-                let file_box_style = parent_style.add_modifier(Modifier::BOLD);
-
-                let mut styled_lines: Vec<Vec<Span<'a>>> = vec![
-                    vec![Span::styled(top_line, file_box_style)],
-                    vec![Span::styled(mid_line, file_box_style)],
-                    vec![Span::styled(bot_line, file_box_style)],
-                ];
-
-                let file_header_len = 12 + file_size_len;
-
-                let name_len = UnicodeWidthStr::width(file_name.as_str()) as u16;
-
-                // We will later adjust it to window_max_width. If window_max_width is small enough, then this bubble can go out of window.
-                *bubble_inner_width = (*bubble_inner_width).max(12 + file_size_len + name_len);
-
-                // Calculate loading bar string based on progress.
-                if let Some(loading_bar) = &loading_bar {
-                    let locked_loading_bar = &loading_bar.lock().unwrap().loadingbar;
-
-                    match &locked_loading_bar {
-                        LoadingBar::Status(loading_bar_status) => {
-                            let procentage =
-                                (loading_bar_status.position * 100) / loading_bar_status.end;
-                            let filled_len = ((*bubble_inner_width - 5) * procentage as u16) / 100;
-
-                            let bar_style = parent_style.fg(Color::Green);
-
-                            styled_lines.push(vec![
-                                Span::styled(format!("{:3}% ", procentage), parent_style),
-                                Span::styled("═".repeat(filled_len as usize), bar_style),
-                                Span::styled(
-                                    "─".repeat((*bubble_inner_width - 5 - filled_len) as usize),
-                                    bar_style,
-                                ),
-                            ]);
-                        }
-                        LoadingBar::Error(err) => {
-                            let err_style = parent_style.fg(Color::Red);
-                            let err_len = UnicodeWidthStr::width(err.as_str()) as u16;
-
-                            *bubble_inner_width =
-                                (*bubble_inner_width).max(err_len + 5).min(window_max_width);
-
-                            styled_lines.push(vec![Span::styled(
-                                format!(
-                                    "ERR: {: <width$}",
-                                    err,
-                                    width = *bubble_inner_width as usize - 5
-                                ),
-                                err_style,
-                            )]);
-                        }
+            UserMessage::FileHeader(file_name, size, _id, _hash) => Self::formatted_file_box(
+                "FILE",
+                file_name.clone(),
+                *size,
+                loading_bar,
+                download_count,
+                parent_style,
+                window_max_width,
+                bubble_inner_width,
+            ),
+            UserMessage::DirectoryHeader(tree) => Self::formatted_file_box(
+                "DIR",
+                format!("{}/ ({} files)", tree.name, tree.files.len()),
+                tree.total_size(),
+                loading_bar,
+                download_count,
+                parent_style,
+                window_max_width,
+                bubble_inner_width,
+            ),
+        }
+    }
+
+    // Box with file (or directory) size and name, followed by loading bar.
+    #[allow(clippy::too_many_arguments)]
+    fn formatted_file_box(
+        label: &str,
+        file_name: String,
+        size: FileSize,
+        loading_bar: &Option<Arc<Mutex<LoadingBarWrap>>>,
+        download_count: usize,
+        parent_style: Style,
+        window_max_width: u16,
+        bubble_inner_width: &mut u16,
+    ) -> Vec<Vec<Span<'a>>> {
+        let file_size: String = format_size(size, DECIMAL);
+        let file_size_len = UnicodeWidthStr::width(file_size.as_str()) as u16;
+
+        let top_line = "┌──────┬─".to_string() + &"─".repeat(file_size_len as usize) + "─┐ ";
+        let mid_line = format!("│ {:<4} │ ", label) + &file_size + " │ ";
+        let bot_line = "└──────┴─".to_string() + &"─".repeat(file_size_len as usize) + "─┘ ";
+
+        let file_box_style = parent_style.add_modifier(Modifier::BOLD);
+
+        let mut styled_lines: Vec<Vec<Span<'a>>> = vec![
+            vec![Span::styled(top_line, file_box_style)],
+            vec![Span::styled(mid_line, file_box_style)],
+            vec![Span::styled(bot_line, file_box_style)],
+        ];
+
+        let file_header_len = 12 + file_size_len;
+
+        let name_len = UnicodeWidthStr::width(file_name.as_str()) as u16;
+
+        // We will later adjust it to window_max_width. If window_max_width is small enough, then this bubble can go out of window.
+        *bubble_inner_width = (*bubble_inner_width).max(12 + file_size_len + name_len);
+
+        // Bar shows only the latest download, so number of them is written above.
+        let download_info =
+            (download_count > 1).then(|| format!("{} downloads by peer", download_count));
+
+        if let Some(info) = &download_info {
+            *bubble_inner_width = (*bubble_inner_width)
+                .max(UnicodeWidthStr::width(info.as_str()) as u16)
+                .min(window_max_width);
+        }
+
+        // Calculate loading bar string based on progress.
+        if let Some(loading_bar) = &loading_bar {
+            let locked_loading_bar = &loading_bar.lock().unwrap().loadingbar;
+
+            match &locked_loading_bar {
+                LoadingBar::Status(loading_bar_status) => {
+                    let now = Instant::now();
+                    let bytes_info = format!(
+                        "{} / {}",
+                        format_size(loading_bar_status.position, DECIMAL),
+                        format_size(loading_bar_status.end, DECIMAL)
+                    );
+                    let mut progress_info = bytes_info.clone();
+
+                    if let Some(speed) = loading_bar_status.speed(now) {
+                        progress_info += &format!(", {}/s", format_size(speed as u64, DECIMAL));
+                    }
+                    if let Some(eta) = loading_bar_status.eta(now) {
+                        progress_info += &format!(", {} left", format_duration(eta));
                     }
-                }
 
-                styled_lines[0].push(Span::styled(
-                    " ".repeat((*bubble_inner_width - file_header_len) as usize),
-                    parent_style,
-                ));
-                styled_lines[1].push(Span::styled(
-                    file_name.clone()
-                        + &" ".repeat((*bubble_inner_width - file_header_len - name_len) as usize),
-                    parent_style,
-                ));
-                styled_lines[2].push(Span::styled(
-                    " ".repeat((*bubble_inner_width - file_header_len) as usize),
-                    parent_style,
-                ));
+                    *bubble_inner_width = (*bubble_inner_width)
+                        .max(UnicodeWidthStr::width(progress_info.as_str()) as u16)
+                        .min(window_max_width);
 
-                *bubble_inner_width = (*bubble_inner_width).min(window_max_width);
+                    // On narrow window speed is skipped rather than wrapped.
+                    if UnicodeWidthStr::width(progress_info.as_str()) > *bubble_inner_width as usize
+                    {
+                        progress_info =
+                            truncate_to_width(&bytes_info, *bubble_inner_width as usize);
+                    }
 
-                styled_lines
+                    let procentage = (loading_bar_status.position * 100)
+                        .checked_div(loading_bar_status.end)
+                        .unwrap_or(100); // Empty file is done from the start.
+                    let (label, bar_style) = match loading_bar_status.paused {
+                        false => (format!("{:3}% ", procentage), parent_style.fg(Color::Green)),
+                        true => (
+                            format!("{:3}% paused ", procentage),
+                            parent_style.fg(Color::Yellow),
+                        ),
+                    };
+                    let label = truncate_to_width(&label, *bubble_inner_width as usize);
+                    let bar_len = bubble_inner_width
+                        .saturating_sub(UnicodeWidthStr::width(label.as_str()) as u16);
+                    let filled_len = (bar_len * procentage as u16) / 100;
+
+                    styled_lines.push(vec![
+                        Span::styled(label, parent_style),
+                        Span::styled("═".repeat(filled_len as usize), bar_style),
+                        Span::styled("─".repeat((bar_len - filled_len) as usize), bar_style),
+                    ]);
+                    styled_lines.push(vec![Span::styled(
+                        format!(
+                            "{: <width$}",
+                            progress_info,
+                            width = *bubble_inner_width as usize
+                        ),
+                        parent_style,
+                    )]);
+                }
+                LoadingBar::Verified(hash) => {
+                    let ok_style = parent_style.fg(Color::Green);
+                    let hash_prefix: String =
+                        hash[..8].iter().map(|b| format!("{:02x}", b)).collect();
+                    let ok_msg = format!("Verified, sha256 {}…", hash_prefix);
+                    let ok_len = UnicodeWidthStr::width(ok_msg.as_str()) as u16;
+
+                    *bubble_inner_width =
+                        (*bubble_inner_width).max(ok_len + 4).min(window_max_width);
+
+                    styled_lines.push(vec![Span::styled(
+                        format!(
+                            "OK: {: <width$}",
+                            ok_msg,
+                            width = (*bubble_inner_width as usize).saturating_sub(4)
+                        ),
+                        ok_style,
+                    )]);
+                }
+                LoadingBar::Delivered => {
+                    let ok_msg = "Delivered to peer";
+
+                    *bubble_inner_width = (*bubble_inner_width)
+                        .max(UnicodeWidthStr::width(ok_msg) as u16 + 4)
+                        .min(window_max_width);
+
+                    styled_lines.push(vec![Span::styled(
+                        format!(
+                            "OK: {: <width$}",
+                            ok_msg,
+                            width = (*bubble_inner_width as usize).saturating_sub(4)
+                        ),
+                        parent_style.fg(Color::Green),
+                    )]);
+                }
+                LoadingBar::Error(err) => {
+                    Self::widen_for_error(err, window_max_width, bubble_inner_width);
+                    styled_lines.push(Self::error_line(err, parent_style, *bubble_inner_width));
+                }
             }
         }
+
+        if let Some(info) = download_info {
+            styled_lines.insert(
+                3,
+                vec![Span::styled(
+                    format!("{: <width$}", info, width = *bubble_inner_width as usize),
+                    parent_style,
+                )],
+            );
+        }
+
+        // Width could be clamped to window above, then name (chosen by peer) is cut to fit.
+        let name_width = bubble_inner_width.saturating_sub(file_header_len);
+        let file_name = truncate_to_width(&file_name, name_width as usize);
+        let name_len = UnicodeWidthStr::width(file_name.as_str()) as u16;
+
+        styled_lines[0].push(Span::styled(" ".repeat(name_width as usize), parent_style));
+        styled_lines[1].push(Span::styled(
+            file_name + &" ".repeat(name_width.saturating_sub(name_len) as usize),
+            parent_style,
+        ));
+        styled_lines[2].push(Span::styled(" ".repeat(name_width as usize), parent_style));
+
+        *bubble_inner_width = (*bubble_inner_width).min(window_max_width);
+
+        styled_lines
     }
 }
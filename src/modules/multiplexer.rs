@@ -0,0 +1,67 @@
+use std::collections::VecDeque;
+use std::future::poll_fn;
+use std::task::{Context, Poll};
+use tokio::sync::mpsc;
+
+/// Bulk data streams (one per running upload) sharing one connection.
+/// Streams are served in round robin, so concurrent transfers get equal share of bandwidth.
+pub struct BulkStreams<T> {
+    streams: VecDeque<mpsc::Receiver<T>>,
+}
+
+impl<T> Default for BulkStreams<T> {
+    fn default() -> Self {
+        BulkStreams {
+            streams: VecDeque::new(),
+        }
+    }
+}
+
+impl<T> BulkStreams<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, stream: mpsc::Receiver<T>) {
+        self.streams.push_back(stream);
+    }
+
+    pub fn len(&self) -> usize {
+        self.streams.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.streams.is_empty()
+    }
+
+    /// Takes message from the first stream that has one, starting after the last served stream.
+    /// Finished streams are removed. Pending while no stream has data.
+    pub fn poll_next(&mut self, cx: &mut Context<'_>) -> Poll<T> {
+        let mut checked = 0;
+
+        while checked < self.streams.len() {
+            // Front stream is the next one in order, rotation keeps the order fair.
+            let Some(mut stream) = self.streams.pop_front() else {
+                break;
+            };
+
+            match stream.poll_recv(cx) {
+                Poll::Ready(Some(message)) => {
+                    self.streams.push_back(stream);
+                    return Poll::Ready(message);
+                }
+                Poll::Ready(None) => {} // Upload ended, stream is dropped.
+                Poll::Pending => {
+                    self.streams.push_back(stream);
+                    checked += 1;
+                }
+            }
+        }
+
+        Poll::Pending
+    }
+
+    pub async fn next(&mut self) -> T {
+        poll_fn(|cx| self.poll_next(cx)).await
+    }
+}
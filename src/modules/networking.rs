@@ -2,67 +2,134 @@ use cli_log::*;
 use std::net::{Ipv4Addr, SocketAddr};
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Instant;
 use tokio::net::{TcpListener, TcpStream, UdpSocket};
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, Notify, OwnedSemaphorePermit};
 use tokio::time;
 use tokio::time::Duration;
 
 use socket2::{Domain, Protocol, Socket, Type};
 
-use crate::config::{MULTICAST_IP, MULTICAST_PORT, USER_ID, USER_NAME};
+use crate::config::{IDENTITY, MULTICAST_IP, MULTICAST_PORT, ROOM_KEY, USER_ID, USER_NAME};
 
+use super::access_list::ACCESS_LIST;
+use super::encryption::SecureStream;
+use super::identity::user_id_from_key;
+use super::limits::*;
 use super::protocol::*;
+use super::trust::REJECTED_PEERS;
+
+// Woken when presence should be announced again, e.g. after unblocking a peer.
+static ANNOUNCE: Notify = Notify::const_new();
 
 pub struct ConnectionData {
-    pub stream: TcpStream,
+    pub stream: SecureStream,
     pub peer_address: SocketAddr,
-    // pub peer_id: u64,
+    pub peer_id: u64,
+    pub peer_key: [u8; 32], // Identity key verified during handshake.
     pub peer_name: String,
+    pub initiator: u64, // User that opened connection, decides which of duplicate connections is kept.
 }
 
-/// Converts unread TcpStream into ConnectionData.
-async fn establish_connection(
-    mut stream: TcpStream,
+/// Converts unread TcpStream into ConnectionData, `initiated` tells if we opened the connection.
+/// Performs encryption handshake first, so ConnectionInfo is never sent in plain text.
+pub async fn establish_connection(
+    stream: TcpStream,
     addr: SocketAddr,
-    conn_queue: mpsc::UnboundedSender<ConnectionData>,
-) {
+    initiated: bool,
+) -> Result<ConnectionData, StreamSerializerError> {
+    let (mut stream, peer_identity) = time::timeout(
+        Duration::from_secs(2),
+        SecureStream::handshake(stream, ROOM_KEY.as_ref()),
+    )
+    .await
+    .map_err(|_| "Timed out during handshake!")??;
+
+    info!("Established encrypted connection with {}", addr);
+
     // Send our initial msg.
-    match time::timeout(
+    time::timeout(
         Duration::from_secs(2),
-        (ConnectionInfo {
+        stream.send(&ConnectionInfo {
             user_name: (*USER_NAME).clone(),
-        })
-        .send(&mut stream),
+            user_id: *USER_ID,
+            identity_key: IDENTITY.signing_key.verifying_key().to_bytes(),
+        }),
     )
     .await
-    {
-        Ok(Err(e)) => {
-            info!("Couldn't establish connection: {:?}", e);
-            return;
-        }
-        Err(e) => {
-            error!("Couldn't establish connection: {:?}", e);
-            return;
-        }
-        _ => (),
-    }
+    .map_err(|_| "Timed out while sending connection info!")??;
 
     info!("Sent connection info to {}", addr);
 
     // Wait for incoming initial msg.
-    match time::timeout(Duration::from_secs(2), ConnectionInfo::read(&mut stream)).await {
-        Ok(Ok(info)) => {
-            let _ = conn_queue.send(ConnectionData {
-                stream,
-                peer_address: addr,
-                peer_name: info.user_name,
-            });
+    let info: ConnectionInfo = time::timeout(Duration::from_secs(2), stream.read())
+        .await
+        .map_err(|_| "Timed out while waiting for connection info!")??;
+
+    // Peer can't claim identity diffrent from the one proven in handshake.
+    if info.identity_key != peer_identity.to_bytes()
+        || info.user_id != user_id_from_key(&peer_identity)
+    {
+        return Err("Connection info doesn't match handshake identity!".into());
+    }
+
+    Ok(ConnectionData {
+        stream,
+        peer_address: addr,
+        peer_id: info.user_id,
+        peer_key: info.identity_key,
+        peer_name: info.user_name,
+        initiator: match initiated {
+            true => *USER_ID,
+            false => info.user_id,
+        },
+    })
+}
+
+/// When both sides connect at once, each gets two connections with the same peer.
+/// Both keep the one opened by user with lower id, so they don't close diffrent ones.
+pub fn prefers_new_connection(old_initiator: u64, new_initiator: u64) -> bool {
+    new_initiator < old_initiator
+}
+
+// Rejected or blocked peers are never connected.
+// Peers outside of allowlist are, but they stay pending until user allowlists them.
+pub fn is_ignored(peer_id: u64, addr: SocketAddr) -> bool {
+    REJECTED_PEERS.lock().unwrap().contains(&peer_id)
+        || !ACCESS_LIST
+            .lock()
+            .unwrap()
+            .is_connectable(peer_id, addr.ip())
+}
+
+// Establishes connection in the background and passes it to the queue.
+// Handshake slot is released when this function ends.
+async fn accept_connection(
+    stream: TcpStream,
+    addr: SocketAddr,
+    conn_queue: mpsc::UnboundedSender<ConnectionData>,
+    _permit: OwnedSemaphorePermit,
+    initiated: bool,
+) {
+    let result = establish_connection(stream, addr, initiated).await;
+
+    match &result {
+        Ok(_) => ATTEMPT_LIMITER.lock().unwrap().record_success(addr.ip()),
+        Err(_) => ATTEMPT_LIMITER
+            .lock()
+            .unwrap()
+            .record_failure(addr.ip(), Instant::now()),
+    }
+
+    match result {
+        Ok(connection_data) if is_ignored(connection_data.peer_id, addr) => {
+            info!("Closing connection with ignored peer {}", addr);
         }
-        Ok(Err(e)) => {
-            error!("Couldn't establish connection: {:?}", e);
+        Ok(connection_data) => {
+            let _ = conn_queue.send(connection_data);
         }
         Err(e) => {
-            error!("Timed out during connection establishment: {:?}", e);
+            error!("Couldn't establish connection with {}: {:?}", addr, e);
         }
     }
 }
@@ -79,22 +146,52 @@ pub async fn search_for_users(
 
     trace!("Accepting tcp connections on  port {}", used_port);
 
+    send_invitation(&socket, used_port).await?;
+
+    // TODO: handle their JoinHandles.
+    tokio::task::spawn(socket_listener(listener, connection_queue.clone()));
+    tokio::task::spawn(announcer(socket.clone(), used_port));
+    tokio::task::spawn(detect_new_users(socket, connection_queue.clone()));
+    Ok(())
+}
+
+/// Announces our presence on MULTICAST again, peers that aren't connected will connect to us.
+pub fn announce() {
+    ANNOUNCE.notify_one();
+}
+
+// Every invitation has fresh timestamp and nonce, so it isn't taken for a replay.
+async fn send_invitation(
+    socket: &(UdpSocket, SocketAddr),
+    port: u16,
+) -> Result<(), StreamSerializerError> {
     let invitation_packet = UserDiscovery {
         user_id: *USER_ID,
-        port: used_port,
+        identity_key: IDENTITY.signing_key.verifying_key().to_bytes(),
+        port,
+        timestamp: unix_time(),
+        nonce: rand::random(),
+        private_room: ROOM_KEY.is_some(),
     }
-    .to_packet()?;
+    .to_packet(&IDENTITY.signing_key)?;
 
-    trace!("Sending invite on MULTICAST for port {}!", used_port);
+    trace!("Sending invite on MULTICAST for port {}!", port);
 
     socket.0.send_to(&invitation_packet, socket.1).await?;
-
-    // TODO: handle their JoinHandles.
-    tokio::task::spawn(socket_listener(listener, connection_queue.clone()));
-    tokio::task::spawn(detect_new_users(socket, connection_queue.clone()));
     Ok(())
 }
 
+// Sends invitation every time announce is called.
+async fn announcer(socket: Arc<(UdpSocket, SocketAddr)>, port: u16) {
+    loop {
+        ANNOUNCE.notified().await;
+
+        if let Err(e) = send_invitation(&socket, port).await {
+            error!("Couldn't announce presence: {:?}", e);
+        }
+    }
+}
+
 /// Detects new tcp connections on port indefinitly and annouces user presence on MULTICAST.
 async fn socket_listener(
     listener: TcpListener,
@@ -102,10 +199,24 @@ async fn socket_listener(
 ) -> Result<(), std::io::Error> {
     loop {
         match listener.accept().await {
-            Ok((socket, addr)) => {
-                info!("Accepted new tcp connection from {}", addr);
-                tokio::task::spawn(establish_connection(socket, addr, connection_queue.clone()));
+            Ok((_, addr)) if !ACCESS_LIST.lock().unwrap().is_address_allowed(addr.ip()) => {
+                info!("Dropping tcp connection from blocked address {}", addr);
             }
+            Ok((socket, addr)) => match admit(addr.ip()) {
+                Ok(permit) => {
+                    info!("Accepted new tcp connection from {}", addr);
+                    tokio::task::spawn(accept_connection(
+                        socket,
+                        addr,
+                        connection_queue.clone(),
+                        permit,
+                        false,
+                    ));
+                }
+                Err(reason) => {
+                    warn!("Dropping tcp connection from {}: {}", addr, reason);
+                }
+            },
             Err(e) => {
                 return Err(e);
             }
@@ -118,41 +229,75 @@ async fn detect_new_users(
     socket: Arc<(UdpSocket, SocketAddr)>,
     connection_queue: mpsc::UnboundedSender<ConnectionData>,
 ) -> Result<(), std::io::Error> {
+    let mut replay_guard = DiscoveryReplayGuard::new();
+
     loop {
         let mut buf = vec![0; 4096];
         let (len, mut addr) = socket.0.recv_from(&mut buf).await?;
         info!("Received some bytes on MULTICAST!");
 
-        match UserDiscovery::from_packet(buf[0..len].to_vec()) {
+        // Checked before signature, so flood of packets can't keep us busy verifying them.
+        let permit = match admit(addr.ip()) {
+            Ok(permit) => permit,
+            Err(reason) => {
+                warn!("Dropping discovery packet from {}: {}", addr, reason);
+                continue;
+            }
+        };
+
+        match UserDiscovery::from_packet(buf[0..len].to_vec(), &mut replay_guard) {
             Ok(disc) => {
                 if disc.user_id == *USER_ID {
                     continue;
                 }
 
+                if is_ignored(disc.user_id, addr) {
+                    info!("Ignoring discovery of user {:016x}", disc.user_id);
+                    continue;
+                }
+
+                // Users outside of any room are not even contacted.
+                // Users from other rooms fail the handshake, since they don't know our passphrase.
+                if !disc.may_be_in_room(ROOM_KEY.is_some()) {
+                    info!("Ignoring user {:016x} from diffrent room", disc.user_id);
+                    continue;
+                }
+
                 addr.set_port(disc.port); // update addr to point to tcp socket.
                 info!("Multicast Userdiscovery packet received from: {:?}", addr);
 
-                match TcpStream::connect(addr).await {
-                    Ok(stream) => {
-                        info!("connected ot tcp {}", addr);
-                        tokio::task::spawn(establish_connection(
-                            stream,
-                            addr,
-                            connection_queue.clone(),
-                        ));
-                    }
-                    Err(e) => {
-                        error!("Couldnt connect to addr posted via MULTICAST {e}!");
-                    }
-                }
+                tokio::task::spawn(connect_to_user(addr, connection_queue.clone(), permit));
             }
             Err(e) => {
-                error!("UserDiscovery parsing error: {:?}!", e);
+                warn!("Dropping discovery packet from {}: {:?}!", addr, e);
             }
         }
     }
 }
 
+// Connects to user posted via MULTICAST, without holding up discovery of others.
+async fn connect_to_user(
+    addr: SocketAddr,
+    conn_queue: mpsc::UnboundedSender<ConnectionData>,
+    permit: OwnedSemaphorePermit,
+) {
+    match time::timeout(Duration::from_secs(2), TcpStream::connect(addr)).await {
+        Ok(Ok(stream)) => {
+            info!("connected ot tcp {}", addr);
+            accept_connection(stream, addr, conn_queue, permit, true).await;
+        }
+        Ok(Err(e)) => {
+            error!("Couldnt connect to addr posted via MULTICAST {e}!");
+        }
+        Err(_) => {
+            error!(
+                "Timed out while connecting to addr posted via MULTICAST {}!",
+                addr
+            );
+        }
+    }
+}
+
 pub async fn get_multicast_socket(
     mc_ip: &str,
     mc_port: u16,
@@ -0,0 +1,131 @@
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::config::settings;
+use crate::modules::protocol::*;
+use crate::modules::storage::*;
+
+static OFFERS_FILE: &str = "offers";
+
+// Files offered to peers, shared by all connections and kept across restarts.
+pub static OFFERS: Lazy<Mutex<OfferStore>> =
+    Lazy::new(|| Mutex::new(OfferStore::open(state_path(OFFERS_FILE))));
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Offer {
+    pub path: PathBuf,
+    pub peers: HashSet<u64>,     // Peers allowed to download the file.
+    pub expires_at: Option<u64>, // Unix time, None if offer never expires.
+    pub revoked: HashSet<u64>,   // Peers that can't download the file anymore.
+}
+
+impl Offer {
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+
+    // Broadcasted file is revoked separately for every peer.
+    pub fn is_revoked(&self, peer_id: u64) -> bool {
+        self.revoked.contains(&peer_id)
+    }
+
+    fn is_revoked_for_all(&self) -> bool {
+        self.peers.is_subset(&self.revoked)
+    }
+}
+
+/// Persistent store of offered files, keyed by id sent to peers in file headers.
+pub struct OfferStore {
+    offers: HashMap<FileID, Offer>,
+    path: Option<PathBuf>, // None if store lives only in memory.
+}
+
+impl OfferStore {
+    // Offers that ended before start are forgotten.
+    pub fn open(path: Option<PathBuf>) -> Self {
+        let mut offers: HashMap<FileID, Offer> =
+            path.as_deref().and_then(load_state).unwrap_or_default();
+
+        let now = unix_time();
+        offers.retain(|_, offer| !offer.is_revoked_for_all() && !offer.is_expired(now));
+
+        OfferStore { offers, path }
+    }
+
+    fn store(&self) {
+        if let Some(path) = &self.path {
+            store_state(path, &self.offers);
+        }
+    }
+
+    /// Offers file to given peers, expiry is taken from settings.
+    pub fn add(&mut self, file_id: FileID, path: PathBuf, peers: &[u64], now: u64) {
+        self.add_many([(file_id, path)], peers, now);
+    }
+
+    // All files of shared directory are written at once.
+    pub fn add_many(
+        &mut self,
+        files: impl IntoIterator<Item = (FileID, PathBuf)>,
+        peers: &[u64],
+        now: u64,
+    ) {
+        let expires_at = settings()
+            .transfer
+            .offer_expiry()
+            .map(|expiry| now + expiry);
+
+        for (file_id, path) in files {
+            self.offers.insert(
+                file_id,
+                Offer {
+                    path,
+                    peers: peers.iter().copied().collect(),
+                    expires_at,
+                    revoked: HashSet::new(),
+                },
+            );
+        }
+        self.store();
+    }
+
+    pub fn get(&self, file_id: FileID) -> Option<&Offer> {
+        self.offers.get(&file_id)
+    }
+
+    /// Path of file offered to peer, or reason why it can't be downloaded.
+    pub fn lookup(&self, peer_id: u64, file_id: FileID, now: u64) -> Result<&Path, String> {
+        match self.offers.get(&file_id) {
+            Some(offer) if !offer.peers.contains(&peer_id) => {
+                Err("File was not offered to you!".to_string())
+            }
+            Some(offer) if offer.is_revoked(peer_id) => Err("File offer was revoked!".to_string()),
+            Some(offer) if offer.is_expired(now) => Err("File offer expired!".to_string()),
+            Some(offer) => Ok(&offer.path),
+            None => Err("File is not offered anymore!".to_string()),
+        }
+    }
+
+    // Why file can't be downloaded by peer anymore, None while offer is valid.
+    pub fn end_reason(&self, peer_id: u64, file_id: FileID, now: u64) -> Option<&'static str> {
+        match self.offers.get(&file_id) {
+            Some(offer) if offer.is_revoked(peer_id) => Some("Offer revoked"),
+            Some(offer) if offer.is_expired(now) => Some("Offer expired"),
+            Some(_) => None,
+            None => Some("Not offered anymore"),
+        }
+    }
+
+    // Peer can't start new downloads of revoked files, other peers they were offered to still can.
+    pub fn revoke(&mut self, peer_id: u64, file_ids: &[FileID]) {
+        for file_id in file_ids {
+            if let Some(offer) = self.offers.get_mut(file_id) {
+                offer.revoked.insert(peer_id);
+            }
+        }
+        self.store();
+    }
+}
@@ -0,0 +1,577 @@
+use cli_log::*;
+use std::io::SeekFrom;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, OwnedSemaphorePermit, Semaphore};
+use tokio::task::JoinSet;
+use tokio::time::{self, Duration};
+
+use crate::config::*;
+use crate::modules::access_list::ACCESS_LIST;
+use crate::modules::encryption::{ChunkBuffer, SecureStream, CHUNK_POOL};
+use crate::modules::file_transfer::*;
+use crate::modules::identity::user_id_from_key;
+use crate::modules::limits::{admit_handshake, ATTEMPT_LIMITER};
+use crate::modules::message_bubble::*;
+use crate::modules::networking::is_ignored;
+use crate::modules::protocol::*;
+use crate::modules::throttle::Throttle;
+
+// Without any data connection in this time, file is downloaded over chat connection.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Handshake and hello on data connection.
+const HELLO_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Without any received byte in this time (while not paused), download fails and can be resumed.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Bytes of file in one frame on data connection.
+/// Receiver rejects bigger frames, so it's the same for every peer.
+pub const DATA_CHUNK_SIZE: usize = 256 * 1024;
+
+// How often loading bar of download is refreshed.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+// Ranges sent at the same time by all uploads, each holds a connection, file and chunk buffer.
+static SENDER_SLOTS: Semaphore = Semaphore::const_new(MAX_CONNECTIONS);
+
+/// Splits file into given number of ranges (start, end) of nearly the same size.
+pub fn split_ranges(file_size: FileSize, parts: usize) -> Vec<(FileSize, FileSize)> {
+    let parts = (parts as FileSize).clamp(1, file_size.max(1));
+    let part_size = file_size / parts;
+
+    (0..parts)
+        .map(|idx| {
+            let start = idx * part_size;
+            let end = match idx + 1 == parts {
+                true => file_size,
+                false => start + part_size,
+            };
+            (start, end)
+        })
+        .collect()
+}
+
+/// Bytes received in each range of parallel download.
+pub struct RangeProgress {
+    ranges: Vec<(FileSize, FileSize)>,
+    received: Vec<AtomicU64>,
+    claimed: Vec<AtomicBool>, // Range has its data connection.
+}
+
+impl RangeProgress {
+    pub fn new(ranges: Vec<(FileSize, FileSize)>) -> Self {
+        RangeProgress {
+            received: ranges.iter().map(|_| AtomicU64::new(0)).collect(),
+            claimed: ranges.iter().map(|_| AtomicBool::new(false)).collect(),
+            ranges,
+        }
+    }
+
+    pub fn add(&self, range: usize, bytes: FileSize) {
+        self.received[range].fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn total(&self) -> FileSize {
+        self.received
+            .iter()
+            .map(|received| received.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    /// Length of file prefix without holes, the part of download that can be resumed.
+    pub fn contiguous_prefix(&self) -> FileSize {
+        let mut prefix = 0;
+
+        for ((start, end), received) in self.ranges.iter().zip(self.received.iter()) {
+            prefix = start + received.load(Ordering::Relaxed);
+
+            if prefix != *end {
+                break;
+            }
+        }
+
+        prefix
+    }
+
+    fn any_claimed(&self) -> bool {
+        self.claimed
+            .iter()
+            .any(|claimed| claimed.load(Ordering::Relaxed))
+    }
+
+    // Every range can be sent only once.
+    fn claim(&self, range: usize) -> bool {
+        self.claimed
+            .get(range)
+            .is_some_and(|claimed| !claimed.swap(true, Ordering::Relaxed))
+    }
+}
+
+/// Downloads file into already created .part file over several data connections.
+/// Control messages (errors, cancel, pause) still come through packets.
+/// Returns false if peer didn't open any data connection, then the usual download should be used.
+/// After failure .part file is cut to contiguous prefix, so it can be resumed.
+/// Data connections are accepted only from peer with given id, after the same checks as chat connections.
+#[allow(clippy::too_many_arguments)]
+pub async fn receive_ranges(
+    packets: &mut mpsc::Receiver<FilePacket>,
+    requests: &mpsc::UnboundedSender<Message>,
+    part_path: &Path,
+    file_id: FileID,
+    file_size: FileSize,
+    connections: usize,
+    peer_id: u64,
+    loading_bar: &Mutex<LoadingBarWrap>,
+    throttle: &Throttle,
+) -> Result<bool, ContentError> {
+    let listener = TcpListener::bind("0.0.0.0:0")
+        .await
+        .map_err(|e| e.to_string())?;
+    let port = listener.local_addr().map_err(|e| e.to_string())?.port();
+
+    let token: u64 = rand::random();
+    let ranges = split_ranges(file_size, connections);
+    let progress = Arc::new(RangeProgress::new(ranges.clone()));
+
+    let _ = requests.send(Message::Internal(InternalMessage::FileRangeRequest(
+        file_id,
+        port,
+        token,
+        ranges.clone(),
+    )));
+
+    let result = receive_into(
+        packets,
+        requests,
+        listener,
+        part_path,
+        file_id,
+        token,
+        peer_id,
+        &progress,
+        loading_bar,
+        throttle,
+    )
+    .await;
+
+    if let Err(ContentError::Failed(e)) = &result {
+        warn!("Parallel download failed: {}", e);
+
+        // Holes can't be resumed, only prefix without them.
+        let truncated = match tokio::fs::OpenOptions::new()
+            .write(true)
+            .open(part_path)
+            .await
+        {
+            Ok(file) => file.set_len(progress.contiguous_prefix()).await,
+            Err(e) => Err(e),
+        };
+
+        if let Err(e) = truncated {
+            warn!("Couldn't truncate {}: {}", part_path.display(), e);
+        }
+    }
+
+    result
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn receive_into(
+    packets: &mut mpsc::Receiver<FilePacket>,
+    requests: &mpsc::UnboundedSender<Message>,
+    listener: TcpListener,
+    part_path: &Path,
+    file_id: FileID,
+    token: u64,
+    peer_id: u64,
+    progress: &Arc<RangeProgress>,
+    loading_bar: &Mutex<LoadingBarWrap>,
+    throttle: &Throttle,
+) -> Result<bool, ContentError> {
+    let range_cnt = progress.ranges.len();
+    let mut receivers: JoinSet<Result<bool, String>> = JoinSet::new();
+    let mut attempts = 0;
+    let mut finished = 0;
+    let mut last_progress = (Instant::now(), 0);
+
+    let connect_deadline = time::sleep(CONNECT_TIMEOUT);
+    tokio::pin!(connect_deadline);
+
+    let mut progress_tick = time::interval(PROGRESS_INTERVAL);
+
+    while finished < range_cnt {
+        tokio::select! {
+            packet = packets.recv() => match packet {
+                Some(FilePacket::Internal(message)) => match message {
+                    // Peer couldn't reach us, chat connection still works.
+                    InternalMessage::FileContentError(_, e) if !progress.any_claimed() => {
+                        info!("Peer couldn't open data connections: {}", e);
+                        return Ok(false);
+                    }
+                    InternalMessage::FileContentError(_, e) => return Err(e.into()),
+                    InternalMessage::FileCancel(_) => return Err(ContentError::Cancelled),
+                    InternalMessage::FilePause(_) => set_paused(loading_bar, true),
+                    InternalMessage::FileResume(_) => set_paused(loading_bar, false),
+                    _ => {}
+                },
+                // File data comes only over data connections.
+                Some(FilePacket::Chunk(chunk)) => CHUNK_POOL.give(chunk),
+                None => return Err("Download interrupted!".to_string().into()),
+            },
+            _ = requests.closed() => return Err("Download interrupted!".to_string().into()),
+            // Every range needs a connection, few more are allowed for failed handshakes.
+            accepted = listener.accept(), if attempts < 2 * range_cnt => {
+                let Ok((stream, addr)) = accepted else {
+                    continue;
+                };
+                attempts += 1;
+
+                if !ACCESS_LIST.lock().unwrap().is_address_allowed(addr.ip()) {
+                    info!("Dropping data connection from blocked address {}", addr);
+                    continue;
+                }
+
+                let permit = match admit_handshake(addr.ip()) {
+                    Ok(permit) => permit,
+                    Err(reason) => {
+                        warn!("Dropping data connection from {}: {}", addr, reason);
+                        continue;
+                    }
+                };
+
+                receivers.spawn(receive_range(
+                    stream,
+                    addr,
+                    permit,
+                    part_path.to_path_buf(),
+                    file_id,
+                    token,
+                    peer_id,
+                    progress.clone(),
+                    throttle.clone(),
+                ));
+            }
+            Some(result) = receivers.join_next() => match result {
+                Ok(Ok(true)) => finished += 1,
+                Ok(Ok(false)) => {} // Connection that wasn't ours.
+                Ok(Err(e)) => return Err(e.into()),
+                Err(e) => return Err(e.to_string().into()),
+            },
+            _ = &mut connect_deadline, if !progress.any_claimed() => {
+                info!("Peer didn't open data connections, downloading over chat connection");
+                return Ok(false);
+            }
+            _ = progress_tick.tick() => {
+                let (now, total) = (Instant::now(), progress.total());
+                let mut paused = false;
+
+                {
+                    let mut loading_bar_lock = loading_bar.lock().unwrap();
+
+                    if let LoadingBar::Status(status) = &mut loading_bar_lock.loadingbar {
+                        status.advance(total, now);
+                        paused = status.paused;
+                        loading_bar_lock.changed = true;
+                    }
+                }
+
+                // Sender could die mid-range, or never open connections for some ranges.
+                if paused || total != last_progress.1 {
+                    last_progress = (now, total);
+                } else if progress.any_claimed() && now - last_progress.0 > IDLE_TIMEOUT {
+                    return Err("Download stalled!".to_string().into());
+                }
+            }
+        }
+    }
+
+    Ok(true)
+}
+
+// Authenticates data connection and writes its range at place in file.
+// Returns false if connection didn't come from peer asked for file.
+// Handshake slot is released once connection is authenticated.
+#[allow(clippy::too_many_arguments)]
+async fn receive_range(
+    stream: TcpStream,
+    addr: SocketAddr,
+    permit: OwnedSemaphorePermit,
+    part_path: PathBuf,
+    file_id: FileID,
+    token: u64,
+    peer_id: u64,
+    progress: Arc<RangeProgress>,
+    throttle: Throttle,
+) -> Result<bool, String> {
+    let hello = time::timeout(HELLO_TIMEOUT, async {
+        let (mut stream, peer_identity) =
+            SecureStream::handshake(stream, ROOM_KEY.as_ref()).await?;
+        let hello: RangeHello = stream.read().await?;
+        Ok::<_, StreamSerializerError>((stream, user_id_from_key(&peer_identity), hello))
+    })
+    .await;
+
+    let authenticated = match hello {
+        Ok(Ok((stream, sender_id, hello)))
+            if sender_id == peer_id && !is_ignored(sender_id, addr) && hello.token == token =>
+        {
+            Some((stream, hello.range as usize))
+        }
+        _ => None,
+    };
+
+    match &authenticated {
+        Some(_) => ATTEMPT_LIMITER.lock().unwrap().record_success(addr.ip()),
+        None => ATTEMPT_LIMITER
+            .lock()
+            .unwrap()
+            .record_failure(addr.ip(), Instant::now()),
+    }
+    drop(permit);
+
+    let (mut stream, range) = match authenticated {
+        Some((stream, range)) if progress.claim(range) => (stream, range),
+        _ => {
+            warn!(
+                "Dropping data connection from {} that doesn't belong to download",
+                addr
+            );
+            return Ok(false);
+        }
+    };
+
+    let (start, end) = progress.ranges[range];
+
+    let mut file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .open(&part_path)
+        .await
+        .map_err(|e| e.to_string())?;
+    file.seek(SeekFrom::Start(start))
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let mut chunk = ChunkBuffer::new(DATA_CHUNK_SIZE);
+    let mut position = start;
+
+    while position != end {
+        stream
+            .read_chunk(&mut chunk)
+            .await
+            .map_err(|e| format!("{:?}", e))?;
+
+        let len = chunk.data().len() as FileSize;
+
+        if len == 0
+            || chunk.file_id() != file_id
+            || chunk.position() != position
+            || position + len > end
+        {
+            return Err(format!("Download error! Status: {}/{}.", position, end));
+        }
+
+        // Reading slower makes peer send slower.
+        throttle.acquire(len as usize).await;
+
+        file.write_all(chunk.data())
+            .await
+            .map_err(|e| e.to_string())?;
+        position += len;
+        progress.add(range, len);
+
+        // Data connections always have data ready, chat tasks need their turn too.
+        tokio::task::yield_now().await;
+    }
+
+    file.flush().await.map_err(|e| e.to_string())?;
+
+    // Uploader counts file as delivered after every range is written.
+    stream
+        .send(&Message::Internal(InternalMessage::FileAck(file_id, 0)))
+        .await
+        .map_err(|e| format!("{:?}", e))?;
+
+    Ok(true)
+}
+
+/// Sends requested ranges of file, each over its own data connection to peer.
+/// Errors are reported to peer through packets, which go over chat connection.
+#[allow(clippy::too_many_arguments)]
+pub async fn range_uploader(
+    packets: mpsc::Sender<FilePacket>,
+    control: Arc<UploadControl>,
+    file_path: PathBuf,
+    file_id: FileID,
+    peer_id: u64,
+    addr: SocketAddr,
+    token: u64,
+    ranges: Vec<(FileSize, FileSize)>,
+) {
+    let result = tokio::select! {
+        result = upload_ranges(&control, &file_path, file_id, peer_id, addr, token, &ranges) => result,
+        reason = control.stopped() => Err(reason),
+    };
+
+    match result {
+        Ok(()) => set_loading_bar(&control.loading_bar, LoadingBar::Delivered),
+        Err(e) => {
+            let e = report_error(&packets, file_id, &e).await;
+            set_loading_bar(&control.loading_bar, LoadingBar::Error(e));
+        }
+    }
+}
+
+async fn upload_ranges(
+    control: &Arc<UploadControl>,
+    file_path: &Path,
+    file_id: FileID,
+    peer_id: u64,
+    addr: SocketAddr,
+    token: u64,
+    ranges: &[(FileSize, FileSize)],
+) -> Result<(), String> {
+    let file_size = tokio::fs::metadata(file_path)
+        .await
+        .map_err(|_| "File does not exsists anymore!".to_string())?
+        .len();
+
+    if ranges
+        .iter()
+        .any(|(start, end)| start > end || *end > file_size)
+    {
+        return Err("Requested range is outside of file!".to_string());
+    }
+
+    // Ranges come from peer, every one costs a task with its own connection and buffer.
+    if ranges.len() > MAX_CONNECTIONS || ranges.windows(2).any(|pair| pair[0].1 > pair[1].0) {
+        return Err("Requested ranges are invalid!".to_string());
+    }
+
+    let mut status = LoadingBarStatus::new(0, ranges.iter().map(|(s, e)| e - s).sum());
+    status.paused = control.is_paused();
+    set_loading_bar(&control.loading_bar, LoadingBar::Status(status));
+
+    // Each range gets its own task, so encryption of ranges runs on many cores.
+    let sent = Arc::new(AtomicU64::new(0));
+    let mut senders = JoinSet::new();
+
+    for (idx, range) in ranges.iter().enumerate() {
+        senders.spawn(send_range(
+            control.clone(),
+            file_path.to_path_buf(),
+            file_id,
+            peer_id,
+            addr,
+            token,
+            idx,
+            *range,
+            sent.clone(),
+        ));
+    }
+
+    while let Some(result) = senders.join_next().await {
+        result.map_err(|e| e.to_string())??;
+    }
+
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn send_range(
+    control: Arc<UploadControl>,
+    file_path: PathBuf,
+    file_id: FileID,
+    peer_id: u64,
+    addr: SocketAddr,
+    token: u64,
+    range: usize,
+    (start, end): (FileSize, FileSize),
+    sent: Arc<AtomicU64>,
+) -> Result<(), String> {
+    let _slot = SENDER_SLOTS
+        .acquire()
+        .await
+        .map_err(|_| "Upload interrupted!".to_string())?;
+
+    let mut stream = time::timeout(HELLO_TIMEOUT, connect(addr, peer_id))
+        .await
+        .map_err(|_| "Timed out while opening data connection!".to_string())??;
+
+    stream
+        .send(&RangeHello {
+            token,
+            range: range as u32,
+        })
+        .await
+        .map_err(|e| format!("{:?}", e))?;
+
+    let mut file = tokio::fs::File::open(&file_path)
+        .await
+        .map_err(|_| "File does not exsists anymore!".to_string())?;
+    file.seek(SeekFrom::Start(start))
+        .await
+        .map_err(|_| "Error reading file!".to_string())?;
+
+    let mut chunk = ChunkBuffer::new(DATA_CHUNK_SIZE);
+    let mut position = start;
+
+    while position != end {
+        control.wait_until_running().await?;
+
+        let len = (end - position).min(DATA_CHUNK_SIZE as FileSize) as usize;
+        file.read_exact(chunk.fill(file_id, position, len))
+            .await
+            .map_err(|_| "Error reading file!".to_string())?;
+
+        control.throttle(len).await;
+
+        stream
+            .send_chunk(&mut chunk)
+            .await
+            .map_err(|_| "Upload interrupted!".to_string())?;
+
+        position += len as FileSize;
+        let total = sent.fetch_add(len as FileSize, Ordering::Relaxed) + len as FileSize;
+
+        {
+            let mut loading_bar_lock = control.loading_bar.lock().unwrap();
+            if let LoadingBar::Status(status) = &mut loading_bar_lock.loadingbar {
+                status.advance(total, Instant::now());
+                loading_bar_lock.changed = true;
+            }
+        }
+
+        // Data connections always have room for data, chat tasks need their turn too.
+        tokio::task::yield_now().await;
+    }
+
+    // Range counts as delivered once peer wrote it.
+    match stream.read::<Message>().await {
+        Ok(Message::Internal(InternalMessage::FileAck(id, _))) if id == file_id => Ok(()),
+        _ => Err("Upload interrupted!".to_string()),
+    }
+}
+
+// Data connection is authenticated the same way as chat connection, and has to lead to the same peer.
+async fn connect(addr: SocketAddr, peer_id: u64) -> Result<SecureStream, String> {
+    let stream = TcpStream::connect(addr)
+        .await
+        .map_err(|e| format!("Couldn't open data connection: {}", e))?;
+
+    let (stream, peer_identity) = SecureStream::handshake(stream, ROOM_KEY.as_ref())
+        .await
+        .map_err(|e| format!("Data connection handshake failed: {:?}", e))?;
+
+    if user_id_from_key(&peer_identity) != peer_id {
+        return Err("Data connection leads to diffrent peer!".to_string());
+    }
+
+    Ok(stream)
+}
@@ -8,8 +8,10 @@ use ratatui::widgets::Borders;
 use ratatui::widgets::Widget;
 use ratatui::{buffer::Buffer, widgets::Block};
 
-use crate::modules::{networking::*, protocol::*};
+use crate::config::settings;
+use crate::modules::{limits::*, networking::*, protocol::*, trust::REJECTED_PEERS};
 use cli_log::*;
+use std::sync::atomic::Ordering;
 use std::sync::{Arc, Mutex};
 use tokio::task::JoinHandle;
 
@@ -54,9 +56,64 @@ impl<'a> PeerList<'a> {
         let mut peer_buffer = self.peer_buffer.lock().unwrap();
 
         for cd in peer_buffer.drain(..) {
-            self.peer_list.push(PeerState::<'a>::from(cd))
+            if REJECTED_PEERS.lock().unwrap().contains(&cd.peer_id) {
+                info!("Dropping connection with rejected peer {}", cd.peer_name);
+                continue;
+            }
+
+            // Duplicate of active connection doesn't add a peer.
+            let duplicate = self
+                .peer_list
+                .list
+                .iter()
+                .any(|peer| peer.id == cd.peer_id && peer.is_active());
+            let active_peers = self.peer_list.list.iter().filter(|p| p.is_active()).count();
+            if !duplicate && active_peers >= settings().connection_limits.max_peers {
+                info!("Dropping connection with {}, too many peers", cd.peer_name);
+                DROPPED_ATTEMPTS.fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
+
+            let known_peer = self
+                .peer_list
+                .list
+                .iter_mut()
+                .find(|peer| peer.id == cd.peer_id);
+
+            match known_peer {
+                // Duplicate connection with already connected peer, the same one is kept on both sides.
+                Some(peer)
+                    if peer.is_active()
+                        && !prefers_new_connection(peer.initiator, cd.initiator) =>
+                {
+                    info!("Dropping duplicate connection with {}", cd.peer_name);
+                }
+                Some(peer) if peer.is_active() => {
+                    info!("Replacing duplicate connection with {}", cd.peer_name);
+                    let mut new_peer = PeerState::<'a>::from(cd);
+                    new_peer.continue_from(peer);
+                    peer.close();
+                    *peer = new_peer;
+                }
+                // Peer came back, continue old conversation.
+                Some(peer) => {
+                    let mut new_peer = PeerState::<'a>::from(cd);
+                    new_peer.continue_from(peer);
+                    *peer = new_peer;
+                }
+                None => self.peer_list.push(PeerState::<'a>::from(cd)),
+            }
+        }
+
+        for peer in self.peer_list.list.iter() {
+            peer.close_if_disconnected();
         }
 
+        ACTIVE_PEERS.store(
+            self.peer_list.list.iter().filter(|p| p.is_active()).count(),
+            Ordering::Relaxed,
+        );
+
         if self.peer_list.get_selected_idx().is_none() && !self.peer_list.is_empty() {
             self.peer_list.select(0);
         }
@@ -69,9 +126,58 @@ impl<'a> PeerList<'a> {
                     return true;
                 }
 
-                KeyCode::Enter if self.get_selected().is_some() => {
+                KeyCode::Enter if self.get_selected().is_some_and(|peer| !peer.is_pending()) => {
                     *current_screen = AppPosition::ChatSession;
                 }
+                KeyCode::Char('a') => {
+                    if let Some(peer) = self.get_selected() {
+                        peer.accept();
+                    }
+                }
+                KeyCode::Char('r') if self.get_selected().is_some_and(|peer| peer.is_pending()) => {
+                    if let Some(idx) = self.peer_list.get_selected_idx() {
+                        self.peer_list.remove(idx).reject();
+                    }
+                }
+                KeyCode::Char('b') => {
+                    if let Some(peer) = self.get_selected() {
+                        peer.toggle_blocked();
+                    }
+                }
+                KeyCode::Char('w') => {
+                    if let Some(peer) = self.get_selected() {
+                        peer.toggle_allowlisted();
+                    }
+                }
+                KeyCode::Char('i') if self.get_selected().is_some() => {
+                    *current_screen = AppPosition::PeerDetails;
+                }
+                KeyCode::Char('u') => {
+                    if let Some(peer) = self.get_selected() {
+                        peer.cycle_limit(true);
+                    }
+                }
+                KeyCode::Char('d') => {
+                    if let Some(peer) = self.get_selected() {
+                        peer.cycle_limit(false);
+                    }
+                }
+                KeyCode::Char('c') => {
+                    if let Some(peer) = self.get_selected() {
+                        peer.cycle_download_connections();
+                    }
+                }
+                KeyCode::Char('t') => {
+                    *current_screen = AppPosition::TransferList;
+                }
+                KeyCode::Char(' ') => {
+                    if let Some(peer) = self.get_selected() {
+                        peer.toggle_marked();
+                    }
+                }
+                KeyCode::Char('s') if self.broadcast_recipients().next().is_some() => {
+                    *current_screen = AppPosition::Broadcast;
+                }
                 KeyCode::Up => {
                     self.peer_list.go_up();
                 }
@@ -89,16 +195,36 @@ impl<'a> PeerList<'a> {
         self.peer_list.get_selected()
     }
 
+    // Marked peers that can get msgs right now.
+    pub fn broadcast_recipients(&self) -> impl Iterator<Item = &PeerState<'a>> {
+        self.peer_list
+            .list
+            .iter()
+            .filter(|peer| peer.is_marked() && peer.is_active() && !peer.is_pending())
+    }
+
+    pub fn get_peer_mut(&mut self, peer_id: u64) -> Option<&mut PeerState<'a>> {
+        self.peer_list
+            .list
+            .iter_mut()
+            .find(|peer| peer.id == peer_id)
+    }
+
     pub fn render(&mut self, rect: &mut Rect, buf: &mut Buffer, is_active: bool) {
+        let dropped = match DROPPED_ATTEMPTS.load(Ordering::Relaxed) {
+            0 => String::new(),
+            dropped => format!(" ({} attempts dropped)", dropped),
+        };
+
         if self.peer_list.is_empty() {
             let block = Block::default()
-                .title("No users detected!")
+                .title(format!("No users detected!{}", dropped))
                 .borders(ratatui::widgets::Borders::ALL);
             Widget::render(block, *rect, buf);
         } else {
             let block = Block::default()
                 .borders(Borders::ALL)
-                .title("Peers:")
+                .title(format!("Peers:{}", dropped))
                 .border_style(Style::default().add_modifier(Modifier::BOLD))
                 .border_style(if is_active {
                     Style::default().fg(Color::Green)
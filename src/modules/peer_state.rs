@@ -10,28 +10,34 @@ use ratatui::style::{Color, Style};
 use ratatui::text::Span;
 use ratatui::widgets::Block;
 use ratatui::widgets::Borders;
+use ratatui::widgets::Paragraph;
 use ratatui::widgets::Widget;
+use ratatui::widgets::Wrap;
 use std::collections::HashMap;
-use std::path::PathBuf;
-use tokio::io::AsyncReadExt;
+use std::path::{Path, PathBuf};
 
 use ratatui::text::Line;
-use tokio::io::AsyncWriteExt;
 use unicode_width::UnicodeWidthStr;
 
 use crate::config::*;
-use crate::modules::{networking::*, protocol::*};
+use crate::modules::transfers::{self, Transfer, TransferDirection};
+use crate::modules::{
+    access_list::*, auto_download::*, encryption::*, file_transfer::*, networking::*,
+    offers::OFFERS, parallel_transfer::range_uploader, protocol::*, throttle::*, trust::*,
+};
 
 use cli_log::*;
 use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 use tokio::task::JoinHandle;
 
 use crate::modules::message_bubble::*;
+use crate::modules::multiplexer::BulkStreams;
 use crate::modules::tui::AppPosition;
 use crate::modules::widgets::list_component::*;
 
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, oneshot};
 
 use tui_textarea::TextArea;
 
@@ -42,42 +48,263 @@ pub enum EditorMode {
     File,
 }
 
-type DownloadedFilesMap = Arc<Mutex<HashMap<FileID, mpsc::UnboundedSender<InternalMessage>>>>;
-type OwnedFilesMap = Arc<Mutex<HashMap<FileID, PathBuf>>>;
-
 /// Struct for messages to be displayed with context.
 pub struct MessageContext {
     pub was_received: bool, // Whether it was sent or received.
     pub message: UserMessage,
+    pub loading_bar: Option<Arc<Mutex<LoadingBarWrap>>>, // Download started on arrival.
+    pub error: Option<String>,                           // Why our msg wasn't sent.
+}
+
+// File header is ready after file is hashed, or it is shown with reason why it wasn't sent.
+type PreparedMessage = Result<UserMessage, (UserMessage, String)>;
+
+/// User msg waiting for its turn, msgs are sent in the order user sent them.
+pub enum Outgoing {
+    Ready(Message),
+    Pending(oneshot::Receiver<PreparedMessage>),
+}
+
+/// Handles needed to start downloads from peer, reader has its own copy for automatic downloads.
+#[derive(Clone)]
+struct Downloads {
+    requests: mpsc::UnboundedSender<Message>,
+    downloaded_files: DownloadedFilesMap,
+    partial_files: PartialFilesMap,
+    throttle: Throttle,
+    connections: Arc<AtomicUsize>, // Chosen for peer, used only for big files.
+    peer_id: u64,
 }
 
+impl Downloads {
+    // Starts download of file into given folder, progress is shown on loading bar.
+    fn start_file(
+        &self,
+        file_id: FileID,
+        file_name: String,
+        file_size: FileSize,
+        file_hash: FileHash,
+        loading_bar: Arc<Mutex<LoadingBarWrap>>,
+        download_dir: PathBuf,
+    ) {
+        let connections = settings()
+            .transfer
+            .connections_for(file_size, self.connections.load(Ordering::Relaxed));
+
+        let (tx, rx) = mpsc::channel::<FilePacket>(settings().transfer.window());
+
+        self.downloaded_files.lock().unwrap().insert(file_id, tx);
+
+        tokio::task::spawn(file_downloader(
+            rx,
+            self.requests.clone(),
+            file_id,
+            file_name,
+            file_size,
+            file_hash,
+            loading_bar,
+            self.downloaded_files.clone(),
+            self.partial_files.clone(),
+            self.throttle.clone(),
+            connections,
+            self.peer_id,
+            download_dir,
+        ));
+    }
+}
+
+// Numbers of data connections user can choose for big downloads from peer.
+const CONNECTION_PRESETS: [usize; 4] = [1, 2, 4, 8];
+
 /// Main struct holding all information about connected peer.
 pub struct PeerState<'a> {
+    pub id: u64,                                    // Id derived from peer identity key
+    pub identity_key: [u8; 32],                     // Identity key verified in handshake
     pub name: String,                               // Name of connected peer
+    pub trust: TrustStatus,                         // Result of comparing key with known peers
     pub addr: SocketAddr,                           // Addres of connected peer
+    pub initiator: u64,                             // User that opened the connection
     render_cache: Option<ListCache<'a>>,            // Cache for UI rendering
     is_connected: bool,                             // If peer is connected.
+    accepted: Arc<AtomicBool>,                      // If user agreed to talk with peer.
     pub messages: ListComponent<'a, MsgBubble<'a>>, // List of user messages exchanged
     pub editor: TextArea<'a>,                       // Editor element
     pub editor_mode: EditorMode,                    // If entering file or text
     downloaded_files: DownloadedFilesMap,           // Files currently being downloaded
-    owned_files: OwnedFilesMap,                     // Files shared with user.
+    partial_files: PartialFilesMap,                 // Interrupted downloads that can be resumed.
+    pub limits: PeerLimits,                         // Bandwidth limits of this peer.
+    download_connections: Arc<AtomicUsize>,         // Data connections used for big downloads.
+    marked: bool,                                   // Selected as recipient of broadcast.
     conversation_buffer: Arc<Mutex<Vec<MessageContext>>>,
     message_writer_queue: mpsc::UnboundedSender<Message>,
+    user_queue: mpsc::UnboundedSender<Outgoing>, // User msgs, passed to writer in order.
     message_writer_handle: JoinHandle<Result<(), StreamSerializerError>>,
     message_reader_handle: JoinHandle<Result<(), StreamSerializerError>>,
 }
 
-impl PeerState<'_> {
+impl<'a> PeerState<'a> {
     pub fn is_active(&self) -> bool {
         !self.message_writer_handle.is_finished() && !self.message_reader_handle.is_finished()
     }
 
+    // Reader stops on broken or hostile connection, writer has to follow to close the socket.
+    pub fn close_if_disconnected(&self) {
+        if self.message_reader_handle.is_finished() && !self.message_writer_handle.is_finished() {
+            self.message_writer_handle.abort();
+        }
+    }
+
+    // Pending peers can't send us anything until user accepts them.
+    pub fn is_pending(&self) -> bool {
+        !self.accepted.load(Ordering::Relaxed)
+    }
+
+    // In strict mode peer has to be allowlisted first.
+    pub fn accept(&mut self) {
+        if ACCESS_LIST.lock().unwrap().is_refused(self.id) {
+            return;
+        }
+        self.accepted.store(true, Ordering::Relaxed);
+        self.render_cache = None;
+    }
+
+    // Closes connection and ignores peer until restart.
+    pub fn reject(&self) {
+        info!("Rejected connection with {} ({})", self.name, self.addr);
+        REJECTED_PEERS.lock().unwrap().insert(self.id);
+
+        self.close();
+    }
+
+    // Stops background tasks, running downloads end as interrupted.
+    pub fn close(&self) {
+        self.message_reader_handle.abort();
+        self.message_writer_handle.abort();
+        self.downloaded_files.lock().unwrap().clear();
+    }
+
+    // Takes over conversation and unfinished transfers from previous connection with the same peer.
+    pub fn continue_from(&mut self, previous: &mut PeerState<'a>) {
+        std::mem::swap(&mut self.messages, &mut previous.messages);
+
+        self.partial_files
+            .lock()
+            .unwrap()
+            .extend(previous.partial_files.lock().unwrap().drain());
+
+        self.limits.copy_from(&previous.limits);
+        self.set_download_connections(previous.download_connections());
+        self.marked = previous.marked;
+
+        if !previous.is_pending() {
+            self.accept(); // Don't ask again about the same peer.
+        }
+    }
+
+    // Blocking also closes connection, after unblocking we announce ourselves so peer connects again.
+    pub fn toggle_blocked(&mut self) {
+        if ACCESS_LIST
+            .lock()
+            .unwrap()
+            .toggle_blocked(self.id, self.addr.ip())
+        {
+            info!("Blocked {} ({})", self.name, self.addr);
+            self.close();
+        } else {
+            info!("Unblocked {} ({})", self.name, self.addr);
+            announce();
+        }
+        self.render_cache = None;
+    }
+
+    // Switches upload or download limit of this peer to the next preset.
+    pub fn cycle_limit(&mut self, upload: bool) {
+        match upload {
+            true => self.limits.upload.cycle_limit(),
+            false => self.limits.download.cycle_limit(),
+        }
+        self.render_cache = None;
+    }
+
+    // Switches number of data connections used for big downloads from peer to the next preset.
+    pub fn cycle_download_connections(&mut self) {
+        let current = CONNECTION_PRESETS
+            .iter()
+            .position(|preset| *preset == self.download_connections());
+
+        self.set_download_connections(match current {
+            Some(idx) => CONNECTION_PRESETS[(idx + 1) % CONNECTION_PRESETS.len()],
+            None => CONNECTION_PRESETS[0],
+        });
+        self.render_cache = None;
+    }
+
+    pub fn download_connections(&self) -> usize {
+        self.download_connections.load(Ordering::Relaxed)
+    }
+
+    // Shared with reader, so automatic downloads use the same number.
+    pub fn set_download_connections(&self, connections: usize) {
+        self.download_connections
+            .store(connections, Ordering::Relaxed);
+    }
+
+    // Limits shown next to peer name, empty if peer uses defaults.
+    fn limits_label(&self) -> String {
+        let mut limits: Vec<String> =
+            [("up", &self.limits.upload), ("down", &self.limits.download)]
+                .into_iter()
+                .filter_map(|(direction, limiter)| {
+                    limiter
+                        .limit()
+                        .map(|limit| format!("{} {}", direction, format_limit(Some(limit))))
+                })
+                .collect();
+
+        if self.download_connections() != settings().transfer.connections {
+            limits.push(format!("{} connections", self.download_connections()));
+        }
+
+        match limits.is_empty() {
+            true => String::new(),
+            false => format!(" [{}]", limits.join(", ")),
+        }
+    }
+
+    pub fn is_marked(&self) -> bool {
+        self.marked
+    }
+
+    // Marks peer as recipient of next broadcast, or unmarks it.
+    pub fn toggle_marked(&mut self) {
+        self.marked = !self.marked;
+        self.render_cache = None;
+    }
+
+    // Allowlisting also accepts peer, in strict mode removing from allowlist closes connection like blocking.
+    pub fn toggle_allowlisted(&mut self) {
+        let allowed = ACCESS_LIST.lock().unwrap().toggle_allowlisted(self.id);
+        let refused = ACCESS_LIST.lock().unwrap().is_refused(self.id);
+
+        if allowed {
+            info!("Allowlisted {} ({})", self.name, self.addr);
+            self.accept();
+            if !self.is_active() {
+                announce(); // So disconnected peer connects again.
+            }
+        } else if refused {
+            info!("Removed {} ({}) from allowlist", self.name, self.addr);
+            self.accepted.store(false, Ordering::Relaxed);
+            self.close();
+        }
+        self.render_cache = None;
+    }
+
     // Merge buffored msgs for rendering.
     pub fn update(&mut self) {
         let mut msg_buffer = self.conversation_buffer.lock().unwrap();
         self.messages.list.extend(msg_buffer.drain(..).map(|mc| {
-            MsgBubble::new(
+            let mut message_bubble = MsgBubble::new(
                 match mc.was_received {
                     true => Some(self.name.clone()),
                     false => None,
@@ -87,12 +314,63 @@ impl PeerState<'_> {
                     true => MsgBubbleAllignment::Left,
                     false => MsgBubbleAllignment::Right,
                 },
-            )
+            );
+            message_bubble.loading_bar = mc.loading_bar;
+            message_bubble.error = mc.error;
+            message_bubble
         }));
+        drop(msg_buffer);
+
+        // Our file msgs show the latest download by peer, or why file can't be downloaded anymore.
+        let now = unix_time();
+
+        for message_bubble in self.messages.list.iter_mut() {
+            if message_bubble.received_from.is_some() || message_bubble.error.is_some() {
+                continue;
+            }
+
+            let end_reason = Self::transferred_ids(&message_bubble.message)
+                .first()
+                .and_then(|file_id| OFFERS.lock().unwrap().end_reason(self.id, *file_id, now));
+
+            if let UserMessage::FileHeader(_, _, file_id, _) = &message_bubble.message {
+                let uploads = transfers::uploads_of(self.id, *file_id);
+
+                if let Some(latest) = uploads
+                    .last()
+                    .filter(|latest| end_reason.is_none() || latest.is_running())
+                {
+                    if !message_bubble
+                        .loading_bar
+                        .as_ref()
+                        .is_some_and(|lb| Arc::ptr_eq(lb, &latest.loading_bar))
+                    {
+                        message_bubble.loading_bar = Some(latest.loading_bar.clone());
+                        message_bubble.download_count = uploads.len();
+                    }
+                    continue;
+                }
+            }
+
+            let Some(reason) = end_reason else {
+                continue;
+            };
+
+            let shown = message_bubble.loading_bar.as_ref().is_some_and(
+                |lb| matches!(&lb.lock().unwrap().loadingbar, LoadingBar::Error(e) if e == reason),
+            );
+
+            if !shown {
+                message_bubble.loading_bar = Some(Arc::new(Mutex::new(LoadingBarWrap {
+                    loadingbar: LoadingBar::Error(reason.to_string()),
+                    changed: true,
+                })));
+            }
+        }
     }
 
     pub fn send(&self, msg: Message) {
-        let _ = self.message_writer_queue.send(msg);
+        self.recipient().send(msg);
     }
 
     // Function used for downloading files with given parameters.
@@ -101,113 +379,329 @@ impl PeerState<'_> {
         file_id: FileID,
         file_name: String,
         file_size: FileSize,
+        file_hash: FileHash,
         loading_bar: Arc<Mutex<LoadingBarWrap>>,
     ) {
-        let (tx, rx) = mpsc::unbounded_channel::<InternalMessage>();
-
-        self.downloaded_files.lock().unwrap().insert(file_id, tx);
-
-        tokio::task::spawn(file_downloader(
-            rx,
+        self.downloads().start_file(
             file_id,
             file_name,
             file_size,
+            file_hash,
             loading_bar,
-            self.downloaded_files.clone(),
-        ));
+            DOWNLOAD_PATH.clone(),
+        );
+    }
 
-        self.send(Message::Internal(InternalMessage::FileRequest(file_id)));
+    fn downloads(&self) -> Downloads {
+        Downloads {
+            requests: self.message_writer_queue.clone(),
+            downloaded_files: self.downloaded_files.clone(),
+            partial_files: self.partial_files.clone(),
+            throttle: self.limits.download_throttle(),
+            connections: self.download_connections.clone(),
+            peer_id: self.id,
+        }
     }
 
-    // Send file-msg containing this file if exists.
+    // Send file-msg (or directory-msg) containing this file if exists.
     pub fn upload_file(&mut self, file_path: PathBuf) {
-        let file_id: FileID = rand::thread_rng().gen();
+        if offer_path(file_path, vec![self.recipient()]) {
+            self.editor = TextArea::default();
+        }
+    }
+
+    pub fn recipient(&self) -> Recipient {
+        Recipient {
+            peer_id: self.id,
+            queue: self.user_queue.clone(),
+        }
+    }
+
+    // Performs action operation (download, copy, ...) on selected msg.
+    pub fn handle_action_on_msg(&mut self) {
+        let Some(idx) = self.messages.get_selected_idx() else {
+            return;
+        };
 
-        if std::fs::metadata(&file_path)
-            .map(|metadata| metadata.is_file())
-            .unwrap_or(false)
+        match &self.messages.list[idx as usize].message {
+            UserMessage::Text(text) => {
+                let _ = CLIPBOARD.lock().unwrap().set_contents(text.clone());
+            }
+            UserMessage::FileHeader(..) | UserMessage::DirectoryHeader(_) => {
+                self.start_download(idx as usize);
+            }
+        }
+    }
+
+    // Downloads file or directory of received msg, unless it is being downloaded already.
+    fn start_download(&mut self, idx: usize) {
+        let message_bubble = &mut self.messages.list[idx];
+
+        if message_bubble.received_from.is_none()
+            || !is_loading_bar_free(&message_bubble.loading_bar)
         {
-            let file_name: String = file_path
-                .file_name()
-                .unwrap()
-                .to_string_lossy() // Maybe this could be improved.
-                .to_string();
+            return;
+        }
 
-            let file_size: FileSize = std::fs::metadata(&file_path)
-                .map(|metadata| metadata.len())
-                .unwrap_or(0);
+        // Loading bar will be loaded later, those are placeholder values.
+        let loading_bar = Arc::new(Mutex::new(LoadingBarWrap {
+            loadingbar: LoadingBar::Status(LoadingBarStatus::new(0, 1)),
+            changed: true,
+        }));
 
-            self.owned_files.lock().unwrap().insert(file_id, file_path);
+        // Previous try is replaced on transfer list.
+        if let Some(previous) = message_bubble.loading_bar.replace(loading_bar.clone()) {
+            transfers::forget(&previous);
+        }
 
-            self.editor = TextArea::default();
+        let name = match &message_bubble.message {
+            UserMessage::Text(_) => return,
+            UserMessage::FileHeader(file_name, ..) => file_name.clone(),
+            UserMessage::DirectoryHeader(tree) => format!("{}/", tree.name),
+        };
 
-            self.send(Message::User(UserMessage::FileHeader(
-                file_name, file_size, file_id,
-            )));
+        transfers::register(Transfer::new(
+            TransferDirection::Download,
+            self.id,
+            self.name.clone(),
+            name,
+            DOWNLOAD_PATH.clone(),
+            loading_bar.clone(),
+        ));
+
+        match message_bubble.message.clone() {
+            UserMessage::Text(_) => {}
+            UserMessage::FileHeader(file_name, file_size, file_id, file_hash) => {
+                self.download_file(file_id, file_name, file_size, file_hash, loading_bar);
+            }
+            UserMessage::DirectoryHeader(tree) => {
+                tokio::task::spawn(directory_downloader(
+                    self.message_writer_queue.clone(),
+                    tree,
+                    loading_bar,
+                    self.downloaded_files.clone(),
+                    self.limits.download_throttle(),
+                    DOWNLOAD_PATH.clone(),
+                ));
+            }
         }
     }
 
-    // Performs action operation (download, copy, ...) on selected msg.
-    pub fn handle_action_on_msg(&mut self) {
+    // Ids of files transferred for given message.
+    fn transferred_ids(message: &UserMessage) -> Vec<FileID> {
+        match message {
+            UserMessage::Text(_) => Vec::new(),
+            UserMessage::FileHeader(_, _, file_id, _) => vec![*file_id],
+            UserMessage::DirectoryHeader(tree) => tree.files.iter().map(|file| file.id).collect(),
+        }
+    }
+
+    // Index of received msg whose download shows on given loading bar.
+    fn download_idx(&self, loading_bar: &Arc<Mutex<LoadingBarWrap>>) -> Option<usize> {
+        self.messages.list.iter().position(|message_bubble| {
+            message_bubble
+                .loading_bar
+                .as_ref()
+                .is_some_and(|lb| Arc::ptr_eq(lb, loading_bar))
+        })
+    }
+
+    // Cancels download of selected msg, or upload of it if we sent it.
+    pub fn cancel_selected_transfer(&mut self) {
+        let Some(idx) = self.messages.get_selected_idx() else {
+            return;
+        };
+        let message_bubble = &self.messages.list[idx as usize];
+
+        if message_bubble.received_from.is_some() {
+            self.cancel_download(idx as usize);
+        } else if let Some(transfer) = message_bubble
+            .loading_bar
+            .as_ref()
+            .and_then(transfers::find)
+        {
+            self.cancel_transfer(&transfer);
+        }
+    }
+
+    // Peer can't start new downloads of our selected file msg, running ones are cancelled.
+    pub fn revoke_selected_offer(&mut self) {
+        let Some(idx) = self.messages.get_selected_idx() else {
+            return;
+        };
+        let message_bubble = &self.messages.list[idx as usize];
+
+        if message_bubble.received_from.is_some() {
+            return;
+        }
+
+        let file_ids = Self::transferred_ids(&message_bubble.message);
+        OFFERS.lock().unwrap().revoke(self.id, &file_ids);
+
+        for file_id in file_ids {
+            for transfer in transfers::uploads_of(self.id, file_id) {
+                self.cancel_transfer(&transfer);
+            }
+        }
+    }
+
+    // Stops download of msg, peer stops sending and partial file is deleted.
+    fn cancel_download(&mut self, idx: usize) {
+        let message_bubble = &self.messages.list[idx];
+
+        if message_bubble.received_from.is_none()
+            || is_loading_bar_free(&message_bubble.loading_bar)
+        {
+            return;
+        }
+
+        for file_id in Self::transferred_ids(&message_bubble.message) {
+            let _ = self
+                .message_writer_queue
+                .send(Message::Internal(InternalMessage::FileCancel(file_id)));
+
+            // Downloader finishes on its own and cleans downloaded_files.
+            let tx = self.downloaded_files.lock().unwrap().get(&file_id).cloned();
+            if let Some(tx) = tx {
+                tokio::task::spawn(async move {
+                    let _ = tx
+                        .send(FilePacket::Internal(InternalMessage::FileCancel(file_id)))
+                        .await;
+                });
+            }
+        }
+    }
+
+    // Stops transfer from transfer list, uploads are also stopped on our side right away.
+    pub fn cancel_transfer(&mut self, transfer: &Transfer) {
+        if !transfer.is_running() {
+            return;
+        }
+
+        match &transfer.direction {
+            TransferDirection::Upload(file_id, control) => {
+                control.cancel();
+                self.send(Message::Internal(InternalMessage::FileCancel(*file_id)));
+            }
+            TransferDirection::Download => {
+                if let Some(idx) = self.download_idx(&transfer.loading_bar) {
+                    self.cancel_download(idx);
+                }
+            }
+        }
+    }
+
+    // Starts failed download from transfer list again.
+    pub fn retry_transfer(&mut self, transfer: &Transfer) {
+        if let Some(idx) = self.download_idx(&transfer.loading_bar) {
+            self.start_download(idx);
+        }
+    }
+
+    // Pauses or resumes download of selected msg, or upload of it if we sent it.
+    pub fn toggle_pause_selected_transfer(&mut self) {
         let Some(message_bubble) = self.messages.get_selected() else {
             return;
         };
 
-        match &message_bubble.message {
-            UserMessage::Text(text) => {
-                let _ = CLIPBOARD.lock().unwrap().set_contents(text.clone());
+        let Some(loading_bar) = message_bubble.loading_bar.as_ref() else {
+            return;
+        };
+
+        if message_bubble.received_from.is_none() {
+            if let Some(transfer) = transfers::find(loading_bar) {
+                if let TransferDirection::Upload(file_id, control) = &transfer.direction {
+                    if transfer.is_running() {
+                        let message = match control.is_paused() {
+                            true => {
+                                control.resume();
+                                InternalMessage::FileResume(*file_id)
+                            }
+                            false => {
+                                control.pause();
+                                InternalMessage::FilePause(*file_id)
+                            }
+                        };
+                        self.send(Message::Internal(message));
+                    }
+                }
             }
-            UserMessage::FileHeader(file_name, file_size, file_id) => {
-                if message_bubble.received_from.is_some()
-                    && is_loading_bar_free(&message_bubble.loading_bar)
-                {
-                    // Loading bar will be loaded later, those are placeholder values.
-                    let loading_bar = Arc::new(Mutex::new(LoadingBarWrap {
-                        loadingbar: LoadingBar::Status(LoadingBarStatus {
-                            position: 0,
-                            end: 1,
-                        }),
-                        changed: true,
-                    }));
-                    message_bubble.loading_bar = Some(loading_bar.clone());
-
-                    // Copy fist to allow for mut borrow of self in self.download_file call - droping message_bubble refs.
-                    let file_name = file_name.clone();
-                    let file_size = *file_size;
-                    let file_id = *file_id;
-
-                    self.download_file(file_id, file_name, file_size, loading_bar);
+            return;
+        }
+
+        let paused = match &loading_bar.lock().unwrap().loadingbar {
+            LoadingBar::Status(status) if status.position != status.end => status.paused,
+            _ => return,
+        };
+
+        set_paused(loading_bar, !paused);
+
+        for file_id in Self::transferred_ids(&message_bubble.message) {
+            let _ = self
+                .message_writer_queue
+                .send(Message::Internal(match paused {
+                    true => InternalMessage::FileResume(file_id),
+                    false => InternalMessage::FilePause(file_id),
+                }));
+        }
+    }
+
+    // Remember that user confirmed peer key by comparing authentication string.
+    pub fn mark_verified(&mut self) {
+        if KNOWN_PEERS
+            .lock()
+            .unwrap()
+            .mark_verified(self.id, &self.name, &self.identity_key)
+        {
+            self.trust = TrustStatus::Verified;
+        }
+    }
+
+    // Handle key on peer details screen, returns true if screen should be closed.
+    pub fn handle_details_event(&mut self, key: KeyEvent) -> bool {
+        if key.kind == crossterm::event::KeyEventKind::Press {
+            match key.code {
+                KeyCode::Esc => {
+                    return true;
+                }
+                KeyCode::Char('v') => {
+                    self.mark_verified();
                 }
+                _ => {}
             }
         }
+
+        false
     }
 
     pub fn handle_event(&mut self, key: KeyEvent, _current_screen: &mut AppPosition) -> bool {
         if self.messages.is_selected() {
             // Currently listing conversation.
-            match key {
-                key if key.code == KeyCode::Esc => {
-                    if key.kind == crossterm::event::KeyEventKind::Press {
+            if key.kind == crossterm::event::KeyEventKind::Press {
+                match key.code {
+                    KeyCode::Esc => {
                         self.messages.reset();
                     }
-                }
-                key if key.code == KeyCode::Up => {
-                    if key.kind == crossterm::event::KeyEventKind::Press {
+                    KeyCode::Up => {
                         self.messages.go_up();
                     }
-                }
-                key if key.code == KeyCode::Down => {
-                    if key.kind == crossterm::event::KeyEventKind::Press && !self.messages.go_down()
-                    {
+                    KeyCode::Down if !self.messages.go_down() => {
                         self.messages.reset();
                     }
-                }
-                key if key.code == KeyCode::Enter => {
-                    if key.kind == crossterm::event::KeyEventKind::Press {
+                    KeyCode::Enter => {
                         self.handle_action_on_msg();
                     }
+                    KeyCode::Char('c') => {
+                        self.cancel_selected_transfer();
+                    }
+                    KeyCode::Char('p') => {
+                        self.toggle_pause_selected_transfer();
+                    }
+                    KeyCode::Char('x') => {
+                        self.revoke_selected_offer();
+                    }
+                    _ => {}
                 }
-                _ => {}
             }
         } else {
             // Currently editing next msg.
@@ -270,43 +764,218 @@ impl PeerState<'_> {
     }
 }
 
-// Create new peer state from incoming connection.
+/// Peer that our msgs are sent to, the same msg can be sent to many of them.
+#[derive(Clone)]
+pub struct Recipient {
+    pub peer_id: u64,
+    queue: mpsc::UnboundedSender<Outgoing>,
+}
+
+impl Recipient {
+    pub fn send(&self, msg: Message) {
+        let _ = self.queue.send(Outgoing::Ready(msg));
+    }
+
+    // Takes place in queue for msg that isn't ready yet, msgs sent later wait for it.
+    fn reserve(&self) -> oneshot::Sender<PreparedMessage> {
+        let (tx, rx) = oneshot::channel();
+        let _ = self.queue.send(Outgoing::Pending(rx));
+        tx
+    }
+}
+
+// Offers file (or directory) once to all recipients and sends them its msg.
+// Returns false if path is neither file nor directory.
+pub fn offer_path(file_path: PathBuf, recipients: Vec<Recipient>) -> bool {
+    let peer_ids: Vec<u64> = recipients
+        .iter()
+        .map(|recipient| recipient.peer_id)
+        .collect();
+
+    if std::fs::metadata(&file_path)
+        .map(|metadata| metadata.is_file())
+        .unwrap_or(false)
+    {
+        let file_id: FileID = rand::thread_rng().gen();
+
+        let file_name: String = file_path
+            .file_name()
+            .unwrap()
+            .to_string_lossy() // Maybe this could be improved.
+            .to_string();
+
+        let file_size: FileSize = std::fs::metadata(&file_path)
+            .map(|metadata| metadata.len())
+            .unwrap_or(0);
+
+        // Hashing big file takes a while, header is sent when it is done.
+        let slots: Vec<_> = recipients.iter().map(Recipient::reserve).collect();
+
+        tokio::task::spawn(async move {
+            let prepared = match hash_file(&file_path).await {
+                Ok(file_hash) => {
+                    OFFERS
+                        .lock()
+                        .unwrap()
+                        .add(file_id, file_path, &peer_ids, unix_time());
+
+                    Ok(UserMessage::FileHeader(
+                        file_name, file_size, file_id, file_hash,
+                    ))
+                }
+                Err(e) => {
+                    error!("Couldn't hash file {}: {}", file_path.display(), e);
+                    Err((
+                        UserMessage::FileHeader(file_name, file_size, file_id, [0; 32]),
+                        format!("Not sent, couldn't read file: {}", e),
+                    ))
+                }
+            };
+
+            for slot in slots {
+                let _ = slot.send(prepared.clone());
+            }
+        });
+
+        true
+    } else if std::fs::metadata(&file_path)
+        .map(|metadata| metadata.is_dir())
+        .unwrap_or(false)
+    {
+        let slots: Vec<_> = recipients.iter().map(Recipient::reserve).collect();
+
+        tokio::task::spawn(async move {
+            let prepared = match share_directory(file_path.clone(), &peer_ids).await {
+                Ok(tree) => Ok(UserMessage::DirectoryHeader(tree)),
+                Err(e) => {
+                    error!("Couldn't share directory {}: {}", file_path.display(), e);
+                    let tree = DirectoryTree {
+                        name: file_path
+                            .file_name()
+                            .map(|name| name.to_string_lossy().to_string())
+                            .unwrap_or_default(),
+                        directories: Vec::new(),
+                        files: Vec::new(),
+                    };
+                    Err((
+                        UserMessage::DirectoryHeader(tree),
+                        format!("Not sent, couldn't read directory: {}", e),
+                    ))
+                }
+            };
+
+            for slot in slots {
+                let _ = slot.send(prepared.clone());
+            }
+        });
+
+        true
+    } else {
+        false
+    }
+}
+
+// Create new peer state from incoming connection, pending if user wants to be asked first
+// or peer isn't on allowlist in strict mode.
 impl From<ConnectionData> for PeerState<'_> {
     fn from(connection_data: ConnectionData) -> Self {
+        let refused = ACCESS_LIST
+            .lock()
+            .unwrap()
+            .is_refused(connection_data.peer_id);
+
+        PeerState::new(
+            connection_data,
+            !settings().ask_before_connecting && !refused,
+        )
+    }
+}
+
+impl PeerState<'_> {
+    pub fn new(connection_data: ConnectionData, accepted: bool) -> Self {
+        let accepted = Arc::new(AtomicBool::new(accepted));
         let conversation_buffer: Arc<Mutex<Vec<MessageContext>>> = Arc::new(Vec::new().into());
 
         let (rx_stream, tx_stream) = connection_data.stream.into_split();
         let (tx_queue, rx_queue) = mpsc::unbounded_channel::<Message>();
+        let (tx_user_queue, rx_user_queue) = mpsc::unbounded_channel::<Outgoing>();
+        let (tx_bulk, rx_bulk) = mpsc::unbounded_channel::<mpsc::Receiver<FilePacket>>();
 
         let downloaded_files = Arc::new(Mutex::new(HashMap::new()));
-        let owned_files = Arc::new(Mutex::new(HashMap::new()));
+        let partial_files = Arc::new(Mutex::new(HashMap::new()));
+        let limits = PeerLimits::default();
+
+        let trust = KNOWN_PEERS.lock().unwrap().check(
+            connection_data.peer_id,
+            &connection_data.peer_name,
+            &connection_data.peer_key,
+        );
+
+        let download_connections = Arc::new(AtomicUsize::new(settings().transfer.connections));
+
+        let downloads = Downloads {
+            requests: tx_queue.clone(),
+            downloaded_files: downloaded_files.clone(),
+            partial_files: partial_files.clone(),
+            throttle: limits.download_throttle(),
+            connections: download_connections.clone(),
+            peer_id: connection_data.peer_id,
+        };
 
         let message_reader_handle = tokio::task::spawn(message_reader(
             rx_stream,
+            connection_data.peer_id,
+            connection_data.peer_name.clone(),
+            connection_data.peer_address,
+            trust.clone(),
+            tx_bulk,
+            conversation_buffer.clone(),
+            downloads,
+            accepted.clone(),
+            limits.upload_throttle(),
+        ));
+
+        tokio::task::spawn(user_message_sequencer(
+            rx_user_queue,
             tx_queue.clone(),
             conversation_buffer.clone(),
-            downloaded_files.clone(),
-            owned_files.clone(),
         ));
 
         let message_writer_handle = tokio::task::spawn(message_writer(
             tx_stream,
             conversation_buffer.clone(),
             rx_queue,
+            rx_bulk,
         ));
 
+        if let TrustStatus::KeyChanged(reason) = &trust {
+            warn!(
+                "Possible impersonation by {}: {}",
+                connection_data.peer_address, reason
+            );
+        }
+
         PeerState {
+            id: connection_data.peer_id,
+            identity_key: connection_data.peer_key,
             name: connection_data.peer_name,
+            trust,
             addr: connection_data.peer_address,
+            initiator: connection_data.initiator,
             render_cache: None,
             is_connected: true,
+            accepted,
             messages: ListComponent::new(ListBegin::Bottom, ListTop::Last),
             editor: TextArea::default(),
             editor_mode: EditorMode::Text,
             downloaded_files,
-            owned_files,
+            partial_files,
+            limits,
+            download_connections,
+            marked: false,
             conversation_buffer,
             message_writer_queue: tx_queue,
+            user_queue: tx_user_queue,
             message_writer_handle,
             message_reader_handle,
         }
@@ -316,41 +985,154 @@ impl From<ConnectionData> for PeerState<'_> {
 //      ASYNC FUNCTIONS UPDATING STATE IN BACKGROUND
 
 // Function responsible for reading incoming msgs in the background.
+#[allow(clippy::too_many_arguments)]
 async fn message_reader(
-    mut stream: tokio::net::tcp::OwnedReadHalf,
-    tx_message: mpsc::UnboundedSender<Message>,
+    mut stream: SecureReadHalf,
+    peer_id: u64,
+    peer_name: String,
+    peer_addr: SocketAddr,
+    trust: TrustStatus,
+    tx_bulk: mpsc::UnboundedSender<mpsc::Receiver<FilePacket>>,
     msgs: Arc<Mutex<Vec<MessageContext>>>,
-    downloaded_files: DownloadedFilesMap,
-    owned_files: OwnedFilesMap,
+    downloads: Downloads,
+    accepted: Arc<AtomicBool>,
+    upload_throttle: Throttle,
 ) -> Result<(), StreamSerializerError> {
+    // Running uploads, steered by peer with acks, pauses and cancels.
+    let mut uploads = RunningUploads::default();
+
+    // Buffer for next chunk frame, downloader gives it back to pool after writing it.
+    let chunk_size = settings().transfer.chunk_size(&settings().frame_limits);
+    let mut chunk = CHUNK_POOL.take(chunk_size);
+
     loop {
-        let message = Message::read(&mut stream).await?;
+        let frame = match stream.read_msg_or_chunk::<Message>(&mut chunk).await {
+            Ok(frame) => frame,
+            Err(e) => {
+                // Connection is dropped, writer will be stopped in update.
+                warn!("Dropping connection, couldn't read message: {:?}", e);
+                downloads.downloaded_files.lock().unwrap().clear(); // Downloads end as interrupted.
+                return Err(e);
+            }
+        };
         info!("Message received via tcp!");
+
+        if !accepted.load(Ordering::Relaxed) {
+            info!("Ignoring message from peer that wasn't accepted yet!");
+            continue;
+        }
+
+        let message = match frame {
+            Frame::Msg(message) => message,
+            Frame::Chunk => {
+                let tx = downloads
+                    .downloaded_files
+                    .lock()
+                    .unwrap()
+                    .get(&chunk.file_id())
+                    .cloned();
+
+                // Slow disk stops reading from socket, so sender slows down too.
+                if let Some(tx) = tx {
+                    let full = std::mem::replace(&mut chunk, CHUNK_POOL.take(chunk_size));
+                    let _ = tx.send(FilePacket::Chunk(full)).await;
+                }
+                continue;
+            }
+        };
+
         match message {
             Message::User(user_message) => {
+                let loading_bar =
+                    auto_download(&user_message, peer_id, &peer_name, &trust, &downloads);
+
                 msgs.lock().unwrap().push(MessageContext {
                     was_received: true,
                     message: user_message,
+                    loading_bar,
+                    error: None,
                 });
             }
             Message::Internal(internal_message) => match internal_message {
-                InternalMessage::FileRequest(id) => {
-                    if let Some(file_path) = owned_files.lock().unwrap().get(&id) {
-                        tokio::task::spawn(file_uploader(
-                            tx_message.clone(),
-                            file_path.clone(),
-                            id,
-                        ));
-                    }
-                }
-                InternalMessage::FileContent(id, _, _) => {
-                    if let Some(tx) = downloaded_files.lock().unwrap().get(&id) {
-                        let _ = tx.send(internal_message);
+                InternalMessage::FileRequest(id, ..)
+                | InternalMessage::FileRangeRequest(id, ..) => {
+                    // Every upload gets its own stream, writer serves them in turns.
+                    let (tx_stream, rx_stream) = mpsc::channel(settings().transfer.window());
+                    let _ = tx_bulk.send(rx_stream);
+
+                    let offer = OFFERS
+                        .lock()
+                        .unwrap()
+                        .lookup(peer_id, id, unix_time())
+                        .map(Path::to_path_buf);
+
+                    let file_path = match offer {
+                        Ok(file_path) => file_path,
+                        Err(e) => {
+                            info!("Refused request of file {}: {}", id, e);
+                            let _ = tx_stream.try_send(FilePacket::Internal(
+                                InternalMessage::FileContentError(id, e),
+                            ));
+                            continue;
+                        }
+                    };
+
+                    let control = Arc::new(
+                        UploadControl::new(settings().transfer.window())
+                            .throttled(upload_throttle.clone()),
+                    );
+                    uploads.insert(id, control.clone());
+
+                    transfers::register(Transfer::new(
+                        TransferDirection::Upload(id, control.clone()),
+                        peer_id,
+                        peer_name.clone(),
+                        file_path
+                            .file_name()
+                            .unwrap_or_default()
+                            .to_string_lossy()
+                            .to_string(),
+                        file_path.parent().unwrap_or(&file_path).to_path_buf(),
+                        control.loading_bar.clone(),
+                    ));
+
+                    match internal_message {
+                        InternalMessage::FileRangeRequest(_, port, token, ranges) => {
+                            tokio::task::spawn(range_uploader(
+                                tx_stream,
+                                control,
+                                file_path,
+                                id,
+                                peer_id,
+                                SocketAddr::new(peer_addr.ip(), port),
+                                token,
+                                ranges,
+                            ));
+                        }
+                        InternalMessage::FileRequest(_, offset, prefix_hash) => {
+                            tokio::task::spawn(file_uploader(
+                                tx_stream,
+                                control,
+                                file_path,
+                                id,
+                                offset,
+                                prefix_hash,
+                            ));
+                        }
+                        _ => unreachable!(),
                     }
                 }
-                InternalMessage::FileContentError(id, _) => {
-                    if let Some(tx) = downloaded_files.lock().unwrap().get(&id) {
-                        let _ = tx.send(internal_message);
+                // Peer steers our upload, or (below) tells us about its upload.
+                internal_message if uploads.handle(&internal_message) => {}
+                InternalMessage::FileAck(..) => {} // Upload already ended.
+                InternalMessage::FileContentError(id, _)
+                | InternalMessage::FileCancel(id)
+                | InternalMessage::FilePause(id)
+                | InternalMessage::FileResume(id) => {
+                    let tx = downloads.downloaded_files.lock().unwrap().get(&id).cloned();
+
+                    if let Some(tx) = tx {
+                        let _ = tx.send(FilePacket::Internal(internal_message)).await;
                     }
                 }
             },
@@ -358,175 +1140,160 @@ async fn message_reader(
     }
 }
 
-// Function responsible for sending msgs in the background.
-async fn message_writer(
-    mut stream: tokio::net::tcp::OwnedWriteHalf,
-    msgs: Arc<Mutex<Vec<MessageContext>>>,
-    mut msg_queue: mpsc::UnboundedReceiver<Message>,
-) -> Result<(), StreamSerializerError> {
-    loop {
-        match msg_queue.recv().await {
-            Some(message) => {
-                message.send(&mut stream).await?;
-                info!("Message sended via tcp!");
+// Starts download of file msg matching auto download rules, returns its loading bar.
+fn auto_download(
+    message: &UserMessage,
+    peer_id: u64,
+    peer_name: &str,
+    trust: &TrustStatus,
+    downloads: &Downloads,
+) -> Option<Arc<Mutex<LoadingBarWrap>>> {
+    let UserMessage::FileHeader(file_name, file_size, file_id, file_hash) = message else {
+        return None;
+    };
 
-                if let Message::User(message) = message {
+    let download_dir = auto_download_folder(
+        &settings().auto_download,
+        peer_id,
+        &current_trust(peer_id, trust),
+        file_name,
+        *file_size,
+    )?;
+
+    info!(
+        "Downloading {} from {} to {} without asking",
+        file_name,
+        peer_name,
+        download_dir.display()
+    );
+
+    // Loading bar will be loaded later, those are placeholder values.
+    let loading_bar = Arc::new(Mutex::new(LoadingBarWrap {
+        loadingbar: LoadingBar::Status(LoadingBarStatus::new(0, 1)),
+        changed: true,
+    }));
+
+    transfers::register(Transfer::new(
+        TransferDirection::Download,
+        peer_id,
+        peer_name.to_string(),
+        file_name.clone(),
+        download_dir.clone(),
+        loading_bar.clone(),
+    ));
+
+    downloads.start_file(
+        *file_id,
+        file_name.clone(),
+        *file_size,
+        *file_hash,
+        loading_bar.clone(),
+        download_dir,
+    );
+
+    Some(loading_bar)
+}
+
+// Passes user msgs to writer in order they were sent, file headers still being hashed hold up the rest.
+// Msgs that couldn't be prepared are only shown to us, with the reason.
+async fn user_message_sequencer(
+    mut queue: mpsc::UnboundedReceiver<Outgoing>,
+    writer_queue: mpsc::UnboundedSender<Message>,
+    msgs: Arc<Mutex<Vec<MessageContext>>>,
+) {
+    while let Some(outgoing) = queue.recv().await {
+        let message = match outgoing {
+            Outgoing::Ready(message) => message,
+            Outgoing::Pending(prepared) => match prepared.await {
+                Ok(Ok(message)) => Message::User(message),
+                Ok(Err((message, error))) => {
                     msgs.lock().unwrap().push(MessageContext {
                         was_received: false,
                         message,
+                        loading_bar: None,
+                        error: Some(error),
                     });
+                    continue;
                 }
-            }
-            None => {
-                break Ok(());
-            }
+                Err(_) => continue,
+            },
+        };
+
+        if writer_queue.send(message).is_err() {
+            break;
         }
     }
 }
 
-// Function responsible for downloading given file in the background.
-async fn file_downloader(
-    mut packets: mpsc::UnboundedReceiver<InternalMessage>,
-    file_id: FileID,
-    file_name: String,
-    file_size: FileSize,
-    loading_bar: Arc<Mutex<LoadingBarWrap>>,
-    downloaded_files: DownloadedFilesMap,
-) {
-    let mut file_path = DOWNLOAD_PATH.clone();
-    file_path.push(&file_name);
-
-    *loading_bar.lock().unwrap() = LoadingBarWrap {
-        loadingbar: LoadingBar::Status(LoadingBarStatus {
-            position: 0,
-            end: file_size,
-        }),
-        changed: true,
-    };
-
-    let mut byte_cnt = 0;
-
-    // If files [name, name (1), ..., name (9)] exists in download dir, abandon download.
-    'main: for i in 0..10 {
-        let mut file = match tokio::fs::OpenOptions::new()
-            .create_new(true) // Ensures the file doesn't already exist
-            .write(true)
-            .open(&file_path)
-            .await
-        {
-            Ok(file) => file,
-            Err(_) => {
-                file_path = DOWNLOAD_PATH.clone();
-                file_path.push(format!("{} ({})", file_name, i + 1));
+// Function responsible for sending msgs in the background.
+// Chat and control msgs always go first, file content from uploads is sent in turns.
+async fn message_writer(
+    mut stream: SecureWriteHalf,
+    msgs: Arc<Mutex<Vec<MessageContext>>>,
+    mut msg_queue: mpsc::UnboundedReceiver<Message>,
+    mut new_bulk_streams: mpsc::UnboundedReceiver<mpsc::Receiver<FilePacket>>,
+) -> Result<(), StreamSerializerError> {
+    let mut bulk_streams = BulkStreams::new();
 
+    loop {
+        let message = tokio::select! {
+            biased;
+            message = msg_queue.recv() => message,
+            Some(bulk_stream) = new_bulk_streams.recv() => {
+                bulk_streams.push(bulk_stream);
                 continue;
             }
+            packet = bulk_streams.next() => match packet {
+                FilePacket::Internal(message) => Some(Message::Internal(message)),
+                FilePacket::Chunk(mut chunk) => {
+                    stream.send_chunk(&mut chunk).await?;
+                    CHUNK_POOL.give(chunk);
+                    continue;
+                }
+            },
         };
 
-        while let Some(packet) = packets.recv().await {
-            match packet {
-                InternalMessage::FileContent(_, byte_idx, bytes) => {
-                    if byte_idx != byte_cnt || byte_idx + bytes.len() as FileSize > file_size {
-                        break 'main;
-                    }
-
-                    byte_cnt += bytes.len() as FileSize;
-
-                    // For some reason writing drop explicitly doesnt work. Have to use {} instead.
-                    {
-                        let mut loading_bar_lock = loading_bar.lock().unwrap();
-
-                        if let LoadingBar::Status(LoadingBarStatus { position, .. }) =
-                            &mut loading_bar_lock.loadingbar
-                        {
-                            *position = byte_cnt;
-                            loading_bar_lock.changed = true;
+        match message {
+            Some(message) => {
+                match stream.send(&message).await {
+                    // Peer would drop connection on such msg, so it is not sent at all.
+                    Err(StreamSerializerError::InvalidFrame(e)) => {
+                        error!("Message not sent: {:?}", e);
+
+                        if let Message::User(message) = message {
+                            let error = match e {
+                                FrameError::TooLarge { size, limit } => {
+                                    format!("Not sent, too big ({} > {} bytes)", size, limit)
+                                }
+                                FrameError::Malformed(_) => "Not sent".to_string(),
+                            };
+                            msgs.lock().unwrap().push(MessageContext {
+                                was_received: false,
+                                message,
+                                loading_bar: None,
+                                error: Some(error),
+                            });
                         }
+                        continue;
                     }
-
-                    if let Err(e) = file.write_all(&bytes).await {
-                        *loading_bar.lock().unwrap() = LoadingBarWrap {
-                            loadingbar: LoadingBar::Error(e.to_string()),
-                            changed: true,
-                        };
-
-                        break 'main;
-                    }
+                    result => result?,
                 }
-                InternalMessage::FileContentError(_, e) => {
-                    *loading_bar.lock().unwrap() = LoadingBarWrap {
-                        loadingbar: LoadingBar::Error(e),
-                        changed: true,
-                    };
+                info!("Message sended via tcp!");
 
-                    break 'main;
+                if let Message::User(message) = message {
+                    msgs.lock().unwrap().push(MessageContext {
+                        was_received: false,
+                        message,
+                        loading_bar: None,
+                        error: None,
+                    });
                 }
-                _ => {}
             }
-
-            if byte_cnt == file_size {
-                break 'main;
+            None => {
+                break Ok(());
             }
         }
     }
-
-    if byte_cnt != file_size {
-        *loading_bar.lock().unwrap() = LoadingBarWrap {
-            loadingbar: LoadingBar::Error(format!(
-                "Download error! Status: {}/{}",
-                byte_cnt, file_size
-            )),
-            changed: true,
-        };
-    }
-
-    // Clean map after yourself.
-    let _ = downloaded_files.lock().unwrap().remove(&file_id);
-}
-
-// Function responsible for uploading given file in the background.
-async fn file_uploader(
-    packets: mpsc::UnboundedSender<Message>,
-    file_name: PathBuf,
-    file_id: FileID,
-) {
-    // Open the file in read-only mode
-    let Ok(mut file) = tokio::fs::File::open(file_name).await else {
-        let _ = packets.send(Message::Internal(InternalMessage::FileContentError(
-            file_id,
-            "File does not exsists anymore!".to_string(),
-        )));
-        return;
-    };
-
-    let mut buffer = vec![0; 4096]; // Buffer size 4096 bytes
-
-    let mut byte_idx = 0;
-
-    loop {
-        let Ok(n) = file.read(&mut buffer).await else {
-            let _ = packets.send(Message::Internal(InternalMessage::FileContentError(
-                file_id,
-                "Error reading file!".to_string(),
-            )));
-            return;
-        };
-
-        if n == 0 {
-            break; // End of file
-        }
-
-        // Trim the buffer to the size of the data read
-        let chunk = buffer[..n].to_vec();
-
-        // Send the chunk through the sender
-        let message = Message::Internal(InternalMessage::FileContent(file_id, byte_idx, chunk));
-        byte_idx += n as FileSize;
-
-        if packets.send(message).is_err() {
-            break;
-        }
-    }
 }
 
 //      FUNCTIONS RELATED TO RENDERING
@@ -552,6 +1319,22 @@ impl PeerState<'_> {
 
     // Render conversation.
     fn render_conv(&mut self, rect: &mut Rect, buf: &mut Buffer, is_active: bool) {
+        let title = match &self.trust {
+            TrustStatus::KeyChanged(reason) => Line::from(vec![
+                Span::raw(format!("Conversation with {}: ", &self.name)),
+                Span::styled(
+                    format!("⚠ WARNING: {}! Check peer details! ⚠", reason),
+                    Style::default()
+                        .fg(Color::Red)
+                        .add_modifier(ratatui::style::Modifier::BOLD),
+                ),
+            ]),
+            TrustStatus::Verified => {
+                Line::from(format!("Conversation with {} (verified):", &self.name))
+            }
+            _ => Line::from(format!("Conversation with {}:", &self.name)),
+        };
+
         let block = Block::default()
             .borders(Borders::ALL)
             .border_style(if is_active {
@@ -559,7 +1342,7 @@ impl PeerState<'_> {
             } else {
                 Style::default()
             })
-            .title(format!("Conversation with {}:", &self.name));
+            .title(title);
 
         let conv_rect = block.inner(*rect);
 
@@ -587,6 +1370,61 @@ impl PeerState<'_> {
         Widget::render(&self.editor, *block, buf);
     }
 
+    // Render peer identity together with authentication string.
+    pub fn render_details(&mut self, rect: &mut Rect, buf: &mut Buffer) {
+        let own_key = IDENTITY.signing_key.verifying_key().to_bytes();
+
+        let (status, status_style) = match &self.trust {
+            TrustStatus::New => (
+                "First connection, key remembered".to_string(),
+                Style::default(),
+            ),
+            TrustStatus::Known => ("Known key, not verified".to_string(), Style::default()),
+            TrustStatus::Verified => ("Verified".to_string(), Style::default().fg(Color::Green)),
+            TrustStatus::KeyChanged(reason) => (
+                format!("⚠ {} ⚠", reason),
+                Style::default()
+                    .fg(Color::Red)
+                    .add_modifier(ratatui::style::Modifier::BOLD),
+            ),
+        };
+
+        let lines = vec![
+            Line::from(format!("Name:        {}", &self.name)),
+            Line::from(format!("Id:          {:016x}", self.id)),
+            Line::from(format!("Address:     {}", self.addr)),
+            Line::from(format!("Fingerprint: {}", fingerprint(&self.identity_key))),
+            Line::from(vec![
+                Span::raw("Status:      "),
+                Span::styled(status, status_style),
+            ]),
+            Line::from(""),
+            Line::from(vec![
+                Span::raw("Authentication string: "),
+                Span::styled(
+                    authentication_string(&own_key, &self.identity_key),
+                    Style::default().add_modifier(ratatui::style::Modifier::BOLD),
+                ),
+            ]),
+            Line::from(""),
+            Line::from(format!(
+                "Compare this string with {} (not via this chat). If it matches, press 'v' to mark peer as verified.",
+                &self.name
+            )),
+            Line::from("Press 'Esc' to go back."),
+        ];
+
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .border_style(Style::default().fg(Color::Green))
+            .title(format!("Details of {}:", &self.name));
+
+        Paragraph::new(lines)
+            .block(block)
+            .wrap(Wrap { trim: false })
+            .render(*rect, buf);
+    }
+
     // Render if no peer was choosen.
     pub fn render_empty(block: &mut Rect, buf: &mut Buffer) {
         let block2 = Block::default()
@@ -611,8 +1449,30 @@ impl<'a> ListItem<'a> for PeerState<'a> {
 
         let bottom_address_length = UnicodeWidthStr::width(self.addr.to_string().as_str())
             .min(window_max_width as usize - 2);
+        let name = {
+            let access_list = ACCESS_LIST.lock().unwrap();
+
+            if access_list.is_blocked(self.id) {
+                format!("{} (blocked)", self.name)
+            } else if self.is_pending() && access_list.is_refused(self.id) {
+                format!("{} (not allowlisted, w: allow, r: reject)", self.name)
+            } else if self.is_pending() {
+                format!("{} (a: accept, r: reject)", self.name)
+            } else if access_list.is_allowlisted(self.id) {
+                format!("{} (allowlisted)", self.name)
+            } else {
+                self.name.clone()
+            }
+        } + &self.limits_label();
+
+        // Recipients of broadcast.
+        let name = match self.marked {
+            true => format!("[x] {}", name),
+            false => name,
+        };
+
         let middle_name_length =
-            UnicodeWidthStr::width(self.name.as_str()).min(window_max_width as usize - 2);
+            UnicodeWidthStr::width(name.as_str()).min(window_max_width as usize - 2);
         let bottom_address: String = format!(
             "{:─<width$}",
             &self.addr.to_string()[..bottom_address_length],
@@ -620,11 +1480,13 @@ impl<'a> ListItem<'a> for PeerState<'a> {
         );
         let middle_name: String = format!(
             "{: <width$}",
-            &self.name[..middle_name_length],
+            &name[..middle_name_length],
             width = window_max_width as usize - 2
         );
 
-        let fg_color = if self.is_connected {
+        let fg_color = if self.is_connected && self.is_pending() {
+            Color::Yellow
+        } else if self.is_connected {
             Color::LightGreen
         } else {
             Color::LightRed
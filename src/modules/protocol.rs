@@ -1,29 +1,71 @@
 use async_trait::async_trait;
-use bincode::{deserialize, serialize};
+use bincode::{serialize, Options};
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey, SIGNATURE_LENGTH};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::HashMap;
 use std::net::AddrParseError;
+use std::time::{SystemTime, UNIX_EPOCH};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
-use crate::config::UNIQUE_BYTES;
+use crate::config::{settings, DISCOVERY_MAX_AGE, UNIQUE_BYTES};
+use crate::modules::encryption::ChunkBuffer;
+use crate::modules::identity::user_id_from_key;
 
 pub type FileSize = u64;
 pub type FileID = u64;
+pub type FileHash = [u8; 32]; // SHA-256 of file content.
 
 /// Struct with content of user message.
 /// This message type is the type that will be displayed.
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum UserMessage {
     Text(String),
-    FileHeader(String, FileSize, FileID), // Filename, filesize, file-id
+    FileHeader(String, FileSize, FileID, FileHash), // Filename, filesize, file-id, content hash
+    DirectoryHeader(DirectoryTree),
+}
+
+/// Directory shared as a whole, paths are relative to directory and split into components.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DirectoryTree {
+    pub name: String,
+    pub directories: Vec<Vec<String>>, // All subdirectories, so empty ones are recreated too.
+    pub files: Vec<DirectoryEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DirectoryEntry {
+    pub path: Vec<String>,
+    pub size: FileSize,
+    pub id: FileID,
+    pub hash: FileHash,
+}
+
+impl DirectoryTree {
+    pub fn total_size(&self) -> FileSize {
+        self.files
+            .iter()
+            .fold(0, |total: FileSize, file| total.saturating_add(file.size))
+    }
 }
 
 /// Struct with content of internal message.
 /// This message type is the type that is exchanged in background.
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum InternalMessage {
-    FileRequest(FileID),                    // File-id
-    FileContent(FileID, FileSize, Vec<u8>), // File-id, first byte idx, bytes
+    FileRequest(FileID, FileSize, FileHash), // File-id, start offset, hash of bytes before offset
     FileContentError(FileID, String),
+    FileAck(FileID, u32), // File-id, number of chunks written since last ack
+    FileCancel(FileID),   // Sent by either side, transfer is stopped and partial file deleted
+    FilePause(FileID),
+    FileResume(FileID),
+    FileRangeRequest(FileID, u16, u64, Vec<(FileSize, FileSize)>), // File-id, port, token, ranges
+}
+
+/// File data or control msg of one transfer, passed between connection and transfer tasks.
+/// File data is sent in chunk frames instead of msgs, so its buffers are reused.
+pub enum FilePacket {
+    Internal(InternalMessage),
+    Chunk(ChunkBuffer),
 }
 
 /// Main message structure.
@@ -39,18 +81,93 @@ pub enum Message {
 pub struct UserDiscovery {
     pub port: u16,
     pub user_id: u64,
+    pub identity_key: [u8; 32], // Ed25519 public key, user_id is derived from it.
+    pub timestamp: u64,         // Unix time of sending in seconds.
+    pub nonce: u64,             // Random value, together with timestamp protects from replays.
+    pub private_room: bool, // Sender uses passphrase, which one is checked only during handshake.
 }
 
 /// Struct that is being is send once at the begining of connection.
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct ConnectionInfo {
     pub user_name: String,
-    // Add id, currently under some conditions, user can have auto 2 conversations with same person.
+    pub user_id: u64,
+    pub identity_key: [u8; 32], // Has to match key used in handshake.
+}
+
+/// First msg on data connection of parallel download, after handshake.
+/// Token was sent in FileRangeRequest, so only the peer asked for file knows it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RangeHello {
+    pub token: u64,
+    pub range: u32, // Index of range sent on this connection.
+}
+
+/// First msg of handshake, exchanged in plain text right after tcp connect.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct HandshakeHello {
+    pub identity_key: [u8; 32],     // Ed25519 public key of sender.
+    pub ephemeral_key: [u8; 32],    // X25519 public key used only for this connection.
+    pub pake_key: Option<[u8; 32]>, // SPAKE2 msg, present only in private room.
+}
+
+/// Second msg of handshake, already encrypted. Proves ownership of identity key.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct HandshakeAuth {
+    pub signature: Vec<u8>, // Ed25519 signature over handshake transcript.
+}
+
+/// Remembers recently seen discovery packets, so that they can't be replayed.
+#[derive(Default)]
+pub struct DiscoveryReplayGuard {
+    seen: HashMap<(u64, u64), u64>, // (user id, nonce) -> timestamp
+}
+
+impl DiscoveryReplayGuard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Accepts packet only if it is fresh and wasn't seen before.
+    fn check(&mut self, discovery: &UserDiscovery) -> Result<(), StreamSerializerError> {
+        let now = unix_time();
+
+        if now.abs_diff(discovery.timestamp) > DISCOVERY_MAX_AGE {
+            return Err("Discovery packet outside of time window!".into());
+        }
+
+        // Packets older than window would be rejected anyway.
+        self.seen
+            .retain(|_, timestamp| now.abs_diff(*timestamp) <= DISCOVERY_MAX_AGE);
+
+        if self
+            .seen
+            .insert((discovery.user_id, discovery.nonce), discovery.timestamp)
+            .is_some()
+        {
+            return Err("Discovery packet replayed!".into());
+        }
+
+        Ok(())
+    }
+}
+
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
 }
 
 impl UserDiscovery {
-    // Packet format: UNIQUE_BYTES, 8 bytes of msg len, msg
-    pub fn to_packet(&self) -> Result<Vec<u8>, StreamSerializerError> {
+    // Whether sender can be in the same room as we are (both in none or both use passphrase).
+    pub fn may_be_in_room(&self, private_room: bool) -> bool {
+        self.private_room == private_room
+    }
+
+    // Packet format: UNIQUE_BYTES, 8 bytes of msg len, msg, 64 bytes of signature.
+    // Signature covers everything before it.
+    pub fn to_packet(&self, signing_key: &SigningKey) -> Result<Vec<u8>, StreamSerializerError> {
         let msg_data = serialize(self)?;
         let msg_len = (msg_data.len() as u64).to_be_bytes();
 
@@ -60,12 +177,18 @@ impl UserDiscovery {
         packet.extend_from_slice(&msg_len);
         packet.extend(msg_data);
 
+        let signature = signing_key.sign(&packet);
+        packet.extend_from_slice(&signature.to_bytes());
+
         assert!(packet.len() < 4048); // Make sure it fits in one packet.
         Ok(packet)
     }
 
-    pub fn from_packet(packet: Vec<u8>) -> Result<Self, StreamSerializerError> {
-        if packet.len() < UNIQUE_BYTES.len() + 8 {
+    pub fn from_packet(
+        packet: Vec<u8>,
+        replay_guard: &mut DiscoveryReplayGuard,
+    ) -> Result<Self, StreamSerializerError> {
+        if packet.len() < UNIQUE_BYTES.len() + 8 + SIGNATURE_LENGTH {
             return Err("Discovery packet too short!".into());
         }
 
@@ -82,27 +205,122 @@ impl UserDiscovery {
 
         buff_idx += 8;
 
-        if packet.len() - buff_idx != msg_len {
+        if packet.len() - buff_idx - SIGNATURE_LENGTH != msg_len {
             return Err("Discovery packet incorrect length!".into());
         }
 
         // Read UserDiscovery struct.
-        let data: UserDiscovery = bincode::deserialize(&packet[buff_idx..(buff_idx + msg_len)])?;
+        let data: UserDiscovery =
+            frame_options(msg_len as u64).deserialize(&packet[buff_idx..(buff_idx + msg_len)])?;
+
+        let identity_key = VerifyingKey::from_bytes(&data.identity_key)?;
+        if data.user_id != user_id_from_key(&identity_key) {
+            return Err("Discovery user id doesn't match identity key!".into());
+        }
+
+        let (signed_part, signature) = packet.split_at(buff_idx + msg_len);
+        identity_key.verify_strict(signed_part, &Signature::from_slice(signature)?)?;
+
+        // Checked only after signature, so forged packets can't fill the guard.
+        replay_guard.check(&data)?;
 
         Ok(data)
     }
 }
 
+/// Maximal size of serialized struct, checked before buffer for incoming frame is allocated.
+/// Protects from peers announcing huge frames in order to exhaust our memory.
+pub trait FrameLimit {
+    fn frame_limit() -> u64;
+}
+
+impl FrameLimit for Message {
+    fn frame_limit() -> u64 {
+        settings().frame_limits.message
+    }
+}
+
+impl FrameLimit for ConnectionInfo {
+    fn frame_limit() -> u64 {
+        settings().frame_limits.connection_info
+    }
+}
+
+impl FrameLimit for RangeHello {
+    fn frame_limit() -> u64 {
+        settings().frame_limits.connection_info
+    }
+}
+
+impl FrameLimit for HandshakeHello {
+    fn frame_limit() -> u64 {
+        settings().frame_limits.handshake
+    }
+}
+
+impl FrameLimit for HandshakeAuth {
+    fn frame_limit() -> u64 {
+        settings().frame_limits.handshake
+    }
+}
+
+// Same encoding as bincode::serialize, but with limit on bytes used during deserialization.
+// Limit makes sure that length fields inside of frame can't trigger huge allocations.
+fn frame_options(limit: u64) -> impl Options {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .with_limit(limit)
+}
+
+/// Checks length of frame against limit.
+pub fn check_frame_len(frame_len: u64, limit: u64) -> Result<(), StreamSerializerError> {
+    if frame_len > limit {
+        return Err(StreamSerializerError::InvalidFrame(FrameError::TooLarge {
+            size: frame_len,
+            limit,
+        }));
+    }
+
+    Ok(())
+}
+
+/// Serialize msg, fails if peer would refuse it because of its size.
+pub fn encode_frame<T: Serialize + FrameLimit>(msg: &T) -> Result<Vec<u8>, StreamSerializerError> {
+    let mut data = Vec::new();
+    encode_frame_into(msg, &mut data)?;
+
+    Ok(data)
+}
+
+/// Serialize msg into reused buffer, previous content is removed.
+pub fn encode_frame_into<T: Serialize + FrameLimit>(
+    msg: &T,
+    buffer: &mut Vec<u8>,
+) -> Result<(), StreamSerializerError> {
+    buffer.clear();
+    frame_options(u64::MAX).serialize_into(&mut *buffer, msg)?;
+    check_frame_len(buffer.len() as u64, T::frame_limit())
+}
+
+/// Deserialize msg, whole frame has to be consumed.
+pub fn decode_frame<T: DeserializeOwned + FrameLimit>(
+    data: &[u8],
+) -> Result<T, StreamSerializerError> {
+    frame_options(T::frame_limit())
+        .deserialize(data)
+        .map_err(|e| StreamSerializerError::InvalidFrame(FrameError::Malformed(e)))
+}
+
 // Auto implement stream serialization for all possible structs.
-impl<T> StreamSerialization for T where T: Serialize + DeserializeOwned {}
+impl<T> StreamSerialization for T where T: Serialize + DeserializeOwned + FrameLimit {}
 
 #[async_trait]
-pub trait StreamSerialization: Serialize + DeserializeOwned {
+pub trait StreamSerialization: Serialize + DeserializeOwned + FrameLimit {
     async fn send<S: AsyncWriteExt + Unpin + Send>(
         &self,
         stream: &mut S,
     ) -> Result<(), StreamSerializerError> {
-        let msg_data = serialize(self)?;
+        let msg_data = encode_frame(self)?;
         let msg_len = (msg_data.len() as u64).to_be_bytes();
 
         stream.write_all(&msg_len).await?;
@@ -118,20 +336,31 @@ pub trait StreamSerialization: Serialize + DeserializeOwned {
         stream.read_exact(&mut msg_len_buff).await?;
         let msg_len = u64::from_be_bytes(msg_len_buff);
 
+        check_frame_len(msg_len, Self::frame_limit())?;
+
         let mut msg_data_buff: Vec<u8> = vec![0; msg_len as usize];
         stream.read_exact(&mut msg_data_buff).await?;
-        let msg = deserialize::<Self>(&msg_data_buff)?;
+        let msg = decode_frame::<Self>(&msg_data_buff)?;
 
         Ok(msg)
     }
 }
 
+/// Reason of rejecting frame.
+#[derive(Debug)]
+pub enum FrameError {
+    TooLarge { size: u64, limit: u64 },
+    Malformed(bincode::Error),
+}
+
 #[derive(Debug)]
 pub enum StreamSerializerError {
     Io(std::io::Error),
     Bincode(bincode::Error),
     StrError(String),
     AddrParse(AddrParseError), // Possible only when parsing multicast addr. Left here for convinience.
+    Crypto(String), // Failed handshake, bad signature or frame that could not be decrypted.
+    InvalidFrame(FrameError), // Frame from peer exceeded limit or couldn't be parsed.
 }
 
 // Implement `From` trait to automatically convert `std::io::Error` to `StreamSerializerError`
@@ -161,3 +390,17 @@ impl From<AddrParseError> for StreamSerializerError {
         StreamSerializerError::AddrParse(err)
     }
 }
+
+// Implementing `From<ed25519_dalek::SignatureError>` for automatic conversion
+impl From<ed25519_dalek::SignatureError> for StreamSerializerError {
+    fn from(err: ed25519_dalek::SignatureError) -> Self {
+        StreamSerializerError::Crypto(err.to_string())
+    }
+}
+
+// Implementing `From<chacha20poly1305::Error>` for automatic conversion
+impl From<chacha20poly1305::Error> for StreamSerializerError {
+    fn from(_: chacha20poly1305::Error) -> Self {
+        StreamSerializerError::Crypto("Frame encryption or decryption failed!".to_string())
+    }
+}
@@ -0,0 +1,43 @@
+use cli_log::*;
+use serde::{de::DeserializeOwned, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::config::CONFIG_PATH;
+
+// Path of state file with given name inside config directory.
+pub fn state_path(file_name: &str) -> Option<PathBuf> {
+    CONFIG_PATH.as_ref().map(|path| path.join(file_name))
+}
+
+/// Reads bincode encoded state from file. Returns None if file is missing or corrupted.
+pub fn load_state<T: DeserializeOwned>(path: &Path) -> Option<T> {
+    let data = std::fs::read(path).ok()?;
+
+    match bincode::deserialize(&data) {
+        Ok(state) => Some(state),
+        Err(e) => {
+            error!("Corrupted state file {}: {:?}", path.display(), e);
+            None
+        }
+    }
+}
+
+/// Writes bincode encoded state to file.
+/// Data is written to temporary file first, so crash during write doesn't corrupt old state.
+pub fn store_state<T: Serialize>(path: &Path, state: &T) {
+    let result = (|| -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let tmp_path = path.with_extension("tmp");
+        std::fs::write(&tmp_path, bincode::serialize(state)?)?;
+        std::fs::rename(&tmp_path, path)?;
+
+        Ok(())
+    })();
+
+    if let Err(e) = result {
+        error!("Couldn't store state file {}: {:?}", path.display(), e);
+    }
+}
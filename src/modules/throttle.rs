@@ -0,0 +1,168 @@
+use humansize::{format_size, DECIMAL};
+use once_cell::sync::Lazy;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::config::settings;
+
+// Limits (in bytes per second) switched between in UI, None means unlimited.
+pub const LIMIT_PRESETS: [Option<u64>; 6] = [
+    None,
+    Some(100_000),
+    Some(500_000),
+    Some(1_000_000),
+    Some(5_000_000),
+    Some(10_000_000),
+];
+
+// Shared by uploads to all peers.
+pub static UPLOAD_LIMIT: Lazy<RateLimiter> =
+    Lazy::new(|| RateLimiter::new(settings().bandwidth.upload_limit));
+
+// Shared by downloads from all peers.
+pub static DOWNLOAD_LIMIT: Lazy<RateLimiter> =
+    Lazy::new(|| RateLimiter::new(settings().bandwidth.download_limit));
+
+struct Bucket {
+    limit: Option<u64>,
+    tokens: f64, // Negative when bytes were taken on credit.
+    last_refill: Instant,
+}
+
+/// Token bucket holding at most one second of traffic. Limit can be changed at any time.
+pub struct RateLimiter {
+    bucket: Mutex<Bucket>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: Option<u64>) -> Self {
+        RateLimiter {
+            bucket: Mutex::new(Bucket {
+                limit: limit.filter(|limit| *limit > 0),
+                tokens: limit.unwrap_or(0) as f64,
+                last_refill: Instant::now(),
+            }),
+        }
+    }
+
+    pub fn limit(&self) -> Option<u64> {
+        self.bucket.lock().unwrap().limit
+    }
+
+    pub fn set_limit(&self, limit: Option<u64>) {
+        let mut bucket = self.bucket.lock().unwrap();
+        let limit = limit.filter(|limit| *limit > 0);
+
+        // Newly limited traffic starts with full bucket, like at startup.
+        bucket.tokens = match bucket.limit {
+            Some(_) => bucket.tokens.min(limit.unwrap_or(0) as f64),
+            None => limit.unwrap_or(0) as f64,
+        };
+        bucket.limit = limit;
+    }
+
+    // Switches to the next limit from LIMIT_PRESETS.
+    pub fn cycle_limit(&self) {
+        let current = LIMIT_PRESETS
+            .iter()
+            .position(|preset| *preset == self.limit())
+            .unwrap_or(0);
+
+        self.set_limit(LIMIT_PRESETS[(current + 1) % LIMIT_PRESETS.len()]);
+    }
+
+    /// Takes bytes from bucket, returns how long caller has to wait before moving them.
+    pub fn take(&self, bytes: usize, now: Instant) -> Duration {
+        let mut bucket = self.bucket.lock().unwrap();
+
+        let elapsed = now.saturating_duration_since(bucket.last_refill);
+        bucket.last_refill = bucket.last_refill.max(now);
+
+        let Some(limit) = bucket.limit else {
+            return Duration::ZERO;
+        };
+
+        bucket.tokens = (bucket.tokens + elapsed.as_secs_f64() * limit as f64).min(limit as f64);
+        bucket.tokens -= bytes as f64;
+
+        match bucket.tokens < 0.0 {
+            true => Duration::from_secs_f64(-bucket.tokens / limit as f64),
+            false => Duration::ZERO,
+        }
+    }
+
+    pub async fn acquire(&self, bytes: usize) {
+        let wait = self.take(bytes, Instant::now());
+
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// Limits of one peer together with global limit for the same direction.
+#[derive(Clone)]
+pub struct Throttle {
+    peer: Arc<RateLimiter>,
+    global: &'static RateLimiter,
+}
+
+impl Throttle {
+    pub fn upload(peer: Arc<RateLimiter>) -> Self {
+        Throttle {
+            peer,
+            global: &UPLOAD_LIMIT,
+        }
+    }
+
+    pub fn download(peer: Arc<RateLimiter>) -> Self {
+        Throttle {
+            peer,
+            global: &DOWNLOAD_LIMIT,
+        }
+    }
+
+    // Waits until both limits let given number of bytes through.
+    pub async fn acquire(&self, bytes: usize) {
+        self.peer.acquire(bytes).await;
+        self.global.acquire(bytes).await;
+    }
+}
+
+/// Upload and download limits of one peer.
+pub struct PeerLimits {
+    pub upload: Arc<RateLimiter>,
+    pub download: Arc<RateLimiter>,
+}
+
+impl Default for PeerLimits {
+    fn default() -> Self {
+        PeerLimits {
+            upload: Arc::new(RateLimiter::new(settings().bandwidth.peer_upload_limit)),
+            download: Arc::new(RateLimiter::new(settings().bandwidth.peer_download_limit)),
+        }
+    }
+}
+
+impl PeerLimits {
+    pub fn upload_throttle(&self) -> Throttle {
+        Throttle::upload(self.upload.clone())
+    }
+
+    pub fn download_throttle(&self) -> Throttle {
+        Throttle::download(self.download.clone())
+    }
+
+    // Limits chosen by user stay after reconnecting.
+    pub fn copy_from(&self, other: &PeerLimits) {
+        self.upload.set_limit(other.upload.limit());
+        self.download.set_limit(other.download.limit());
+    }
+}
+
+pub fn format_limit(limit: Option<u64>) -> String {
+    match limit {
+        Some(limit) => format!("{}/s", format_size(limit, DECIMAL)),
+        None => "unlimited".to_string(),
+    }
+}
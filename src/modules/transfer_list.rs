@@ -0,0 +1,216 @@
+use crossterm::event::KeyCode;
+use crossterm::event::KeyEvent;
+use humansize::{format_size, DECIMAL};
+use ratatui::buffer::Buffer;
+use ratatui::layout::{Constraint, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::widgets::{Block, Borders, Cell, Row, StatefulWidget, Table, TableState, Widget};
+use std::sync::Arc;
+use std::time::Instant;
+
+use cli_log::*;
+
+use crate::modules::message_bubble::format_duration;
+use crate::modules::peer_list::PeerList;
+use crate::modules::throttle::*;
+use crate::modules::transfers::*;
+
+/// Screen listing transfers of all peers.
+pub struct TransferList {
+    transfers: Vec<Arc<Transfer>>,
+    state: TableState,
+}
+
+impl Default for TransferList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TransferList {
+    pub fn new() -> Self {
+        TransferList {
+            transfers: Vec::new(),
+            state: TableState::default(),
+        }
+    }
+
+    // Take current transfers from registry.
+    pub fn update(&mut self) {
+        self.transfers = TRANSFERS.lock().unwrap().clone();
+
+        let selected = match self.state.selected() {
+            _ if self.transfers.is_empty() => None,
+            None => Some(0),
+            Some(idx) => Some(idx.min(self.transfers.len() - 1)),
+        };
+        self.state.select(selected);
+    }
+
+    fn get_selected(&self) -> Option<Arc<Transfer>> {
+        self.state
+            .selected()
+            .and_then(|idx| self.transfers.get(idx))
+            .cloned()
+    }
+
+    // Returns true if screen should be closed.
+    pub fn handle_event(&mut self, key: KeyEvent, peers: &mut PeerList) -> bool {
+        if key.kind != crossterm::event::KeyEventKind::Press {
+            return false;
+        }
+
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => {
+                return true;
+            }
+            KeyCode::Up => {
+                let idx = self.state.selected().unwrap_or(0);
+                self.state.select(Some(idx.saturating_sub(1)));
+            }
+            KeyCode::Down => {
+                let idx = self.state.selected().map_or(0, |idx| idx + 1);
+                self.state
+                    .select(Some(idx.min(self.transfers.len().saturating_sub(1))));
+            }
+            KeyCode::Char('c') => {
+                if let Some(transfer) = self.get_selected() {
+                    match peers.get_peer_mut(transfer.peer_id) {
+                        Some(peer) => peer.cancel_transfer(&transfer),
+                        None => {
+                            if let TransferDirection::Upload(_, control) = &transfer.direction {
+                                control.cancel();
+                            }
+                        }
+                    }
+                }
+            }
+            KeyCode::Char('r') => {
+                if let Some(transfer) = self.get_selected() {
+                    if matches!(transfer.status(), TransferStatus::Failed(_)) {
+                        if let Some(peer) = peers.get_peer_mut(transfer.peer_id) {
+                            peer.retry_transfer(&transfer);
+                        }
+                    }
+                }
+            }
+            KeyCode::Char('u') => UPLOAD_LIMIT.cycle_limit(),
+            KeyCode::Char('d') => DOWNLOAD_LIMIT.cycle_limit(),
+            KeyCode::Char('o') => {
+                if let Some(transfer) = self.get_selected() {
+                    if let Err(e) = open::that_detached(&transfer.folder) {
+                        error!("Couldn't open {}: {}", transfer.folder.display(), e);
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        false
+    }
+
+    pub fn render(&mut self, rect: &mut Rect, buf: &mut Buffer) {
+        let limits = format!(
+            "upload limit: {}, download limit: {}",
+            format_limit(UPLOAD_LIMIT.limit()),
+            format_limit(DOWNLOAD_LIMIT.limit())
+        );
+
+        if self.transfers.is_empty() {
+            let block = Block::default()
+                .title(format!(
+                    "No transfers yet! ({}; u/d: change, Esc: back)",
+                    limits
+                ))
+                .borders(Borders::ALL);
+            Widget::render(block, *rect, buf);
+            return;
+        }
+
+        let now = Instant::now();
+
+        let header = Row::new(["", "Peer", "Name", "Progress", "Speed", "ETA", "Status"])
+            .style(Style::default().add_modifier(Modifier::BOLD));
+
+        let rows: Vec<Row> = self
+            .transfers
+            .iter()
+            .map(|transfer| {
+                let direction = match transfer.is_upload() {
+                    true => "↑",
+                    false => "↓",
+                };
+
+                let progress = match transfer.progress() {
+                    Some((position, end)) => format!(
+                        "{:3}% {} / {}",
+                        (position * 100).checked_div(end).unwrap_or(100),
+                        format_size(position, DECIMAL),
+                        format_size(end, DECIMAL)
+                    ),
+                    None => String::new(),
+                };
+
+                let status = transfer.status();
+
+                let (speed, eta) = match status {
+                    TransferStatus::Active => (
+                        transfer
+                            .speed(now)
+                            .map(|speed| format!("{}/s", format_size(speed as u64, DECIMAL)))
+                            .unwrap_or_default(),
+                        transfer.eta(now).map(format_duration).unwrap_or_default(),
+                    ),
+                    _ => (String::new(), String::new()),
+                };
+
+                let (status, status_style) = match status {
+                    TransferStatus::Queued => ("Queued".to_string(), Style::default()),
+                    TransferStatus::Active => ("Active".to_string(), Style::default()),
+                    TransferStatus::Paused => {
+                        ("Paused".to_string(), Style::default().fg(Color::Yellow))
+                    }
+                    TransferStatus::Finished => {
+                        ("Finished".to_string(), Style::default().fg(Color::Green))
+                    }
+                    TransferStatus::Failed(e) => (e, Style::default().fg(Color::Red)),
+                };
+
+                Row::new([
+                    Cell::from(direction),
+                    Cell::from(transfer.peer_name.clone()),
+                    Cell::from(transfer.name.clone()),
+                    Cell::from(progress),
+                    Cell::from(speed),
+                    Cell::from(eta),
+                    Cell::from(status).style(status_style),
+                ])
+            })
+            .collect();
+
+        let widths = [
+            Constraint::Length(1),
+            Constraint::Percentage(15),
+            Constraint::Percentage(25),
+            Constraint::Length(25),
+            Constraint::Length(11),
+            Constraint::Length(8),
+            Constraint::Fill(1),
+        ];
+
+        let table = Table::new(rows, widths)
+            .header(header)
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(format!(
+                        "Transfers ({}): (c: cancel, r: retry, o: open folder, u/d: change limit, Esc: back)",
+                        limits
+                    ))
+                    .border_style(Style::default().fg(Color::Green)),
+            )
+            .row_highlight_style(Style::default().bg(Color::DarkGray));
+
+        StatefulWidget::render(table, *rect, buf, &mut self.state);
+    }
+}
@@ -0,0 +1,136 @@
+use once_cell::sync::Lazy;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::modules::file_transfer::UploadControl;
+use crate::modules::message_bubble::*;
+use crate::modules::protocol::*;
+
+// Every transfer of this session across all peers, oldest first.
+pub static TRANSFERS: Lazy<Mutex<Vec<Arc<Transfer>>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+pub enum TransferDirection {
+    Upload(FileID, Arc<UploadControl>), // Uploads can be stopped without going through their message.
+    Download,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TransferStatus {
+    Queued, // No bytes were moved yet.
+    Active,
+    Paused,
+    Finished,
+    Failed(String),
+}
+
+/// File (or directory) sent to or received from peer, shown on transfer manager screen.
+pub struct Transfer {
+    pub direction: TransferDirection,
+    pub peer_id: u64,
+    pub peer_name: String,
+    pub name: String,
+    pub folder: PathBuf, // Folder where file is (or will be) stored.
+    pub loading_bar: Arc<Mutex<LoadingBarWrap>>,
+}
+
+impl Transfer {
+    pub fn new(
+        direction: TransferDirection,
+        peer_id: u64,
+        peer_name: String,
+        name: String,
+        folder: PathBuf,
+        loading_bar: Arc<Mutex<LoadingBarWrap>>,
+    ) -> Self {
+        Transfer {
+            direction,
+            peer_id,
+            peer_name,
+            name,
+            folder,
+            loading_bar,
+        }
+    }
+
+    pub fn is_upload(&self) -> bool {
+        matches!(self.direction, TransferDirection::Upload(..))
+    }
+
+    pub fn is_running(&self) -> bool {
+        matches!(
+            self.status(),
+            TransferStatus::Queued | TransferStatus::Active | TransferStatus::Paused
+        )
+    }
+
+    // Bytes moved so far and total size, None once transfer ended.
+    pub fn progress(&self) -> Option<(FileSize, FileSize)> {
+        match &self.loading_bar.lock().unwrap().loadingbar {
+            LoadingBar::Status(status) => Some((status.position, status.end)),
+            _ => None,
+        }
+    }
+
+    pub fn status(&self) -> TransferStatus {
+        match &self.loading_bar.lock().unwrap().loadingbar {
+            LoadingBar::Status(status) if status.paused => TransferStatus::Paused,
+            LoadingBar::Status(status) if status.is_started() => TransferStatus::Active,
+            LoadingBar::Status(_) => TransferStatus::Queued,
+            LoadingBar::Verified(_) | LoadingBar::Delivered => TransferStatus::Finished,
+            LoadingBar::Error(e) => TransferStatus::Failed(e.clone()),
+        }
+    }
+
+    // Smoothed speed in bytes per second.
+    pub fn speed(&self, now: Instant) -> Option<f64> {
+        match &self.loading_bar.lock().unwrap().loadingbar {
+            LoadingBar::Status(status) => status.speed(now),
+            _ => None,
+        }
+    }
+
+    pub fn eta(&self, now: Instant) -> Option<Duration> {
+        match &self.loading_bar.lock().unwrap().loadingbar {
+            LoadingBar::Status(status) => status.eta(now),
+            _ => None,
+        }
+    }
+}
+
+pub fn register(transfer: Transfer) -> Arc<Transfer> {
+    let transfer = Arc::new(transfer);
+    TRANSFERS.lock().unwrap().push(transfer.clone());
+    transfer
+}
+
+pub fn find(loading_bar: &Arc<Mutex<LoadingBarWrap>>) -> Option<Arc<Transfer>> {
+    TRANSFERS
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|transfer| Arc::ptr_eq(&transfer.loading_bar, loading_bar))
+        .cloned()
+}
+
+// All uploads of given file to given peer, oldest first.
+pub fn uploads_of(peer_id: u64, file_id: FileID) -> Vec<Arc<Transfer>> {
+    TRANSFERS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|transfer| {
+            transfer.peer_id == peer_id
+                && matches!(transfer.direction, TransferDirection::Upload(id, _) if id == file_id)
+        })
+        .cloned()
+        .collect()
+}
+
+// Removes transfer shown on given loading bar, used when download starts again with new bar.
+pub fn forget(loading_bar: &Arc<Mutex<LoadingBarWrap>>) {
+    TRANSFERS
+        .lock()
+        .unwrap()
+        .retain(|transfer| !Arc::ptr_eq(&transfer.loading_bar, loading_bar));
+}
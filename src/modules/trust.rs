@@ -0,0 +1,178 @@
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use crate::modules::storage::*;
+
+static KNOWN_PEERS_FILE: &str = "known_peers";
+static SAS_LABEL: &[u8] = b"CHATapp short authentication string v1";
+
+// Peers seen in previous sessions, shared by all connections.
+pub static KNOWN_PEERS: Lazy<Mutex<KnownPeers>> =
+    Lazy::new(|| Mutex::new(KnownPeers::open(state_path(KNOWN_PEERS_FILE))));
+
+// Peers rejected by user, they are not reconnected until restart.
+pub static REJECTED_PEERS: Lazy<Mutex<HashSet<u64>>> = Lazy::new(|| Mutex::new(HashSet::new()));
+
+/// What we know about identity of connected peer.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TrustStatus {
+    New,                // First time we see this peer, key was remembered.
+    Known,              // Key matches the one remembered on first use.
+    Verified,           // Key was confirmed by comparing authentication string.
+    KeyChanged(String), // Name or id known from before shows up with diffrent key.
+}
+
+impl TrustStatus {
+    pub fn is_warning(&self) -> bool {
+        matches!(self, TrustStatus::KeyChanged(_))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct KnownPeer {
+    pub name: String,
+    pub identity_key: [u8; 32],
+    pub verified: bool,
+}
+
+/// Trust on first use store of peer identity keys, keyed by peer id.
+pub struct KnownPeers {
+    peers: HashMap<u64, KnownPeer>,
+    path: Option<PathBuf>, // None if store lives only in memory.
+}
+
+impl KnownPeers {
+    pub fn open(path: Option<PathBuf>) -> Self {
+        let peers = path.as_deref().and_then(load_state).unwrap_or_default();
+
+        KnownPeers { peers, path }
+    }
+
+    fn store(&self) {
+        if let Some(path) = &self.path {
+            store_state(path, &self.peers);
+        }
+    }
+
+    pub fn get(&self, peer_id: u64) -> Option<&KnownPeer> {
+        self.peers.get(&peer_id)
+    }
+
+    // Id of another remembered peer that uses this name with diffrent key.
+    fn name_owner(&self, peer_id: u64, name: &str, identity_key: &[u8; 32]) -> Option<u64> {
+        self.peers
+            .iter()
+            .find(|(id, known)| {
+                **id != peer_id && known.name == name && known.identity_key != *identity_key
+            })
+            .map(|(id, _)| *id)
+    }
+
+    /// Compares peer with remembered keys, remembers it if seen for the first time.
+    /// Key that claims name of another remembered key is not remembered until user verifies it,
+    /// so warning is shown on every connection.
+    pub fn check(&mut self, peer_id: u64, name: &str, identity_key: &[u8; 32]) -> TrustStatus {
+        let name_owner = self.name_owner(peer_id, name, identity_key);
+
+        if let Some(known) = self.peers.get_mut(&peer_id) {
+            if known.identity_key != *identity_key {
+                return TrustStatus::KeyChanged(format!(
+                    "id {:016x} was seen before with diffrent key",
+                    peer_id
+                ));
+            }
+
+            if let (Some(owner_id), false) = (name_owner, known.verified) {
+                return TrustStatus::KeyChanged(format!(
+                    "name \"{}\" belongs to diffrent key (id {:016x})",
+                    name, owner_id
+                ));
+            }
+
+            let verified = known.verified;
+
+            // Key is the same, so it is still the same person who only changed their name.
+            if known.name != name {
+                known.name = name.to_string();
+                self.store();
+            }
+
+            return match verified {
+                true => TrustStatus::Verified,
+                false => TrustStatus::Known,
+            };
+        }
+
+        if let Some(owner_id) = name_owner {
+            return TrustStatus::KeyChanged(format!(
+                "name \"{}\" belongs to diffrent key (id {:016x})",
+                name, owner_id
+            ));
+        }
+
+        self.peers.insert(
+            peer_id,
+            KnownPeer {
+                name: name.to_string(),
+                identity_key: *identity_key,
+                verified: false,
+            },
+        );
+        self.store();
+
+        TrustStatus::New
+    }
+
+    /// Marks peer key as confirmed by user, remembers it if it wasn't remembered yet.
+    /// Returns false if peer id is remembered with diffrent key.
+    pub fn mark_verified(&mut self, peer_id: u64, name: &str, identity_key: &[u8; 32]) -> bool {
+        let known = self.peers.entry(peer_id).or_insert_with(|| KnownPeer {
+            name: name.to_string(),
+            identity_key: *identity_key,
+            verified: false,
+        });
+
+        if known.identity_key != *identity_key {
+            return false;
+        }
+
+        known.verified = true;
+        self.store();
+        true
+    }
+}
+
+/// Hex fingerprint of identity key, grouped for readability.
+pub fn fingerprint(identity_key: &[u8; 32]) -> String {
+    Sha256::digest(identity_key)[..16]
+        .chunks(2)
+        .map(|pair| format!("{:02x}{:02x}", pair[0], pair[1]))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+/// Short authentication string that both users can compare out loud.
+/// It is the same on both sides, since keys are sorted before hashing.
+pub fn authentication_string(key_a: &[u8; 32], key_b: &[u8; 32]) -> String {
+    let (first, second) = if key_a < key_b {
+        (key_a, key_b)
+    } else {
+        (key_b, key_a)
+    };
+
+    let mut hasher = Sha256::new();
+    hasher.update(SAS_LABEL);
+    hasher.update(first);
+    hasher.update(second);
+    let hash = hasher.finalize();
+
+    // 3 groups of 4 digits, about 40 bits.
+    let value = u64::from_be_bytes(hash[..8].try_into().unwrap()) % 1_000_000_000_000; // This unwrap will never fail.
+    let digits = format!("{:012}", value);
+
+    format!("{} {} {}", &digits[..4], &digits[4..8], &digits[8..])
+}
@@ -1,7 +1,7 @@
 use crate::modules::event_handler;
 
 use super::peer_state::PeerState;
-use super::{event_handler::*, peer_list::*};
+use super::{broadcast::*, event_handler::*, peer_list::*, transfer_list::*};
 
 use ratatui::{
     backend::CrosstermBackend,
@@ -15,10 +15,15 @@ use ratatui::{
 pub enum AppPosition {
     PeerList,
     ChatSession,
+    PeerDetails,
+    TransferList,
+    Broadcast,
 }
 
 pub struct App<'a> {
     peers: PeerList<'a>,
+    transfers: TransferList,
+    broadcast: Broadcast<'a>,
     current_screen: AppPosition,
     events: EventHandler,
 }
@@ -33,6 +38,8 @@ impl App<'_> {
     pub fn new() -> Self {
         App {
             peers: PeerList::new(),
+            transfers: TransferList::new(),
+            broadcast: Broadcast::new(),
             current_screen: AppPosition::PeerList,
             events: EventHandler::new(),
         }
@@ -66,10 +73,30 @@ impl App<'_> {
                             self.current_screen = AppPosition::PeerList;
                         }
                     }
+                    AppPosition::PeerDetails => {
+                        if let Some(peer) = self.peers.get_selected() {
+                            if peer.handle_details_event(key) {
+                                self.current_screen = AppPosition::PeerList;
+                            }
+                        } else {
+                            self.current_screen = AppPosition::PeerList;
+                        }
+                    }
+                    AppPosition::TransferList => {
+                        if self.transfers.handle_event(key, &mut self.peers) {
+                            self.current_screen = AppPosition::PeerList;
+                        }
+                    }
+                    AppPosition::Broadcast => {
+                        if self.broadcast.handle_event(key, &mut self.peers) {
+                            self.current_screen = AppPosition::PeerList;
+                        }
+                    }
                 }
             }
 
             self.peers.update();
+            self.transfers.update();
             if let Some(peer) = self.peers.get_selected() {
                 peer.update();
             }
@@ -82,6 +109,13 @@ impl App<'_> {
 
 impl Widget for &mut App<'_> {
     fn render(self, area: Rect, buf: &mut Buffer) {
+        // Transfers of all peers take whole screen.
+        if self.current_screen == AppPosition::TransferList {
+            let mut transfers_block = area;
+            self.transfers.render(&mut transfers_block, buf);
+            return;
+        }
+
         // Devide main screen.
         let [mut peers_block, mut msg_block] =
             Layout::horizontal([Constraint::Percentage(30), Constraint::Percentage(70)])
@@ -91,7 +125,17 @@ impl Widget for &mut App<'_> {
 
         self.peers.render(&mut peers_block, buf, is_active);
 
+        if self.current_screen == AppPosition::Broadcast {
+            self.broadcast.render(&mut msg_block, buf, &self.peers);
+            return;
+        }
+
         if let Some(peer) = self.peers.get_selected() {
+            if self.current_screen == AppPosition::PeerDetails {
+                peer.render_details(&mut msg_block, buf);
+                return;
+            }
+
             // Devide conversation to include editor box.
 
             let is_active: bool = self.current_screen == AppPosition::ChatSession;
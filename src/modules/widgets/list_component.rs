@@ -218,6 +218,21 @@ impl<'a, Item: ListItem<'a>> ListComponent<'a, Item> {
         self.list.push(item);
     }
 
+    // Remove item, keeping selection inside the list.
+    pub fn remove(&mut self, idx: u16) -> Item {
+        let item = self.list.remove(idx as usize);
+
+        if self.list.is_empty() {
+            self.reset();
+        } else if let Some(selected_msg) = self.scroll.selected_msg {
+            let selected_msg = selected_msg.min(self.list.len() as u16 - 1);
+            self.scroll.selected_msg = Some(selected_msg);
+            self.scroll.top_visisted = Some((selected_msg, 0));
+        }
+
+        item
+    }
+
     pub fn append(&mut self, other: &mut Vec<Item>) {
         self.list.append(other);
     }
@@ -0,0 +1,57 @@
+use rust_project::modules::access_list::*;
+use std::net::{IpAddr, Ipv4Addr};
+use tempfile::tempdir;
+
+const NOISY_ADDR: IpAddr = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 7));
+const OTHER_ADDR: IpAddr = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 8));
+
+#[test]
+fn blocklist_survives_restart() {
+    let tmp_dir = tempdir().unwrap();
+    let path = tmp_dir.path().join("access_list");
+
+    let mut access_list = AccessList::open(Some(path.clone()), false);
+    assert!(access_list.is_allowed(1, NOISY_ADDR));
+
+    assert!(access_list.toggle_blocked(1, NOISY_ADDR));
+
+    let mut access_list = AccessList::open(Some(path), false);
+
+    // Both identity and address are blocked.
+    assert!(!access_list.is_allowed(1, OTHER_ADDR));
+    assert!(!access_list.is_address_allowed(NOISY_ADDR));
+    assert!(access_list.is_allowed(2, OTHER_ADDR));
+
+    assert!(!access_list.toggle_blocked(1, NOISY_ADDR));
+    assert!(access_list.is_allowed(1, NOISY_ADDR));
+}
+
+#[test]
+fn strict_mode_only_allows_allowlisted() {
+    let mut access_list = AccessList::open(None, true);
+
+    assert!(!access_list.is_allowed(1, NOISY_ADDR));
+
+    // Refused peer is still connected, so it can be added to allowlist.
+    assert!(access_list.is_connectable(1, NOISY_ADDR));
+    assert!(access_list.is_refused(1));
+
+    assert!(access_list.toggle_allowlisted(1));
+    assert!(!access_list.is_refused(1));
+    assert!(access_list.is_allowed(1, NOISY_ADDR));
+
+    // Blocking takes peer off the allowlist.
+    access_list.toggle_blocked(1, NOISY_ADDR);
+    access_list.toggle_blocked(1, NOISY_ADDR);
+    assert!(!access_list.is_allowed(1, NOISY_ADDR));
+}
+
+#[test]
+fn allowlist_is_ignored_outside_strict_mode() {
+    let mut access_list = AccessList::open(None, false);
+
+    assert!(!access_list.is_refused(1));
+    assert!(access_list.toggle_allowlisted(1));
+    assert!(!access_list.toggle_allowlisted(1));
+    assert!(access_list.is_allowed(1, NOISY_ADDR));
+}
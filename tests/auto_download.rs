@@ -0,0 +1,128 @@
+use rust_project::config::*;
+use rust_project::modules::{auto_download::*, trust::TrustStatus};
+use std::path::PathBuf;
+
+const PEER_ID: u64 = 0x1234abcd;
+
+fn photos_rule() -> AutoDownloadRule {
+    AutoDownloadRule {
+        trust: AutoDownloadTrust::Trusted,
+        max_size: Some(10 << 20),
+        extensions: vec!["jpg".to_string(), ".PNG".to_string()],
+        folder: Some(PathBuf::from("/tmp/photos")),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn rule_checks_trust() {
+    let verified_only = AutoDownloadRule::default();
+    let trusted = AutoDownloadRule {
+        trust: AutoDownloadTrust::Trusted,
+        ..Default::default()
+    };
+
+    assert!(verified_only.matches(PEER_ID, &TrustStatus::Verified, "a.txt", 1));
+    assert!(!verified_only.matches(PEER_ID, &TrustStatus::Known, "a.txt", 1));
+
+    assert!(trusted.matches(PEER_ID, &TrustStatus::Known, "a.txt", 1));
+    assert!(trusted.matches(PEER_ID, &TrustStatus::Verified, "a.txt", 1));
+
+    // Peers seen for the first time or with changed key are never trusted.
+    for rule in [verified_only, trusted] {
+        assert!(!rule.matches(PEER_ID, &TrustStatus::New, "a.txt", 1));
+        assert!(!rule.matches(
+            PEER_ID,
+            &TrustStatus::KeyChanged("Key changed".to_string()),
+            "a.txt",
+            1
+        ));
+    }
+}
+
+#[test]
+fn rule_checks_size_and_extension() {
+    let rule = photos_rule();
+
+    assert!(rule.matches(PEER_ID, &TrustStatus::Known, "cat.JPG", 10 << 20));
+    assert!(rule.matches(PEER_ID, &TrustStatus::Known, "dog.png", 1));
+    assert!(!rule.matches(PEER_ID, &TrustStatus::Known, "cat.jpg", (10 << 20) + 1));
+    assert!(!rule.matches(PEER_ID, &TrustStatus::Known, "run.exe", 1));
+    assert!(!rule.matches(PEER_ID, &TrustStatus::Known, "jpg", 1));
+}
+
+#[test]
+fn rule_checks_peer() {
+    let rule = AutoDownloadRule {
+        peer: Some("000000001234ABCD".to_string()),
+        ..Default::default()
+    };
+
+    assert!(rule.matches(PEER_ID, &TrustStatus::Verified, "a.txt", 1));
+    assert!(!rule.matches(PEER_ID + 1, &TrustStatus::Verified, "a.txt", 1));
+}
+
+#[test]
+fn first_matching_rule_picks_folder() {
+    let other_peer = AutoDownloadRule {
+        peer: Some(format!("{:016x}", PEER_ID + 1)),
+        trust: AutoDownloadTrust::Trusted,
+        folder: Some(PathBuf::from("/tmp/other")),
+        ..Default::default()
+    };
+    let rules = vec![other_peer, photos_rule(), AutoDownloadRule::default()];
+
+    assert_eq!(
+        auto_download_folder(&rules, PEER_ID, &TrustStatus::Known, "cat.jpg", 1),
+        Some(PathBuf::from("/tmp/photos"))
+    );
+    assert_eq!(
+        auto_download_folder(&rules, PEER_ID + 1, &TrustStatus::Known, "cat.jpg", 1),
+        Some(PathBuf::from("/tmp/other"))
+    );
+
+    // Rule without folder downloads to default download folder.
+    assert_eq!(
+        auto_download_folder(&rules, PEER_ID, &TrustStatus::Verified, "notes.txt", 1),
+        Some(DOWNLOAD_PATH.clone())
+    );
+    assert_eq!(
+        auto_download_folder(&rules, PEER_ID, &TrustStatus::Known, "notes.txt", 1),
+        None
+    );
+    assert_eq!(
+        auto_download_folder(&[], PEER_ID, &TrustStatus::Verified, "cat.jpg", 1),
+        None
+    );
+}
+
+#[test]
+fn rules_are_read_from_settings() {
+    let settings: Settings = toml::from_str(
+        r#"
+        [[auto_download]]
+        trust = "trusted"
+        max_size = 1000
+        extensions = ["pdf"]
+        folder = "/tmp/papers"
+
+        [[auto_download]]
+        peer = "000000001234abcd"
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(settings.auto_download.len(), 2);
+    assert_eq!(settings.auto_download[0].trust, AutoDownloadTrust::Trusted);
+    assert_eq!(settings.auto_download[1].trust, AutoDownloadTrust::Verified);
+    assert_eq!(
+        auto_download_folder(
+            &settings.auto_download,
+            PEER_ID,
+            &TrustStatus::Known,
+            "paper.pdf",
+            1000
+        ),
+        Some(PathBuf::from("/tmp/papers"))
+    );
+}
@@ -0,0 +1,183 @@
+use rust_project::modules::{encryption::*, protocol::*};
+use tokio::io::AsyncWriteExt;
+use tokio::net::*;
+
+use ntest::timeout;
+
+async fn get_2_raw_streams() -> (TcpStream, TcpStream) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let handle = tokio::task::spawn(TcpStream::connect(addr));
+    let (stream, _) = listener.accept().await.unwrap();
+
+    (stream, handle.await.unwrap().unwrap())
+}
+
+#[tokio::test]
+#[timeout(1000)]
+async fn encrypted_message_roundtrip() {
+    let (stream1, stream2) = get_2_raw_streams().await;
+
+    let handle = tokio::task::spawn(SecureStream::handshake(stream2, None));
+    let (secure1, _) = SecureStream::handshake(stream1, None).await.unwrap();
+    let (secure2, _) = handle.await.unwrap().unwrap();
+
+    let (_, mut writer) = secure1.into_split();
+    let (mut reader, _) = secure2.into_split();
+
+    let original = Message::User(UserMessage::Text("Dzień dobry".to_string()));
+
+    writer.send(&original).await.unwrap();
+    writer.send(&original).await.unwrap(); // Second frame uses diffrent nonce.
+
+    assert_eq!(reader.read::<Message>().await.unwrap(), original);
+    assert_eq!(reader.read::<Message>().await.unwrap(), original);
+}
+
+#[tokio::test]
+#[timeout(1000)]
+async fn handshake_rejects_forged_auth() {
+    let (stream1, mut stream2) = get_2_raw_streams().await;
+
+    let handle = tokio::task::spawn(SecureStream::handshake(stream1, None));
+
+    // Pretend to be a peer that doesn't know the session keys.
+    let _ = HandshakeHello::read(&mut stream2).await.unwrap();
+    HandshakeHello {
+        identity_key: [7; 32],
+        ephemeral_key: [9; 32],
+        pake_key: None,
+    }
+    .send(&mut stream2)
+    .await
+    .unwrap();
+    stream2
+        .write_all(&[0, 0, 0, 0, 0, 0, 0, 4, 1, 2, 3, 4])
+        .await
+        .unwrap();
+
+    assert!(handle.await.unwrap().is_err());
+}
+
+async fn handshake_in_rooms(passphrase1: &str, passphrase2: &str) -> bool {
+    let room_key1 = RoomKey::derive(passphrase1);
+    let room_key2 = RoomKey::derive(passphrase2);
+
+    let (stream1, stream2) = get_2_raw_streams().await;
+
+    let (result1, result2) = tokio::join!(
+        SecureStream::handshake(stream1, Some(&room_key1)),
+        SecureStream::handshake(stream2, Some(&room_key2)),
+    );
+
+    result1.is_ok() && result2.is_ok()
+}
+
+#[tokio::test]
+async fn handshake_in_private_room() {
+    assert!(handshake_in_rooms("correct horse", "correct horse").await);
+    assert!(!handshake_in_rooms("correct horse", "battery staple").await);
+}
+
+#[tokio::test]
+async fn handshake_rejects_peer_outside_room() {
+    let room_key = RoomKey::derive("correct horse");
+
+    let (stream1, stream2) = get_2_raw_streams().await;
+
+    let (result1, result2) = tokio::join!(
+        SecureStream::handshake(stream1, Some(&room_key)),
+        SecureStream::handshake(stream2, None),
+    );
+
+    assert!(result1.is_err() && result2.is_err());
+}
+
+#[tokio::test]
+#[timeout(1000)]
+async fn chunk_roundtrip() {
+    let (stream1, stream2) = get_2_raw_streams().await;
+
+    let handle = tokio::task::spawn(SecureStream::handshake(stream2, None));
+    let (mut secure1, _) = SecureStream::handshake(stream1, None).await.unwrap();
+    let (mut secure2, _) = handle.await.unwrap().unwrap();
+
+    let mut sent = ChunkBuffer::new(1024);
+    let mut received = ChunkBuffer::new(1024);
+
+    for (position, byte) in [(0, 1u8), (1024, 2u8)] {
+        sent.fill(7, position, 1024).fill(byte);
+        secure1.send_chunk(&mut sent).await.unwrap();
+
+        secure2.read_chunk(&mut received).await.unwrap();
+        assert_eq!(received.file_id(), 7);
+        assert_eq!(received.position(), position);
+        assert_eq!(received.data(), &[byte; 1024]);
+    }
+
+    // Msg frames still work after chunks.
+    let original = Message::User(UserMessage::Text("Dzień dobry".to_string()));
+    secure1.send(&original).await.unwrap();
+    assert_eq!(secure2.read::<Message>().await.unwrap(), original);
+}
+
+#[tokio::test]
+#[timeout(1000)]
+async fn chunk_bigger_than_buffer_is_rejected() {
+    let (stream1, stream2) = get_2_raw_streams().await;
+
+    let handle = tokio::task::spawn(SecureStream::handshake(stream2, None));
+    let (mut secure1, _) = SecureStream::handshake(stream1, None).await.unwrap();
+    let (mut secure2, _) = handle.await.unwrap().unwrap();
+
+    let mut sent = ChunkBuffer::new(2048);
+    sent.fill(1, 0, 2048);
+    secure1.send_chunk(&mut sent).await.unwrap();
+
+    assert!(matches!(
+        secure2.read_chunk(&mut ChunkBuffer::new(1024)).await,
+        Err(StreamSerializerError::InvalidFrame(
+            FrameError::TooLarge { .. }
+        ))
+    ));
+}
+
+#[tokio::test]
+#[timeout(1000)]
+async fn chunks_and_msgs_are_told_apart() {
+    let (stream1, stream2) = get_2_raw_streams().await;
+
+    let handle = tokio::task::spawn(SecureStream::handshake(stream2, None));
+    let (secure1, _) = SecureStream::handshake(stream1, None).await.unwrap();
+    let (secure2, _) = handle.await.unwrap().unwrap();
+    let (_, mut sender) = secure1.into_split();
+    let (mut receiver, _) = secure2.into_split();
+
+    let original = Message::User(UserMessage::Text("Dzień dobry".to_string()));
+    let mut sent = ChunkBuffer::new(1024);
+    // Smaller buffer grows to fit chunk.
+    let mut received = ChunkBuffer::new(16);
+
+    sender.send(&original).await.unwrap();
+    sent.fill(3, 1024, 1024).fill(5);
+    sender.send_chunk(&mut sent).await.unwrap();
+    sender.send(&original).await.unwrap();
+    sent.fill(3, 0, 1024).fill(5);
+    sender.send_chunk(&mut sent).await.unwrap();
+
+    for _ in 0..2 {
+        match receiver.read_msg_or_chunk::<Message>(&mut received).await {
+            Ok(Frame::Msg(msg)) => assert_eq!(msg, original),
+            _ => panic!("Expected msg!"),
+        }
+        match receiver.read_msg_or_chunk::<Message>(&mut received).await {
+            Ok(Frame::Chunk) => assert_eq!(received.data(), &[5; 1024]),
+            _ => panic!("Expected chunk!"),
+        }
+    }
+
+    // Plain read doesn't take chunk for msg.
+    sender.send_chunk(&mut sent).await.unwrap();
+    assert!(receiver.read::<Message>().await.is_err());
+}
@@ -0,0 +1,458 @@
+use rust_project::config::{DOWNLOAD_PATH, USER_ID};
+use rust_project::modules::{
+    encryption::ChunkBuffer, file_transfer::*, message_bubble::*, protocol::*, throttle::*,
+};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tempfile::tempdir;
+use tokio::sync::mpsc;
+
+#[test]
+fn paths_are_stripped() {
+    assert_eq!(sanitize_file_name("../../.bashrc").unwrap(), ".bashrc");
+    assert_eq!(sanitize_file_name("/etc/passwd").unwrap(), "passwd");
+    assert_eq!(sanitize_file_name("a/b.txt").unwrap(), "b.txt");
+    assert_eq!(sanitize_file_name("dir\\file").unwrap(), "file");
+    assert_eq!(sanitize_file_name("C:file.txt").unwrap(), "file.txt");
+
+    for name in ["..", ".", "dir/..", "C:.."] {
+        assert!(sanitize_file_name(name).is_err(), "{} was accepted", name);
+    }
+}
+
+#[test]
+fn names_are_cleaned() {
+    assert_eq!(sanitize_file_name("report.pdf").unwrap(), "report.pdf");
+    assert_eq!(sanitize_file_name("CON.txt").unwrap(), "_CON.txt");
+    assert_eq!(sanitize_file_name("a\nb\x07c.txt").unwrap(), "abc.txt");
+    assert_eq!(sanitize_file_name("what?.txt. ").unwrap(), "what_.txt");
+    assert_eq!(sanitize_file_name("\r\n").unwrap(), "download");
+    assert_eq!(
+        sanitize_file_name("photo\u{202E}gpj.exe").unwrap(),
+        "photogpj.exe"
+    );
+    assert_eq!(sanitize_file_name("a\u{200B}b.txt").unwrap(), "ab.txt");
+
+    let long_name = format!("{}.txt", "ą".repeat(300));
+    let name = sanitize_file_name(&long_name).unwrap();
+    assert!(name.len() <= 200);
+    assert!(name.ends_with(".txt"));
+}
+
+#[tokio::test]
+async fn finished_download_never_overwrites_file() {
+    let tmp_dir = tempdir().unwrap();
+    let file_path = tmp_dir.path().join("report.txt");
+
+    // File with the same name showed up while download was running.
+    std::fs::write(&file_path, "mine").unwrap();
+    std::fs::write(part_path(&file_path), "downloaded").unwrap();
+
+    let renamed = rename_part_file(&file_path).await.unwrap();
+
+    assert_eq!(renamed, tmp_dir.path().join("report (1).txt"));
+    assert_eq!(std::fs::read_to_string(&file_path).unwrap(), "mine");
+    assert_eq!(std::fs::read_to_string(&renamed).unwrap(), "downloaded");
+    assert!(!part_path(&file_path).exists());
+}
+
+// Connects downloader directly with uploader of given file, without network.
+async fn run_transfer(
+    source: PathBuf,
+    file_name: &str,
+    partial_files: PartialFilesMap,
+) -> (Arc<Mutex<LoadingBarWrap>>, FileSize) {
+    let content = std::fs::read(&source).unwrap();
+    let file_hash: FileHash = Sha256::digest(&content).into();
+
+    let (tx_packets, rx_packets) = mpsc::channel(4);
+    let (tx_requests, mut rx_requests) = mpsc::unbounded_channel();
+    let loading_bar = Arc::new(Mutex::new(LoadingBarWrap {
+        loadingbar: LoadingBar::Error(String::new()),
+        changed: true,
+    }));
+
+    let downloader = tokio::task::spawn(file_downloader(
+        rx_packets,
+        tx_requests,
+        1,
+        file_name.to_string(),
+        content.len() as FileSize,
+        file_hash,
+        loading_bar.clone(),
+        Arc::new(Mutex::new(HashMap::new())),
+        partial_files,
+        PeerLimits::default().download_throttle(),
+        1,
+        *USER_ID,
+        DOWNLOAD_PATH.clone(),
+    ));
+
+    let Some(Message::Internal(InternalMessage::FileRequest(id, offset, prefix_hash))) =
+        rx_requests.recv().await
+    else {
+        panic!("Expected file request!");
+    };
+
+    let control = Arc::new(UploadControl::new(4));
+    let (tx_upload, mut rx_upload) = mpsc::channel(4);
+    tokio::task::spawn(file_uploader(
+        tx_upload,
+        control.clone(),
+        source,
+        id,
+        offset,
+        prefix_hash,
+    ));
+
+    // Acks from downloader give credits back to uploader.
+    tokio::task::spawn(async move {
+        while let Some(Message::Internal(InternalMessage::FileAck(_, chunks))) =
+            rx_requests.recv().await
+        {
+            control.add_credits(chunks);
+        }
+    });
+
+    let mut first_byte_idx = None;
+    while let Some(packet) = rx_upload.recv().await {
+        if let FilePacket::Chunk(chunk) = &packet {
+            first_byte_idx.get_or_insert(chunk.position());
+        }
+        tx_packets.send(packet).await.unwrap();
+    }
+
+    downloader.await.unwrap();
+
+    (loading_bar, first_byte_idx.unwrap_or_default())
+}
+
+#[tokio::test]
+async fn interrupted_download_is_resumed() {
+    let file_name = "rust-project-test-file-R3sUm3dPaRt0Xq";
+    let download_path = DOWNLOAD_PATH.join(file_name);
+    let part_path = DOWNLOAD_PATH.join(format!("{}.part", file_name));
+
+    let tmp_dir = tempdir().unwrap();
+    let source = tmp_dir.path().join(file_name);
+    let content: Vec<u8> = (0..10_000u32).map(|i| i as u8).collect();
+    std::fs::write(&source, &content).unwrap();
+
+    // Half of the file was received before connection dropped.
+    std::fs::write(&part_path, &content[..5000]).unwrap();
+    let partial_files: PartialFilesMap =
+        Arc::new(Mutex::new(HashMap::from([(1, download_path.clone())])));
+
+    let (loading_bar, first_byte_idx) =
+        run_transfer(source.clone(), file_name, partial_files).await;
+
+    let downloaded = std::fs::read(&download_path);
+    let _ = std::fs::remove_file(&download_path);
+
+    assert_eq!(first_byte_idx, 5000);
+    assert_eq!(downloaded.unwrap(), content);
+    assert!(!part_path.exists());
+    assert!(matches!(
+        loading_bar.lock().unwrap().loadingbar,
+        LoadingBar::Verified(_)
+    ));
+}
+
+#[tokio::test]
+async fn mismatching_partial_file_is_downloaded_again() {
+    let file_name = "rust-project-test-file-W7onGPaRt9zLk2";
+    let download_path = DOWNLOAD_PATH.join(file_name);
+    let part_path = DOWNLOAD_PATH.join(format!("{}.part", file_name));
+
+    let tmp_dir = tempdir().unwrap();
+    let source = tmp_dir.path().join(file_name);
+    let content: Vec<u8> = (0..10_000u32).map(|i| i as u8).collect();
+    std::fs::write(&source, &content).unwrap();
+
+    std::fs::write(&part_path, vec![0xff; 5000]).unwrap();
+    let partial_files: PartialFilesMap =
+        Arc::new(Mutex::new(HashMap::from([(1, download_path.clone())])));
+
+    let (_, first_byte_idx) = run_transfer(source, file_name, partial_files).await;
+
+    let downloaded = std::fs::read(&download_path);
+    let _ = std::fs::remove_file(&download_path);
+
+    assert_eq!(first_byte_idx, 0);
+    assert_eq!(downloaded.unwrap(), content);
+}
+
+#[tokio::test]
+async fn colliding_directory_entries_are_both_kept() {
+    let tmp_dir = tempdir().unwrap();
+    let download_dir = tmp_dir.path().join("downloads");
+
+    // Both names sanitize to "b_.txt".
+    let contents = [b"first".to_vec(), b"second".to_vec()];
+    let mut sources = HashMap::new();
+    let mut files = Vec::new();
+    for (idx, (name, content)) in ["b?.txt", "b*.txt"].iter().zip(&contents).enumerate() {
+        let source = tmp_dir.path().join(format!("source{}", idx));
+        std::fs::write(&source, content).unwrap();
+        sources.insert(idx as FileID, source);
+
+        files.push(DirectoryEntry {
+            path: vec!["x".to_string(), name.to_string()],
+            size: content.len() as FileSize,
+            id: idx as FileID,
+            hash: Sha256::digest(content).into(),
+        });
+    }
+    let tree = DirectoryTree {
+        name: "shared".to_string(),
+        directories: vec![vec!["x".to_string()]],
+        files,
+    };
+
+    let (tx_requests, mut rx_requests) = mpsc::unbounded_channel();
+    let downloaded_files: DownloadedFilesMap = Arc::new(Mutex::new(HashMap::new()));
+    let loading_bar = Arc::new(Mutex::new(LoadingBarWrap {
+        loadingbar: LoadingBar::Error(String::new()),
+        changed: true,
+    }));
+
+    tokio::task::spawn(directory_downloader(
+        tx_requests,
+        tree,
+        loading_bar.clone(),
+        downloaded_files.clone(),
+        PeerLimits::default().download_throttle(),
+        download_dir.clone(),
+    ));
+
+    // Files are small, so uploader never waits for acks.
+    while let Some(Message::Internal(request)) = rx_requests.recv().await {
+        let InternalMessage::FileRequest(id, offset, prefix_hash) = request else {
+            continue;
+        };
+
+        let (tx_upload, mut rx_upload) = mpsc::channel(4);
+        tokio::task::spawn(file_uploader(
+            tx_upload,
+            Arc::new(UploadControl::new(4)),
+            sources[&id].clone(),
+            id,
+            offset,
+            prefix_hash,
+        ));
+
+        let tx_packets = downloaded_files.lock().unwrap()[&id].clone();
+        while let Some(packet) = rx_upload.recv().await {
+            tx_packets.send(packet).await.unwrap();
+        }
+    }
+
+    let dir = download_dir.join("shared").join("x");
+    assert_eq!(std::fs::read(dir.join("b_.txt")).unwrap(), contents[0]);
+    assert_eq!(std::fs::read(dir.join("b_ (1).txt")).unwrap(), contents[1]);
+    assert!(matches!(
+        loading_bar.lock().unwrap().loadingbar,
+        LoadingBar::Verified(_)
+    ));
+}
+
+#[tokio::test]
+async fn uploader_waits_for_free_space_in_queue() {
+    let tmp_dir = tempdir().unwrap();
+    let source = tmp_dir.path().join("big_file");
+    std::fs::write(&source, vec![7; 1 << 22]).unwrap();
+
+    let (tx_upload, mut rx_upload) = mpsc::channel(4);
+    let uploader = tokio::task::spawn(file_uploader(
+        tx_upload,
+        Arc::new(UploadControl::new(1 << 20)),
+        source,
+        1,
+        0,
+        Sha256::digest(b"").into(),
+    ));
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    // Nobody reads the queue, so only few chunks of 4 MiB file were read.
+    assert_eq!(rx_upload.len(), 4);
+    assert!(!uploader.is_finished());
+
+    let mut received = 0;
+    while let Some(FilePacket::Chunk(chunk)) = rx_upload.recv().await {
+        received += chunk.data().len();
+    }
+
+    assert_eq!(received, 1 << 22);
+}
+
+#[tokio::test]
+async fn uploader_waits_for_credits() {
+    let tmp_dir = tempdir().unwrap();
+    let source = tmp_dir.path().join("big_file");
+    std::fs::write(&source, vec![7; 1 << 22]).unwrap();
+
+    let control = Arc::new(UploadControl::new(2));
+    let (tx_upload, mut rx_upload) = mpsc::channel(64);
+    let uploader = tokio::task::spawn(file_uploader(
+        tx_upload,
+        control.clone(),
+        source,
+        1,
+        0,
+        Sha256::digest(b"").into(),
+    ));
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    // Queue has space, but peer didn't acknowledge any chunk yet.
+    assert_eq!(rx_upload.len(), 2);
+    assert!(!uploader.is_finished());
+
+    // Peer can't give more credits than window.
+    control.add_credits(100);
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    assert_eq!(rx_upload.len(), 4);
+
+    control.cancel();
+    uploader.await.unwrap();
+    while rx_upload.recv().await.is_some() {}
+}
+
+#[tokio::test]
+async fn paused_upload_waits_for_resume() {
+    let tmp_dir = tempdir().unwrap();
+    let source = tmp_dir.path().join("big_file");
+    std::fs::write(&source, vec![7; 1 << 22]).unwrap();
+
+    let control = Arc::new(UploadControl::new(2));
+    control.pause();
+
+    let (tx_upload, rx_upload) = mpsc::channel(64);
+    let uploader = tokio::task::spawn(file_uploader(
+        tx_upload,
+        control.clone(),
+        source,
+        1,
+        0,
+        Sha256::digest(b"").into(),
+    ));
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    assert_eq!(rx_upload.len(), 0);
+
+    control.resume();
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    assert_eq!(rx_upload.len(), 2);
+
+    // Cancel wakes up paused upload too.
+    control.pause();
+    control.cancel();
+    uploader.await.unwrap();
+}
+
+#[tokio::test]
+async fn cancelled_download_removes_partial_file() {
+    let file_name = "rust-project-test-file-C4nc3lL3dDwNl0aD";
+    let download_path = DOWNLOAD_PATH.join(file_name);
+    let part_path = DOWNLOAD_PATH.join(format!("{}.part", file_name));
+
+    let (tx_packets, rx_packets) = mpsc::channel(4);
+    let (tx_requests, mut rx_requests) = mpsc::unbounded_channel();
+    let loading_bar = Arc::new(Mutex::new(LoadingBarWrap {
+        loadingbar: LoadingBar::Error(String::new()),
+        changed: true,
+    }));
+    let partial_files: PartialFilesMap = Arc::new(Mutex::new(HashMap::new()));
+
+    let downloader = tokio::task::spawn(file_downloader(
+        rx_packets,
+        tx_requests,
+        1,
+        file_name.to_string(),
+        10_000,
+        [0; 32],
+        loading_bar.clone(),
+        Arc::new(Mutex::new(HashMap::new())),
+        partial_files.clone(),
+        PeerLimits::default().download_throttle(),
+        1,
+        *USER_ID,
+        DOWNLOAD_PATH.clone(),
+    ));
+
+    assert!(matches!(
+        rx_requests.recv().await,
+        Some(Message::Internal(InternalMessage::FileRequest(1, 0, _)))
+    ));
+
+    let mut chunk = ChunkBuffer::new(5000);
+    chunk.fill(1, 0, 5000).fill(1);
+    tx_packets.send(FilePacket::Chunk(chunk)).await.unwrap();
+    tx_packets
+        .send(FilePacket::Internal(InternalMessage::FilePause(1)))
+        .await
+        .unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+    assert!(matches!(
+        loading_bar.lock().unwrap().loadingbar,
+        LoadingBar::Status(LoadingBarStatus {
+            position: 5000,
+            paused: true,
+            ..
+        })
+    ));
+
+    tx_packets
+        .send(FilePacket::Internal(InternalMessage::FileCancel(1)))
+        .await
+        .unwrap();
+    downloader.await.unwrap();
+
+    assert!(!part_path.exists());
+    assert!(!download_path.exists());
+    assert!(partial_files.lock().unwrap().is_empty());
+    assert!(is_loading_bar_free(&Some(loading_bar)));
+}
+
+#[tokio::test]
+async fn uploads_are_interrupted_with_connection() {
+    let tmp_dir = tempdir().unwrap();
+    let source = tmp_dir.path().join("big_file");
+    std::fs::write(&source, vec![7; 1 << 22]).unwrap();
+
+    let control = Arc::new(UploadControl::new(2));
+    let mut uploads = RunningUploads::default();
+    uploads.insert(1, control.clone());
+
+    let (tx_upload, _rx_upload) = mpsc::channel(64);
+    let uploader = tokio::task::spawn(file_uploader(
+        tx_upload,
+        control.clone(),
+        source,
+        1,
+        0,
+        Sha256::digest(b"").into(),
+    ));
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    assert!(matches!(
+        control.loading_bar.lock().unwrap().loadingbar,
+        LoadingBar::Status(LoadingBarStatus {
+            position: 32768,
+            end: 4194304,
+            ..
+        })
+    ));
+
+    drop(uploads);
+    uploader.await.unwrap();
+
+    assert!(matches!(
+        &control.loading_bar.lock().unwrap().loadingbar,
+        LoadingBar::Error(e) if e == "Upload interrupted!"
+    ));
+}
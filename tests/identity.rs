@@ -0,0 +1,40 @@
+use rust_project::modules::identity::Identity;
+use tempfile::tempdir;
+
+#[test]
+fn identity_survives_restart() {
+    let tmp_dir = tempdir().unwrap();
+
+    let created = Identity::load_or_create_in(Some(tmp_dir.path()));
+    let loaded = Identity::load_or_create_in(Some(tmp_dir.path()));
+
+    assert_eq!(created.user_id(), loaded.user_id());
+    assert_eq!(created.user_name, loaded.user_name);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let metadata = std::fs::metadata(tmp_dir.path().join("identity")).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+    }
+}
+
+#[test]
+fn corrupted_identity_is_replaced() {
+    let tmp_dir = tempdir().unwrap();
+    std::fs::write(tmp_dir.path().join("identity"), b"garbage").unwrap();
+
+    let created = Identity::load_or_create_in(Some(tmp_dir.path()));
+    let loaded = Identity::load_or_create_in(Some(tmp_dir.path()));
+
+    assert_eq!(created.user_id(), loaded.user_id());
+}
+
+#[test]
+fn identity_without_config_dir_is_temporary() {
+    let first = Identity::load_or_create_in(None);
+    let second = Identity::load_or_create_in(None);
+
+    assert_ne!(first.user_id(), second.user_id());
+}
@@ -0,0 +1,71 @@
+use rust_project::config::ConnectionLimits;
+use rust_project::modules::limits::*;
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::{Duration, Instant};
+
+const FLOODING_ADDR: IpAddr = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 7));
+const OTHER_ADDR: IpAddr = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 8));
+
+fn limits() -> ConnectionLimits {
+    ConnectionLimits {
+        attempts_per_minute: 6,
+        failure_backoff_secs: 2,
+        max_backoff_secs: 5,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn attempts_are_rate_limited_per_source() {
+    let mut limiter = AttemptLimiter::new(&limits());
+    let now = Instant::now();
+
+    for _ in 0..6 {
+        assert!(limiter.allow(FLOODING_ADDR, now));
+    }
+    assert!(!limiter.allow(FLOODING_ADDR, now));
+
+    // Other sources are not affected.
+    assert!(limiter.allow(OTHER_ADDR, now));
+
+    // One attempt comes back every 10 seconds.
+    assert!(limiter.allow(FLOODING_ADDR, now + Duration::from_secs(10)));
+    assert!(!limiter.allow(FLOODING_ADDR, now + Duration::from_secs(10)));
+}
+
+#[test]
+fn failing_source_is_backed_off() {
+    let mut limiter = AttemptLimiter::new(&limits());
+    let now = Instant::now();
+
+    limiter.record_failure(FLOODING_ADDR, now);
+    assert!(!limiter.allow(FLOODING_ADDR, now + Duration::from_secs(1)));
+    assert!(limiter.allow(FLOODING_ADDR, now + Duration::from_secs(2)));
+
+    // Second failure doubles back-off, third one hits the cap.
+    limiter.record_failure(FLOODING_ADDR, now);
+    assert!(!limiter.allow(FLOODING_ADDR, now + Duration::from_secs(3)));
+    limiter.record_failure(FLOODING_ADDR, now);
+    assert!(!limiter.allow(FLOODING_ADDR, now + Duration::from_secs(4)));
+    assert!(limiter.allow(FLOODING_ADDR, now + Duration::from_secs(5)));
+
+    limiter.record_failure(FLOODING_ADDR, now + Duration::from_secs(5));
+    limiter.record_success(FLOODING_ADDR);
+    assert!(limiter.allow(FLOODING_ADDR, now + Duration::from_secs(5)));
+}
+
+#[test]
+fn failing_sources_are_forgotten() {
+    let mut limiter = AttemptLimiter::new(&limits());
+    let now = Instant::now();
+
+    for i in 0..5000u32 {
+        limiter.record_failure(IpAddr::V4(Ipv4Addr::from(i)), now);
+    }
+    assert!(limiter.tracked_sources() <= 1024);
+
+    // Once their back-off is long over, failing sources make room at once.
+    let later = now + Duration::from_secs(60);
+    limiter.record_failure(FLOODING_ADDR, later);
+    assert_eq!(limiter.tracked_sources(), 1);
+}
@@ -0,0 +1,109 @@
+use ratatui::buffer::Buffer;
+use ratatui::layout::Rect;
+use rust_project::modules::widgets::list_component::*;
+use rust_project::modules::{message_bubble::*, protocol::*};
+use std::sync::{Arc, Mutex};
+
+// Renders bubble from the top, one bubble line per buffer row.
+fn render(bubble: &mut MsgBubble, width: u16) -> Buffer {
+    let rect = Rect::new(0, 0, width, 12);
+    let mut buf = Buffer::empty(rect);
+
+    bubble.render(rect, &mut buf, false, RenderingTop::Bottom);
+
+    buf
+}
+
+fn row(buf: &Buffer, y: u16) -> String {
+    (0..buf.area.width)
+        .map(|x| buf[(x, y)].symbol())
+        .collect::<String>()
+}
+
+fn file_bubble(name_len: usize, loading_bar: LoadingBar) -> MsgBubble<'static> {
+    let mut bubble = MsgBubble::new(
+        Some("peer".to_string()),
+        UserMessage::FileHeader("x".repeat(name_len), 100, 1, [0; 32]),
+        MsgBubbleAllignment::Left,
+    );
+    bubble.loading_bar = Some(Arc::new(Mutex::new(LoadingBarWrap {
+        loadingbar: loading_bar,
+        changed: true,
+    })));
+
+    bubble
+}
+
+#[test]
+fn running_transfer_with_long_name_fits_narrow_window() {
+    let mut status = LoadingBarStatus::new(50, 100);
+    status.paused = true;
+
+    let mut bubble = file_bubble(200, LoadingBar::Status(status));
+
+    // Progress below bar is cut too, so it doesn't stick out of bubble.
+    for width in [20, 11] {
+        let buf = render(&mut bubble, width);
+        assert!((4..6).all(|y| row(&buf, y).ends_with('│')));
+        assert!(row(&buf, 6).ends_with('┘'));
+    }
+
+    // Name is cut to the bubble, which ends at window border.
+    let buf = render(&mut bubble, 60);
+    assert!(row(&buf, 2).ends_with("xx… │"));
+    assert!(row(&buf, 4).starts_with("│  50% paused ═"));
+    assert!(row(&buf, 4).ends_with("─ │"));
+}
+
+#[test]
+fn verified_download_with_long_name_fits_narrow_window() {
+    let mut bubble = file_bubble(200, LoadingBar::Verified([0xab; 32]));
+
+    for width in [20, 11] {
+        let buf = render(&mut bubble, width);
+        assert!(row(&buf, 5).ends_with('┘'));
+    }
+
+    let buf = render(&mut bubble, 60);
+    assert!(row(&buf, 2).ends_with("xx… │"));
+    assert!(row(&buf, 4).starts_with("│ OK: Verified, sha256 abababababababab…"));
+}
+
+#[test]
+fn own_delivered_file_with_long_name_fits_narrow_window() {
+    let mut bubble = MsgBubble::new(
+        None,
+        UserMessage::FileHeader("x".repeat(200), 100, 1, [0; 32]),
+        MsgBubbleAllignment::Right,
+    );
+    bubble.download_count = 3;
+    bubble.loading_bar = Some(Arc::new(Mutex::new(LoadingBarWrap {
+        loadingbar: LoadingBar::Delivered,
+        changed: true,
+    })));
+
+    for width in [20, 11] {
+        let buf = render(&mut bubble, width);
+        assert!(row(&buf, 6).ends_with('┘'));
+    }
+
+    let buf = render(&mut bubble, 60);
+    assert!(row(&buf, 2).ends_with("xx… │"));
+    assert!(row(&buf, 4).starts_with("│ 3 downloads by peer"));
+    assert!(row(&buf, 5).starts_with("│ OK: Delivered to peer"));
+}
+
+#[test]
+fn unsent_text_shows_error_below() {
+    let mut bubble = MsgBubble::new(
+        None,
+        UserMessage::Text("hi".to_string()),
+        MsgBubbleAllignment::Left,
+    );
+    bubble.error = Some("Not sent".to_string());
+
+    let buf = render(&mut bubble, 30);
+    assert!(row(&buf, 1).starts_with("│ hi            │"));
+    assert!(row(&buf, 2).starts_with("│ ERR: Not sent │"));
+    assert!(row(&buf, 3).starts_with("└"));
+}
@@ -0,0 +1,53 @@
+use rust_project::modules::{encryption::ChunkBuffer, multiplexer::*, protocol::*};
+use tokio::sync::mpsc;
+
+fn chunk(file_id: FileID, byte_idx: FileSize) -> FilePacket {
+    let mut chunk = ChunkBuffer::new(16);
+    chunk.fill(file_id, byte_idx, 16);
+    FilePacket::Chunk(chunk)
+}
+
+fn file_id(packet: FilePacket) -> FileID {
+    match packet {
+        FilePacket::Chunk(chunk) => chunk.file_id(),
+        FilePacket::Internal(message) => panic!("Unexpected message {:?}", message),
+    }
+}
+
+#[tokio::test]
+async fn streams_are_served_in_turns() {
+    let mut bulk_streams = BulkStreams::new();
+
+    let (tx_1, rx_1) = mpsc::channel(8);
+    let (tx_2, rx_2) = mpsc::channel(8);
+    bulk_streams.push(rx_1);
+    bulk_streams.push(rx_2);
+
+    for i in 0..4 {
+        tx_1.send(chunk(1, i)).await.unwrap();
+        tx_2.send(chunk(2, i)).await.unwrap();
+    }
+
+    let mut order = Vec::new();
+    for _ in 0..8 {
+        order.push(file_id(bulk_streams.next().await));
+    }
+
+    assert_eq!(order, [1, 2, 1, 2, 1, 2, 1, 2]);
+}
+
+#[tokio::test]
+async fn finished_streams_are_removed() {
+    let mut bulk_streams = BulkStreams::new();
+
+    let (tx_1, rx_1) = mpsc::channel(8);
+    let (tx_2, rx_2) = mpsc::channel(8);
+    bulk_streams.push(rx_1);
+    bulk_streams.push(rx_2);
+
+    tx_2.send(chunk(2, 0)).await.unwrap();
+    drop(tx_1);
+
+    assert_eq!(file_id(bulk_streams.next().await), 2);
+    assert_eq!(bulk_streams.len(), 1);
+}
@@ -0,0 +1,111 @@
+use rust_project::config::settings;
+use rust_project::modules::offers::*;
+use rust_project::modules::protocol::unix_time;
+use std::path::PathBuf;
+use tempfile::tempdir;
+
+#[test]
+fn offers_survive_restart() {
+    let now = unix_time();
+    let tmp_dir = tempdir().unwrap();
+    let path = tmp_dir.path().join("offers");
+
+    let mut offers = OfferStore::open(Some(path.clone()));
+    offers.add(1, PathBuf::from("/tmp/file"), &[7], now);
+    offers.add_many([(2, PathBuf::from("/tmp/dir/a"))], &[7, 8], now);
+    drop(offers);
+
+    let offers = OfferStore::open(Some(path));
+    assert_eq!(
+        offers.lookup(7, 1, now),
+        Ok(PathBuf::from("/tmp/file").as_path())
+    );
+    assert_eq!(
+        offers.lookup(8, 2, now),
+        Ok(PathBuf::from("/tmp/dir/a").as_path())
+    );
+}
+
+#[test]
+fn offer_is_only_for_its_peers() {
+    let now = unix_time();
+    let mut offers = OfferStore::open(None);
+    offers.add(1, PathBuf::from("/tmp/file"), &[7], now);
+
+    assert!(offers.lookup(8, 1, now).is_err());
+    assert!(offers.lookup(7, 2, now).is_err());
+}
+
+#[test]
+fn revoked_offer_is_refused_and_forgotten() {
+    let now = unix_time();
+    let tmp_dir = tempdir().unwrap();
+    let path = tmp_dir.path().join("offers");
+
+    let mut offers = OfferStore::open(Some(path.clone()));
+    offers.add(1, PathBuf::from("/tmp/file"), &[7], now);
+    offers.add(2, PathBuf::from("/tmp/other"), &[7], now);
+    offers.revoke(7, &[1]);
+
+    assert_eq!(
+        offers.lookup(7, 1, now),
+        Err("File offer was revoked!".to_string())
+    );
+    assert_eq!(offers.end_reason(7, 1, now), Some("Offer revoked"));
+    assert_eq!(offers.end_reason(7, 2, now), None);
+    drop(offers);
+
+    let offers = OfferStore::open(Some(path));
+    assert!(offers.get(1).is_none());
+    assert!(offers.get(2).is_some());
+}
+
+#[test]
+fn broadcasted_offer_is_revoked_per_peer() {
+    let now = unix_time();
+    let tmp_dir = tempdir().unwrap();
+    let path = tmp_dir.path().join("offers");
+
+    let mut offers = OfferStore::open(Some(path.clone()));
+    offers.add(1, PathBuf::from("/tmp/file"), &[7, 8], now);
+    offers.revoke(7, &[1]);
+
+    assert_eq!(
+        offers.lookup(7, 1, now),
+        Err("File offer was revoked!".to_string())
+    );
+    assert_eq!(
+        offers.lookup(8, 1, now),
+        Ok(PathBuf::from("/tmp/file").as_path())
+    );
+    assert_eq!(offers.end_reason(8, 1, now), None);
+    drop(offers);
+
+    // Offer is forgotten only after it is revoked for every peer.
+    let mut offers = OfferStore::open(Some(path.clone()));
+    assert!(offers.lookup(7, 1, now).is_err());
+    assert!(offers.lookup(8, 1, now).is_ok());
+    offers.revoke(8, &[1]);
+    drop(offers);
+
+    let offers = OfferStore::open(Some(path));
+    assert!(offers.get(1).is_none());
+}
+
+#[test]
+fn offers_expire() {
+    let Some(expiry) = settings().transfer.offer_expiry() else {
+        return; // Offers never expire with these settings.
+    };
+    let now = unix_time();
+
+    let mut offers = OfferStore::open(None);
+    offers.add(1, PathBuf::from("/tmp/file"), &[7], now);
+
+    assert!(offers.lookup(7, 1, now + expiry - 1).is_ok());
+    assert_eq!(
+        offers.lookup(7, 1, now + expiry),
+        Err("File offer expired!".to_string())
+    );
+    assert_eq!(offers.end_reason(7, 1, now + expiry), Some("Offer expired"));
+}
@@ -0,0 +1,149 @@
+use rust_project::config::{DOWNLOAD_PATH, USER_ID};
+use rust_project::modules::{
+    file_transfer::*, message_bubble::*, parallel_transfer::*, protocol::*, throttle::*,
+};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tempfile::tempdir;
+use tokio::sync::mpsc;
+
+#[test]
+fn ranges_cover_whole_file() {
+    assert_eq!(split_ranges(10, 3), vec![(0, 3), (3, 6), (6, 10)]);
+    assert_eq!(split_ranges(2, 4), vec![(0, 1), (1, 2)]);
+    assert_eq!(split_ranges(0, 4), vec![(0, 0)]);
+
+    let ranges = split_ranges(1_000_003, 7);
+    assert_eq!(ranges.len(), 7);
+    assert_eq!(ranges.first().unwrap().0, 0);
+    assert_eq!(ranges.last().unwrap().1, 1_000_003);
+    assert!(ranges.windows(2).all(|pair| pair[0].1 == pair[1].0));
+}
+
+#[test]
+fn only_prefix_without_holes_is_resumable() {
+    let progress = RangeProgress::new(vec![(0, 10), (10, 20), (20, 30)]);
+    assert_eq!(progress.contiguous_prefix(), 0);
+
+    progress.add(0, 10);
+    progress.add(1, 4);
+    progress.add(2, 10);
+    assert_eq!(progress.total(), 24);
+    assert_eq!(progress.contiguous_prefix(), 14);
+
+    progress.add(1, 6);
+    assert_eq!(progress.contiguous_prefix(), 30);
+}
+
+#[tokio::test]
+async fn file_is_downloaded_over_parallel_connections() {
+    let file_name = "rust-project-test-file-PaRaLL3lR4nGe5q";
+    let download_path = DOWNLOAD_PATH.join(file_name);
+
+    let tmp_dir = tempdir().unwrap();
+    let source = tmp_dir.path().join(file_name);
+    let content: Vec<u8> = (0..1_000_000u32).map(|i| (i % 251) as u8).collect();
+    std::fs::write(&source, &content).unwrap();
+
+    let (_tx_packets, rx_packets) = mpsc::channel(4);
+    let (tx_requests, mut rx_requests) = mpsc::unbounded_channel();
+    let loading_bar = Arc::new(Mutex::new(LoadingBarWrap {
+        loadingbar: LoadingBar::Error(String::new()),
+        changed: true,
+    }));
+
+    let downloader = tokio::task::spawn(file_downloader(
+        rx_packets,
+        tx_requests,
+        1,
+        file_name.to_string(),
+        content.len() as FileSize,
+        Sha256::digest(&content).into(),
+        loading_bar.clone(),
+        Arc::new(Mutex::new(HashMap::new())),
+        Arc::new(Mutex::new(HashMap::new())),
+        PeerLimits::default().download_throttle(),
+        4,
+        *USER_ID,
+        DOWNLOAD_PATH.clone(),
+    ));
+
+    let Some(Message::Internal(InternalMessage::FileRangeRequest(id, port, token, ranges))) =
+        rx_requests.recv().await
+    else {
+        panic!("Expected file range request!");
+    };
+    assert_eq!(ranges.len(), 4);
+
+    // Both sides run with the same identity in tests.
+    let control = Arc::new(UploadControl::new(4));
+    let (tx_upload, _rx_upload) = mpsc::channel(4);
+    range_uploader(
+        tx_upload,
+        control.clone(),
+        source,
+        id,
+        *USER_ID,
+        SocketAddr::from(([127, 0, 0, 1], port)),
+        token,
+        ranges,
+    )
+    .await;
+
+    downloader.await.unwrap();
+
+    let downloaded = std::fs::read(&download_path);
+    let _ = std::fs::remove_file(&download_path);
+
+    assert_eq!(downloaded.unwrap(), content);
+    assert!(matches!(
+        control.loading_bar.lock().unwrap().loadingbar,
+        LoadingBar::Delivered
+    ));
+    assert!(matches!(
+        loading_bar.lock().unwrap().loadingbar,
+        LoadingBar::Verified(_)
+    ));
+}
+
+#[tokio::test]
+async fn too_many_or_overlapping_ranges_are_rejected() {
+    let tmp_dir = tempdir().unwrap();
+    let source = tmp_dir.path().join("source");
+    std::fs::write(&source, vec![0; 1000]).unwrap();
+
+    let too_many = (0..1000).map(|i| (i, i + 1)).collect();
+    let overlapping = vec![(0, 600), (400, 1000)];
+
+    for ranges in [too_many, overlapping] {
+        let control = Arc::new(UploadControl::new(4));
+        let (tx_upload, mut rx_upload) = mpsc::channel(4);
+
+        // Nothing listens on this port, so any data connection would fail diffrently.
+        range_uploader(
+            tx_upload,
+            control.clone(),
+            source.clone(),
+            1,
+            *USER_ID,
+            SocketAddr::from(([127, 0, 0, 1], 1)),
+            0,
+            ranges,
+        )
+        .await;
+
+        assert!(matches!(
+            &control.loading_bar.lock().unwrap().loadingbar,
+            LoadingBar::Error(e) if e == "Requested ranges are invalid!"
+        ));
+        assert!(matches!(
+            rx_upload.recv().await,
+            Some(FilePacket::Internal(InternalMessage::FileContentError(
+                1,
+                _
+            )))
+        ));
+    }
+}
@@ -1,18 +1,77 @@
-use rust_project::modules::protocol::*;
+use rust_project::config::*;
+use rust_project::modules::{identity::Identity, protocol::*};
 use std::io::Cursor;
 
+// Generated identity, so tests don't touch the one stored in config directory.
+fn example_discovery(identity: &Identity) -> UserDiscovery {
+    UserDiscovery {
+        port: 121,
+        user_id: identity.user_id(),
+        identity_key: identity.signing_key.verifying_key().to_bytes(),
+        timestamp: unix_time(),
+        nonce: 98989,
+        private_room: false,
+    }
+}
+
 #[test]
 fn serialization_user_discovery() {
+    let identity = Identity::generate();
+    let original = example_discovery(&identity);
+
+    let data = original.to_packet(&identity.signing_key).unwrap();
+
+    let deserialized = UserDiscovery::from_packet(data, &mut DiscoveryReplayGuard::new()).unwrap();
+
+    assert_eq!(original, deserialized);
+}
+
+#[test]
+fn serialization_user_discovery_rejects_foreign_id() {
+    let identity = Identity::generate();
     let original = UserDiscovery {
-        port: 121,
-        user_id: 98989,
+        user_id: identity.user_id() ^ 1,
+        ..example_discovery(&identity)
     };
 
-    let data = original.to_packet().unwrap();
+    let data = original.to_packet(&identity.signing_key).unwrap();
 
-    let deserialized = UserDiscovery::from_packet(data).unwrap();
+    assert!(UserDiscovery::from_packet(data, &mut DiscoveryReplayGuard::new()).is_err());
+}
 
-    assert_eq!(original, deserialized);
+#[test]
+fn serialization_user_discovery_rejects_tampering() {
+    let identity = Identity::generate();
+    let mut data = example_discovery(&identity)
+        .to_packet(&identity.signing_key)
+        .unwrap();
+
+    // Change port without updating signature.
+    let port_idx = UNIQUE_BYTES.len() + 8;
+    data[port_idx] ^= 1;
+
+    assert!(UserDiscovery::from_packet(data, &mut DiscoveryReplayGuard::new()).is_err());
+}
+
+#[test]
+fn serialization_user_discovery_rejects_replay() {
+    let mut replay_guard = DiscoveryReplayGuard::new();
+    let identity = Identity::generate();
+
+    let data = example_discovery(&identity)
+        .to_packet(&identity.signing_key)
+        .unwrap();
+
+    assert!(UserDiscovery::from_packet(data.clone(), &mut replay_guard).is_ok());
+    assert!(UserDiscovery::from_packet(data, &mut replay_guard).is_err());
+
+    let stale = UserDiscovery {
+        timestamp: unix_time() - 10 * DISCOVERY_MAX_AGE,
+        ..example_discovery(&identity)
+    };
+    let data = stale.to_packet(&identity.signing_key).unwrap();
+
+    assert!(UserDiscovery::from_packet(data, &mut replay_guard).is_err());
 }
 
 #[tokio::test]
@@ -36,10 +95,11 @@ async fn serialization_message_async_1() {
 
 #[tokio::test]
 async fn serialization_message_async_2() {
-    let original = Message::Internal(InternalMessage::FileContent(
+    let original = Message::Internal(InternalMessage::FileRangeRequest(
         24857234,
         0b10101,
-        vec![42; 0b10101],
+        42,
+        vec![(0, 42); 0b10101],
     ));
 
     let mut buf: Vec<u8> = Vec::new();
@@ -56,3 +116,49 @@ async fn serialization_message_async_2() {
     // Check if serialization-deserialization is identity
     assert_eq!(cursor.position(), buf.len() as u64);
 }
+
+#[tokio::test]
+async fn serialization_rejects_oversized_frame() {
+    // Frame announcing more bytes than any limit, nothing should be allocated for it.
+    let mut cursor = Cursor::new(u64::MAX.to_be_bytes().to_vec());
+
+    match Message::read(&mut cursor).await {
+        Err(StreamSerializerError::InvalidFrame(FrameError::TooLarge { size, .. })) => {
+            assert_eq!(size, u64::MAX);
+        }
+        result => panic!("Unexpected result: {:?}", result),
+    }
+}
+
+#[tokio::test]
+async fn serialization_rejects_malformed_frame() {
+    // Text variant announcing string longer than the frame.
+    let mut frame = 12u64.to_be_bytes().to_vec();
+    frame.extend_from_slice(&0u32.to_le_bytes()); // Message::User
+    frame.extend_from_slice(&0u32.to_le_bytes()); // UserMessage::Text
+    frame.extend_from_slice(&[0xff, 0xff, 0xff, 0x7f]); // Part of string length
+
+    let mut cursor = Cursor::new(frame);
+
+    assert!(matches!(
+        Message::read(&mut cursor).await,
+        Err(StreamSerializerError::InvalidFrame(FrameError::Malformed(
+            _
+        )))
+    ));
+}
+
+#[test]
+fn user_discovery_only_tells_whether_room_is_used() {
+    let identity = Identity::generate();
+    let in_room = UserDiscovery {
+        private_room: true,
+        ..example_discovery(&identity)
+    };
+
+    // Passphrase itself is checked only during handshake.
+    assert!(in_room.may_be_in_room(true));
+    assert!(!in_room.may_be_in_room(false));
+    assert!(example_discovery(&identity).may_be_in_room(false));
+    assert!(!example_discovery(&identity).may_be_in_room(true));
+}
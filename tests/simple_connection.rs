@@ -1,5 +1,11 @@
 use rust_project::config::*;
-use rust_project::modules::{networking::*, peer_state::PeerState, protocol::*};
+use rust_project::modules::{
+    message_bubble::{is_loading_bar_free, LoadingBar},
+    networking::*,
+    peer_state::{offer_path, PeerState},
+    protocol::*,
+    transfers::{TransferStatus, TRANSFERS},
+};
 use std::net::SocketAddr;
 use tokio::io::AsyncWriteExt;
 
@@ -12,30 +18,24 @@ use ntest::timeout;
 use tempfile::tempdir;
 // User detection is not possible to be easily tested, since connections from user with same id are ignored.
 
-async fn connect_to_port(addr: SocketAddr) -> TcpStream {
-    TcpStream::connect(addr).await.unwrap()
+async fn connect_to_port(addr: SocketAddr) -> ConnectionData {
+    let stream = TcpStream::connect(addr).await.unwrap();
+    establish_connection(stream, addr, true).await.unwrap()
 }
 
 async fn get_2_connections() -> (ConnectionData, ConnectionData) {
     let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
     let addr = listener.local_addr().unwrap();
 
-    let handle = tokio::task::spawn(connect_to_port(addr.clone()));
+    let handle = tokio::task::spawn(connect_to_port(addr));
 
     let (stream, peer_address) = listener.accept().await.unwrap();
     drop(listener);
 
-    let cd1 = ConnectionData {
-        stream,
-        peer_address,
-        peer_name: "USER_A".to_string(),
-    };
-
-    let cd2 = ConnectionData {
-        stream: handle.await.unwrap(),
-        peer_address: addr,
-        peer_name: "USER_B".to_string(),
-    };
+    let cd1 = establish_connection(stream, peer_address, false)
+        .await
+        .unwrap();
+    let cd2 = handle.await.unwrap();
 
     (cd1, cd2)
 }
@@ -103,6 +103,88 @@ async fn exchange_text_both_ways() {
     }
 }
 
+#[tokio::test]
+#[timeout(500)]
+async fn pending_peer_is_ignored_until_accepted() {
+    let (cd1, cd2) = get_2_connections().await;
+    let (peer1, mut peer2) = (PeerState::from(cd1), PeerState::new(cd2, false));
+
+    assert!(peer2.is_pending());
+
+    peer1.send(Message::User(UserMessage::Text("ignored".to_string())));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    peer2.update();
+
+    assert!(peer2.messages.list.is_empty());
+
+    peer2.accept();
+
+    let example_user_msg = UserMessage::Text("IQVIBOABCHO".to_string());
+    peer1.send(Message::User(example_user_msg.clone()));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    peer2.update();
+
+    match &peer2.messages.list[..] {
+        [msg] => {
+            assert_eq!(msg.message, example_user_msg);
+        }
+        list => panic!("Unexpected msg list length! {:#?}", list),
+    }
+}
+
+#[tokio::test]
+#[timeout(500)]
+async fn oversized_message_is_not_sent() {
+    let (mut peer1, mut peer2) = get_2_peers().await;
+
+    let huge_msg = UserMessage::Text("A".repeat(settings().frame_limits.message as usize + 1));
+    let example_user_msg = UserMessage::Text("IQVIBOABCHO".to_string());
+
+    peer1.send(Message::User(huge_msg));
+    peer1.send(Message::User(example_user_msg.clone()));
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    peer1.update();
+    peer2.update();
+
+    // Sender sees why msg didn't arrive.
+    assert!(peer1.messages.list[0]
+        .error
+        .as_ref()
+        .is_some_and(|e| e.starts_with("Not sent, too big")));
+    assert!(peer1.messages.list[1].error.is_none());
+
+    match &peer2.messages.list[..] {
+        [msg] => {
+            assert_eq!(msg.message, example_user_msg);
+        }
+        list => panic!("Unexpected msg list length! {:#?}", list),
+    }
+
+    assert!(peer1.is_active() && peer2.is_active());
+}
+
+#[tokio::test]
+#[timeout(500)]
+async fn download_connections_stay_after_reconnect() {
+    let (_peer1, mut peer2) = get_2_peers().await;
+
+    assert_eq!(
+        peer2.download_connections(),
+        settings().transfer.connections
+    );
+
+    peer2.cycle_download_connections();
+    let chosen = peer2.download_connections();
+    assert_ne!(chosen, settings().transfer.connections);
+
+    let (_peer1, mut peer2_again) = get_2_peers().await;
+    peer2_again.continue_from(&mut peer2);
+
+    assert_eq!(peer2_again.download_connections(), chosen);
+}
+
 #[tokio::test]
 async fn file_transfer_successful() {
     let (mut peer1, mut peer2) = get_2_peers().await;
@@ -135,6 +217,7 @@ async fn file_transfer_successful() {
     let file_content = "THIS IS TEST FILE!!".as_bytes();
 
     file.write_all(file_content).await.unwrap();
+    file.flush().await.unwrap();
     drop(file);
 
     peer1.upload_file(file_path);
@@ -148,6 +231,26 @@ async fn file_transfer_successful() {
 
     tokio::time::sleep(Duration::from_millis(100)).await;
 
+    let loading_bar = peer2.messages.list[0].loading_bar.clone().unwrap();
+    assert!(matches!(
+        loading_bar.lock().unwrap().loadingbar,
+        LoadingBar::Verified(_)
+    ));
+
+    // Both sides see finished transfer on transfer list.
+    let transfers: Vec<_> = TRANSFERS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|transfer| transfer.name == random_file_name)
+        .cloned()
+        .collect();
+    assert_eq!(transfers.len(), 2);
+    assert!(transfers.iter().any(|transfer| transfer.is_upload()));
+    assert!(transfers
+        .iter()
+        .all(|transfer| transfer.status() == TransferStatus::Finished));
+
     let downloaded_content = fs::read(&download_path).await.unwrap();
 
     let result = downloaded_content == file_content;
@@ -161,3 +264,388 @@ async fn file_transfer_successful() {
 
     assert!(result);
 }
+
+#[tokio::test]
+async fn sender_sees_every_download() {
+    let (mut peer1, mut peer2) = get_2_peers().await;
+
+    let random_file_name = "rust-project-test-file-Up7oAdPr0gReSs".to_string();
+    let download_paths = [
+        DOWNLOAD_PATH.join(&random_file_name),
+        DOWNLOAD_PATH.join(format!("{} (1)", random_file_name)),
+    ];
+
+    let tmp_dir = tempdir().unwrap();
+    let file_path = tmp_dir.path().join(&random_file_name);
+    fs::write(&file_path, "THIS IS TEST FILE!!").await.unwrap();
+
+    peer1.upload_file(file_path);
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    peer1.update();
+    assert!(peer1.messages.list[0].loading_bar.is_none()); // Nobody downloads yet.
+
+    peer2.update();
+    peer2.messages.select(0);
+
+    // Download the same file twice.
+    for _ in 0..2 {
+        peer2.handle_action_on_msg();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    peer1.update();
+
+    let own_bubble = &peer1.messages.list[0];
+    let delivered = matches!(
+        own_bubble
+            .loading_bar
+            .as_ref()
+            .unwrap()
+            .lock()
+            .unwrap()
+            .loadingbar,
+        LoadingBar::Delivered
+    );
+    let download_count = own_bubble.download_count;
+
+    for path in download_paths.iter() {
+        let _ = fs::remove_file(path).await;
+    }
+
+    assert!(delivered);
+    assert_eq!(download_count, 2);
+}
+
+#[tokio::test]
+#[timeout(2000)]
+async fn offer_keeps_its_place_before_later_msgs() {
+    let (mut peer1, mut peer2) = get_2_peers().await;
+
+    let tmp_dir = tempdir().unwrap();
+    let file_path = tmp_dir.path().join("big_file");
+    fs::write(&file_path, vec![7; 16 << 20]).await.unwrap();
+
+    // Text is sent while file is still being hashed.
+    peer1.upload_file(file_path);
+    peer1.send(Message::User(UserMessage::Text("after file".to_string())));
+
+    tokio::time::sleep(Duration::from_millis(1000)).await;
+
+    peer2.update();
+    assert_eq!(peer2.messages.list.len(), 2);
+    assert!(matches!(
+        peer2.messages.list[0].message,
+        UserMessage::FileHeader(..)
+    ));
+    assert_eq!(
+        peer2.messages.list[1].message,
+        UserMessage::Text("after file".to_string())
+    );
+}
+
+#[tokio::test]
+#[timeout(500)]
+async fn unreadable_offer_is_shown_as_error() {
+    let (mut peer1, mut peer2) = get_2_peers().await;
+
+    let tmp_dir = tempdir().unwrap();
+    let file_path = tmp_dir.path().join("vanishing_file");
+    fs::write(&file_path, "THIS IS TEST FILE!!").await.unwrap();
+
+    // File disappears before it is hashed.
+    peer1.upload_file(file_path.clone());
+    std::fs::remove_file(&file_path).unwrap();
+    peer1.send(Message::User(UserMessage::Text("after file".to_string())));
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    peer1.update();
+    peer2.update();
+
+    assert_eq!(peer1.messages.list.len(), 2);
+    assert!(peer1.messages.list[0]
+        .error
+        .as_ref()
+        .is_some_and(|e| e.starts_with("Not sent")));
+    assert!(peer1.messages.list[1].error.is_none());
+
+    match &peer2.messages.list[..] {
+        [msg] => assert_eq!(msg.message, UserMessage::Text("after file".to_string())),
+        list => panic!("Unexpected msg list length! {:#?}", list),
+    }
+}
+
+#[tokio::test]
+async fn revoked_offer_is_refused() {
+    let (mut peer1, mut peer2) = get_2_peers().await;
+
+    let random_file_name = "rust-project-test-file-R3v0kEdOfF3r".to_string();
+    let download_path = DOWNLOAD_PATH.join(&random_file_name);
+
+    let tmp_dir = tempdir().unwrap();
+    let file_path = tmp_dir.path().join(&random_file_name);
+    fs::write(&file_path, "THIS IS TEST FILE!!").await.unwrap();
+
+    peer1.upload_file(file_path);
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    peer1.update();
+    peer1.messages.select(0);
+    peer1.revoke_selected_offer();
+
+    peer2.update();
+    peer2.messages.select(0);
+    peer2.handle_action_on_msg();
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    peer1.update();
+
+    let error_of = |peer: &PeerState| match &peer.messages.list[0]
+        .loading_bar
+        .as_ref()
+        .unwrap()
+        .lock()
+        .unwrap()
+        .loadingbar
+    {
+        LoadingBar::Error(e) => e.clone(),
+        _ => String::new(),
+    };
+
+    // Refused download keeps empty partial file, as any interrupted one.
+    let _ = std::fs::remove_file(DOWNLOAD_PATH.join(format!("{}.part", random_file_name)));
+
+    assert!(!download_path.exists());
+    assert_eq!(error_of(&peer1), "Offer revoked");
+    assert!(error_of(&peer2).starts_with("File offer was revoked!"));
+}
+
+#[tokio::test]
+async fn broadcast_reaches_every_recipient() {
+    let (sender1, mut receiver1) = get_2_peers().await;
+    let (sender2, mut receiver2) = get_2_peers().await;
+
+    let random_file_name = "rust-project-test-file-Br0aDcAsTf1Le9";
+    let download_paths = [
+        DOWNLOAD_PATH.join(random_file_name),
+        DOWNLOAD_PATH.join(format!("{} (1)", random_file_name)),
+    ];
+
+    let tmp_dir = tempdir().unwrap();
+    let file_path = tmp_dir.path().join(random_file_name);
+    fs::write(&file_path, "THIS IS TEST FILE!!").await.unwrap();
+
+    let recipients = vec![sender1.recipient(), sender2.recipient()];
+    for recipient in recipients.iter() {
+        recipient.send(Message::User(UserMessage::Text("Hello all".to_string())));
+    }
+    assert!(offer_path(file_path, recipients));
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut file_ids = Vec::new();
+
+    // Both recipients download the same offer on their own.
+    for receiver in [&mut receiver1, &mut receiver2] {
+        receiver.update();
+        assert_eq!(receiver.messages.list.len(), 2);
+        assert_eq!(
+            receiver.messages.list[0].message,
+            UserMessage::Text("Hello all".to_string())
+        );
+
+        let UserMessage::FileHeader(_, _, file_id, _) = receiver.messages.list[1].message else {
+            panic!("Expected file msg!");
+        };
+        file_ids.push(file_id);
+
+        receiver.messages.select(1);
+        receiver.handle_action_on_msg();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    let downloaded: Vec<_> = download_paths.iter().map(std::fs::read).collect();
+    for download_path in download_paths.iter() {
+        let _ = std::fs::remove_file(download_path);
+    }
+
+    assert_eq!(file_ids[0], file_ids[1]);
+    for receiver in [&receiver1, &receiver2] {
+        assert!(matches!(
+            receiver.messages.list[1]
+                .loading_bar
+                .as_ref()
+                .unwrap()
+                .lock()
+                .unwrap()
+                .loadingbar,
+            LoadingBar::Verified(_)
+        ));
+    }
+    for content in downloaded {
+        assert_eq!(content.unwrap(), b"THIS IS TEST FILE!!");
+    }
+}
+
+#[tokio::test]
+async fn file_with_hash_mismatch_is_deleted() {
+    let (mut peer1, mut peer2) = get_2_peers().await;
+
+    let random_file_name = "rust-project-test-file-Qm4Xz0LwP1aT7c".to_string();
+
+    let download_path = DOWNLOAD_PATH.join(&random_file_name);
+
+    let tmp_dir = tempdir().unwrap();
+    let file_path = tmp_dir.path().join(&random_file_name);
+
+    fs::write(&file_path, "THIS IS TEST FILE!!").await.unwrap();
+
+    peer1.upload_file(file_path.clone());
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // File changes after header with its hash was sent.
+    fs::write(&file_path, "THIS IS EVIL FILE!!").await.unwrap();
+
+    peer2.update();
+
+    peer2.messages.select(0);
+    peer2.handle_action_on_msg();
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    assert!(!download_path.exists());
+    assert!(!DOWNLOAD_PATH
+        .join(format!("{}.part", random_file_name))
+        .exists());
+
+    let loading_bar = peer2.messages.list[0].loading_bar.clone().unwrap();
+    match &loading_bar.lock().unwrap().loadingbar {
+        LoadingBar::Error(e) => assert!(e.contains("Hash mismatch")),
+        other => panic!("Unexpected loading bar state! {:?}", other),
+    };
+}
+
+#[tokio::test]
+async fn directory_transfer_successful() {
+    let (mut peer1, mut peer2) = get_2_peers().await;
+
+    let random_dir_name = "rust-project-test-dir-Hc2Vn8QeY5sWd1";
+    let download_path = DOWNLOAD_PATH.join(random_dir_name);
+
+    let tmp_dir = tempdir().unwrap();
+    let dir_path = tmp_dir.path().join(random_dir_name);
+
+    std::fs::create_dir_all(dir_path.join("src/modules")).unwrap();
+    std::fs::create_dir_all(dir_path.join("empty")).unwrap();
+    std::fs::write(dir_path.join("README.md"), "readme").unwrap();
+    std::fs::write(dir_path.join("src/modules/lib.rs"), "fn main() {}").unwrap();
+    #[cfg(unix)]
+    std::os::unix::fs::symlink("/etc/passwd", dir_path.join("passwd")).unwrap();
+
+    if download_path.exists() {
+        std::fs::remove_dir_all(&download_path).unwrap();
+    }
+
+    peer1.upload_file(dir_path);
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    peer2.update();
+
+    peer2.messages.select(0);
+    peer2.handle_action_on_msg();
+
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let readme = std::fs::read_to_string(download_path.join("README.md"));
+    let lib = std::fs::read_to_string(download_path.join("src/modules/lib.rs"));
+    let empty_exists = download_path.join("empty").is_dir();
+    let symlink_exists = download_path.join("passwd").exists();
+
+    let _ = std::fs::remove_dir_all(&download_path);
+
+    assert_eq!(readme.unwrap(), "readme");
+    assert_eq!(lib.unwrap(), "fn main() {}");
+    assert!(empty_exists);
+    assert!(!symlink_exists);
+
+    let loading_bar = peer2.messages.list[0].loading_bar.clone().unwrap();
+    assert!(matches!(
+        loading_bar.lock().unwrap().loadingbar,
+        LoadingBar::Verified(_)
+    ));
+}
+
+#[tokio::test]
+async fn chat_is_not_delayed_by_file_transfer() {
+    let (mut peer1, mut peer2) = get_2_peers().await;
+
+    // File content shares chat connection, instead of going over data connections.
+    peer2.set_download_connections(1);
+
+    let random_file_name = "rust-project-test-file-L4tEncYCh4tB1g";
+    let download_path = DOWNLOAD_PATH.join(random_file_name);
+
+    let tmp_dir = tempdir().unwrap();
+    let file_path = tmp_dir.path().join(random_file_name);
+    std::fs::write(&file_path, vec![42; 64 << 20]).unwrap();
+
+    peer1.upload_file(file_path);
+
+    // Wait for hash of big file.
+    while peer2.messages.list.is_empty() {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        peer2.update();
+    }
+
+    peer2.messages.select(0);
+    peer2.handle_action_on_msg();
+
+    // Let transfer fill all the queues.
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let sent_at = std::time::Instant::now();
+    peer1.send(Message::User(UserMessage::Text("ping".to_string())));
+
+    while peer2.messages.list.len() < 2 {
+        tokio::time::sleep(Duration::from_millis(1)).await;
+        peer2.update();
+    }
+
+    let latency = sent_at.elapsed();
+    let transfer_running = !is_loading_bar_free(&peer2.messages.list[0].loading_bar);
+
+    drop(peer1);
+    drop(peer2);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let _ = std::fs::remove_file(&download_path);
+    let _ = std::fs::remove_file(DOWNLOAD_PATH.join(format!("{}.part", random_file_name)));
+
+    assert!(transfer_running, "Transfer ended before chat msg arrived");
+    assert!(
+        latency < Duration::from_millis(250),
+        "Latency {:?}",
+        latency
+    );
+}
+
+#[test]
+fn both_sides_keep_the_same_duplicate_connection() {
+    let (lower, higher) = (1, 2);
+
+    // Each side already has the connection it opened, then gets the one opened by peer.
+    let lower_replaces = prefers_new_connection(lower, higher);
+    let higher_replaces = prefers_new_connection(higher, lower);
+
+    // Only the side with higher id replaces, so both keep connection opened by lower id.
+    assert!(!lower_replaces);
+    assert!(higher_replaces);
+
+    // Reconnect from the same side keeps old connection, as before.
+    assert!(!prefers_new_connection(lower, lower));
+}
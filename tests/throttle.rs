@@ -0,0 +1,77 @@
+use rust_project::modules::throttle::*;
+use std::time::{Duration, Instant};
+
+#[test]
+fn unlimited_never_waits() {
+    let limiter = RateLimiter::new(None);
+    let now = Instant::now();
+
+    assert_eq!(limiter.take(1 << 30, now), Duration::ZERO);
+    assert_eq!(limiter.take(1 << 30, now), Duration::ZERO);
+}
+
+#[test]
+fn bytes_over_limit_wait() {
+    let limiter = RateLimiter::new(Some(1000));
+    let now = Instant::now();
+
+    // One second of traffic is allowed at once.
+    assert_eq!(limiter.take(1000, now), Duration::ZERO);
+    assert_eq!(limiter.take(500, now), Duration::from_millis(500));
+
+    // Debt is paid back first.
+    assert_eq!(
+        limiter.take(500, now + Duration::from_millis(500)),
+        Duration::from_millis(500)
+    );
+    assert_eq!(
+        limiter.take(100, now + Duration::from_secs(5)),
+        Duration::ZERO
+    );
+}
+
+#[test]
+fn limit_changes_at_runtime() {
+    let limiter = RateLimiter::new(None);
+    let now = Instant::now();
+
+    limiter.set_limit(Some(1000));
+    assert_eq!(limiter.take(1000, now), Duration::ZERO);
+    assert_eq!(limiter.take(500, now), Duration::from_millis(500));
+
+    // Lower limit takes effect immediately.
+    limiter.set_limit(Some(500));
+    assert_eq!(limiter.take(500, now), Duration::from_secs(2));
+
+    limiter.set_limit(None);
+    assert_eq!(limiter.take(1 << 20, now), Duration::ZERO);
+}
+
+#[test]
+fn limits_cycle_through_presets() {
+    let limiter = RateLimiter::new(None);
+
+    for preset in LIMIT_PRESETS.iter().skip(1) {
+        limiter.cycle_limit();
+        assert_eq!(limiter.limit(), *preset);
+    }
+
+    limiter.cycle_limit();
+    assert_eq!(limiter.limit(), None);
+    assert_eq!(format_limit(Some(1_000_000)), "1 MB/s");
+    assert_eq!(format_limit(None), "unlimited");
+}
+
+#[tokio::test]
+async fn throttle_waits_for_peer_limit() {
+    let limits = PeerLimits::default();
+    limits.upload.set_limit(Some(100_000));
+    let throttle = limits.upload_throttle();
+
+    let start = Instant::now();
+    throttle.acquire(100_000).await;
+    assert!(start.elapsed() < Duration::from_millis(100));
+
+    throttle.acquire(50_000).await;
+    assert!(start.elapsed() >= Duration::from_millis(450));
+}
@@ -0,0 +1,107 @@
+use rust_project::modules::{message_bubble::*, transfers::*};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+fn download(position: u64, end: u64, start: Instant) -> Transfer {
+    Transfer::new(
+        TransferDirection::Download,
+        1,
+        "peer".to_string(),
+        "file".to_string(),
+        PathBuf::new(),
+        Arc::new(Mutex::new(LoadingBarWrap {
+            loadingbar: LoadingBar::Status(LoadingBarStatus::new_at(position, end, start)),
+            changed: true,
+        })),
+    )
+}
+
+fn set_position(transfer: &Transfer, position: u64, now: Instant) {
+    if let LoadingBar::Status(status) = &mut transfer.loading_bar.lock().unwrap().loadingbar {
+        status.advance(position, now);
+    }
+}
+
+#[test]
+fn status_follows_loading_bar() {
+    let transfer = download(0, 1000, Instant::now());
+    assert_eq!(transfer.status(), TransferStatus::Queued);
+
+    set_position(&transfer, 10, Instant::now());
+    assert_eq!(transfer.status(), TransferStatus::Active);
+
+    if let LoadingBar::Status(status) = &mut transfer.loading_bar.lock().unwrap().loadingbar {
+        status.paused = true;
+    }
+    assert_eq!(transfer.status(), TransferStatus::Paused);
+
+    transfer.loading_bar.lock().unwrap().loadingbar = LoadingBar::Error("Oops".to_string());
+    assert_eq!(
+        transfer.status(),
+        TransferStatus::Failed("Oops".to_string())
+    );
+
+    transfer.loading_bar.lock().unwrap().loadingbar = LoadingBar::Delivered;
+    assert_eq!(transfer.status(), TransferStatus::Finished);
+}
+
+#[test]
+fn resumed_part_is_not_counted_in_speed() {
+    let start = Instant::now();
+
+    // Half of file was downloaded before.
+    let transfer = download(500, 1000, start);
+    let now = start + Duration::from_secs(5);
+    set_position(&transfer, 750, now);
+
+    assert_eq!(transfer.speed(now), Some(50.0));
+    assert_eq!(transfer.eta(now), Some(Duration::from_secs(5)));
+}
+
+#[test]
+fn speed_is_smoothed() {
+    let start = Instant::now();
+    let mut status = LoadingBarStatus::new_at(0, 10000, start);
+
+    status.advance(1000, start + Duration::from_secs(1));
+    assert_eq!(status.speed(start + Duration::from_secs(1)), Some(1000.0));
+
+    // Single faster second only moves the estimate partly.
+    status.advance(3000, start + Duration::from_secs(2));
+    let speed = status.speed(start + Duration::from_secs(2)).unwrap();
+    assert!(speed > 1000.0 && speed < 2000.0);
+
+    // Chunks closer than sample interval don't change speed.
+    status.advance(3100, start + Duration::from_millis(2100));
+    assert_eq!(
+        status.speed(start + Duration::from_millis(2100)),
+        Some(speed)
+    );
+    assert_eq!(status.position, 3100);
+}
+
+#[test]
+fn stalled_transfer_has_no_speed() {
+    let start = Instant::now();
+    let mut status = LoadingBarStatus::new_at(0, 10000, start);
+    assert_eq!(status.speed(start), None);
+
+    status.advance(1000, start + Duration::from_secs(1));
+    assert!(status.eta(start + Duration::from_secs(1)).is_some());
+    assert_eq!(status.speed(start + Duration::from_secs(10)), None);
+    assert_eq!(status.eta(start + Duration::from_secs(10)), None);
+
+    status.paused = true;
+    assert_eq!(status.speed(start + Duration::from_secs(1)), None);
+}
+
+#[test]
+fn durations_are_short() {
+    assert_eq!(format_duration(Duration::from_secs(42)), "42s");
+    assert_eq!(format_duration(Duration::from_secs(125)), "2m 5s");
+    assert_eq!(
+        format_duration(Duration::from_secs(3 * 3600 + 600)),
+        "3h 10m"
+    );
+}
@@ -0,0 +1,69 @@
+use rust_project::modules::trust::*;
+use tempfile::tempdir;
+
+#[test]
+fn trust_on_first_use() {
+    let tmp_dir = tempdir().unwrap();
+    let path = tmp_dir.path().join("known_peers");
+
+    let mut known_peers = KnownPeers::open(Some(path.clone()));
+
+    assert_eq!(known_peers.check(1, "Alice", &[1; 32]), TrustStatus::New);
+    assert_eq!(known_peers.check(1, "Alice", &[1; 32]), TrustStatus::Known);
+
+    // Same name, diffrent key.
+    assert!(known_peers.check(2, "Alice", &[2; 32]).is_warning());
+
+    // Same id, diffrent key.
+    assert!(known_peers.check(1, "Alice", &[3; 32]).is_warning());
+
+    assert!(known_peers.mark_verified(1, "Alice", &[1; 32]));
+    assert!(!known_peers.mark_verified(1, "Alice", &[3; 32]));
+
+    // Verification survives restart.
+    let mut known_peers = KnownPeers::open(Some(path));
+    assert_eq!(
+        known_peers.check(1, "Alice", &[1; 32]),
+        TrustStatus::Verified
+    );
+}
+
+#[test]
+fn key_claiming_known_name_warns_until_verified() {
+    let tmp_dir = tempdir().unwrap();
+    let path = tmp_dir.path().join("known_peers");
+
+    let mut known_peers = KnownPeers::open(Some(path.clone()));
+    assert_eq!(known_peers.check(1, "Alice", &[1; 32]), TrustStatus::New);
+
+    // Impostor connects twice, also after restart.
+    assert!(known_peers.check(2, "Alice", &[2; 32]).is_warning());
+    assert!(known_peers.check(2, "Alice", &[2; 32]).is_warning());
+    let mut known_peers = KnownPeers::open(Some(path.clone()));
+    assert!(known_peers.check(2, "Alice", &[2; 32]).is_warning());
+
+    // Known peer renaming itself to taken name is also suspicious.
+    assert_eq!(known_peers.check(3, "Bob", &[3; 32]), TrustStatus::New);
+    assert!(known_peers.check(3, "Alice", &[3; 32]).is_warning());
+    assert_eq!(known_peers.check(3, "Bob", &[3; 32]), TrustStatus::Known);
+
+    // User compared authentication string, so both Alices are fine.
+    assert!(known_peers.mark_verified(2, "Alice", &[2; 32]));
+    assert_eq!(
+        known_peers.check(2, "Alice", &[2; 32]),
+        TrustStatus::Verified
+    );
+    let mut known_peers = KnownPeers::open(Some(path));
+    assert_eq!(
+        known_peers.check(2, "Alice", &[2; 32]),
+        TrustStatus::Verified
+    );
+}
+
+#[test]
+fn authentication_string_is_symmetric() {
+    let sas = authentication_string(&[1; 32], &[2; 32]);
+
+    assert_eq!(sas, authentication_string(&[2; 32], &[1; 32]));
+    assert_ne!(sas, authentication_string(&[1; 32], &[3; 32]));
+}